@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::secrets::SecretRef;
+use utility::human_format;
+
+/// The `alerting` section of the config
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AlertingConfig {
+    pub channels: Vec<AlertChannel>,
+    /// How often an alert that keeps firing is sent again, in seconds
+    #[serde(with = "human_format::duration_secs")]
+    pub repeat_interval: u64,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> AlertingConfig {
+        AlertingConfig {
+            channels: Vec::new(),
+            repeat_interval: 4 * 60 * 60,
+        }
+    }
+}
+
+impl AlertingConfig {
+    pub fn get_channel(&self, name: &str) -> Option<&AlertChannel> {
+        self.channels.iter().find(|channel| channel.name == name)
+    }
+}
+
+/// Somewhere alerts are delivered to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AlertChannel {
+    /// Referenced by `AlertRules::channels`
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ChannelKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelKind {
+    /// POSTs every alert as a JSON document
+    Webhook {
+        url: String,
+    },
+    /// POSTs a `text` message, as understood by Slack incoming webhooks and
+    /// compatible services
+    Slack {
+        url: String,
+    },
+    Email(EmailChannel),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmailChannel {
+    pub smtp_host: String,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Plaintext or a reference, see `SecretRef`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<SecretRef>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain SMTP, for relays on the local network
+    #[default]
+    None,
+    StartTls,
+    Tls,
+}
+
+/// When a database raises alerts, the `alerts` section of a `Database`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AlertRules {
+    /// Channels to notify, all of them when empty
+    pub channels: Vec<String>,
+    /// Failed runs in a row before a target alerts, 0 turns the rule off
+    pub failures: u32,
+    /// Alert when a target is past its RPO
+    pub freshness: bool,
+    /// Alert when a scrub finds segments that are corrupt, missing or differ
+    /// from the source
+    pub verification: bool,
+    /// Alert when the disk holding the data directory is fuller than this, in
+    /// percent; 0 turns the rule off
+    pub disk_usage: u8,
+}
+
+impl Default for AlertRules {
+    fn default() -> AlertRules {
+        AlertRules {
+            channels: Vec::new(),
+            failures: 3,
+            freshness: true,
+            verification: true,
+            disk_usage: 90,
+        }
+    }
+}
+
+impl AlertRules {
+    /// Whether alerts of these rules go to `channel`
+    pub fn routes_to(&self, channel: &AlertChannel) -> bool {
+        self.channels.is_empty() || self.channels.contains(&channel.name)
+    }
+}
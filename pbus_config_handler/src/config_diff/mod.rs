@@ -0,0 +1,106 @@
+use crate::{Config, Database};
+
+/// What changed between two versions of the config
+///
+/// Targets are identified by `(database_name, target_name)`. A database counts as
+/// modified when any of its own settings changed; a target counts as modified when
+/// its fields or `enabled` flag changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub added_databases: Vec<String>,
+    pub removed_databases: Vec<String>,
+    pub modified_databases: Vec<String>,
+    pub added_targets: Vec<(String, String)>,
+    pub removed_targets: Vec<(String, String)>,
+    pub modified_targets: Vec<(String, String)>,
+}
+
+impl ConfigDiff {
+    pub fn new(old: &Config, new: &Config) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+
+        for old_database in old.get_databases() {
+            let name = &old_database.database_name;
+            match find_database(new, name) {
+                None => {
+                    diff.removed_databases.push(name.clone());
+                    for target in old_database.get_targets() {
+                        diff.removed_targets
+                            .push((name.clone(), target.get_name().clone()));
+                    }
+                }
+                Some(new_database) => {
+                    if !same_settings(old_database, new_database) {
+                        diff.modified_databases.push(name.clone());
+                    }
+                    diff_targets(&mut diff, old_database, new_database);
+                }
+            }
+        }
+
+        for new_database in new.get_databases() {
+            let name = &new_database.database_name;
+            if find_database(old, name).is_none() {
+                diff.added_databases.push(name.clone());
+                for target in new_database.get_targets() {
+                    diff.added_targets
+                        .push((name.clone(), target.get_name().clone()));
+                }
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDiff::default()
+    }
+}
+
+fn find_database<'a>(config: &'a Config, database_name: &String) -> Option<&'a Database> {
+    config
+        .get_databases()
+        .iter()
+        .find(|database| &database.database_name == database_name)
+}
+
+fn same_settings(old: &Database, new: &Database) -> bool {
+    old.database_host == new.database_host
+        && old.server_port == new.server_port
+        && old.database_user == new.database_user
+        && old.database_password == new.database_password
+        && old.update_interval == new.update_interval
+}
+
+fn diff_targets(diff: &mut ConfigDiff, old: &Database, new: &Database) {
+    let name = &old.database_name;
+
+    for old_target in old.get_targets() {
+        match new
+            .get_targets()
+            .iter()
+            .find(|target| target.get_name() == old_target.get_name())
+        {
+            None => diff
+                .removed_targets
+                .push((name.clone(), old_target.get_name().clone())),
+            Some(new_target) => {
+                if new_target != old_target {
+                    diff.modified_targets
+                        .push((name.clone(), old_target.get_name().clone()));
+                }
+            }
+        }
+    }
+
+    for new_target in new.get_targets() {
+        if !old
+            .get_targets()
+            .iter()
+            .any(|target| target.get_name() == new_target.get_name())
+        {
+            diff.added_targets
+                .push((name.clone(), new_target.get_name().clone()));
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use fs2::FileExt;
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use utility::PbusError;
+
+use crate::overrides::{apply_env_overrides, interpolate_env};
+
+/// Config file names looked for in the base mount point, in order of preference
+pub const CONFIG_FILES: [&str; 4] = ["config.toml", "config.yaml", "config.yml", "config.json"];
+/// Name used when no config file exists yet
+pub const DEFAULT_CONFIG_FILE: &str = "config.json";
+const LOCK_FILE: &str = "config.lock";
+const BACKUP_SUFFIX: &str = ".bak";
+const TEMP_SUFFIX: &str = ".tmp";
+const CORRUPT_SUFFIX: &str = ".corrupt";
+
+static CONFIG_FILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Syntax of a config file, picked by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &str) -> Result<ConfigFormat, PbusError> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => Err(PbusError::config(format!(
+                "unsupported config file `{}`",
+                path
+            ))),
+        }
+    }
+
+    pub fn parse(&self, contents: &str) -> Result<Value, PbusError> {
+        match self {
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(PbusError::config),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(PbusError::config),
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(PbusError::config),
+        }
+    }
+
+    pub fn to_string(&self, document: &Value) -> Result<String, PbusError> {
+        match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(document).map_err(PbusError::config),
+            ConfigFormat::Toml => toml::to_string_pretty(document).map_err(PbusError::config),
+            ConfigFormat::Yaml => serde_yaml::to_string(document).map_err(PbusError::config),
+        }
+    }
+}
+
+/// Advisory lock held around read-modify-write cycles of the config file
+///
+/// Every process that edits the config (core, web server, CLI) takes this lock
+/// first, so their edits can't interleave. Released when dropped.
+pub struct ConfigLock {
+    file: File,
+}
+
+impl ConfigLock {
+    pub fn acquire(base_mount_point: &str) -> Result<ConfigLock, PbusError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}{}", base_mount_point, LOCK_FILE))?;
+        file.lock_exclusive()?;
+        Ok(ConfigLock { file })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Uses `path` as the config file instead of looking in the base mount point
+///
+/// Meant to be called once at startup, before the config is first read.
+pub fn set_config_file(path: &str) -> Result<(), PbusError> {
+    ConfigFormat::from_path(path)?;
+    CONFIG_FILE_OVERRIDE
+        .set(path.to_string())
+        .map_err(|_| PbusError::config("config file is already set"))
+}
+
+/// Path of the config file in use, `config.json` if there is none yet
+pub fn config_path(base_mount_point: &str) -> String {
+    if let Some(path) = CONFIG_FILE_OVERRIDE.get() {
+        return path.clone();
+    }
+    for file_name in CONFIG_FILES {
+        let path = format!("{}{}", base_mount_point, file_name);
+        if Path::new(&path).exists() {
+            return path;
+        }
+    }
+    format!("{}{}", base_mount_point, DEFAULT_CONFIG_FILE)
+}
+
+pub fn config_exists(base_mount_point: &str) -> bool {
+    Path::new(&config_path(base_mount_point)).exists()
+}
+
+/// True for any of the file names in `CONFIG_FILES`, or the name of the file set
+/// with `set_config_file`
+pub fn is_config_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return false,
+    };
+    match CONFIG_FILE_OVERRIDE.get() {
+        Some(config_file) => Path::new(config_file).file_name() == Some(name),
+        None => name
+            .to_str()
+            .map(|name| CONFIG_FILES.contains(&name))
+            .unwrap_or(false),
+    }
+}
+
+pub fn backup_path(base_mount_point: &str) -> String {
+    format!("{}{}", config_path(base_mount_point), BACKUP_SUFFIX)
+}
+
+pub fn corrupt_path(base_mount_point: &str) -> String {
+    format!("{}{}", config_path(base_mount_point), CORRUPT_SUFFIX)
+}
+
+/// The config document exactly as written in the file
+///
+/// This is what edits and migrations work on, so `${VAR}` references survive
+/// being written back and overrides never end up in the file.
+pub fn read_raw_document(base_mount_point: &str) -> Result<Value, PbusError> {
+    let path = config_path(base_mount_point);
+    let contents = read_config_file(&path)?;
+
+    ConfigFormat::from_path(&path)?.parse(&contents)
+}
+
+/// The config document the program runs with
+///
+/// `${VAR}` references in string values are replaced with the environment
+/// first, then `PBUS_` prefixed environment variables override individual values.
+pub fn read_document(base_mount_point: &str) -> Result<Value, PbusError> {
+    let mut document = read_raw_document(base_mount_point)?;
+    interpolate_env(&mut document, |name| std::env::var(name).ok())?;
+    apply_env_overrides(&mut document, std::env::vars())?;
+
+    Ok(document)
+}
+
+fn read_config_file(path: &str) -> Result<String, PbusError> {
+    fs::read_to_string(path).map_err(|e| PbusError::config(format!("can't read {}: {}", path, e)))
+}
+
+/// Atomically replaces the config file with `document` in the file's own format
+pub fn write_document(base_mount_point: &str, document: &Value) -> Result<(), PbusError> {
+    let path = config_path(base_mount_point);
+    let contents = ConfigFormat::from_path(&path)?.to_string(document)?;
+
+    write_atomic(&path, contents.as_bytes())
+}
+
+/// Replaces `path` with `contents` so that readers see either the old or the new
+/// file, never a partial one
+///
+/// Writes a temporary file next to it, fsyncs it, renames it over `path` and
+/// fsyncs the directory so the rename itself survives a crash.
+pub fn write_atomic(path: &str, contents: &[u8]) -> Result<(), PbusError> {
+    let temp_path = format!("{}{}", path, TEMP_SUFFIX);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path)?;
+
+    if let Some(dir) = Path::new(path).parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
@@ -1,23 +1,62 @@
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::fs;
-use std::{collections::HashMap, error::Error, time::SystemTime};
-
-use utility::Target;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use tracing::{debug, info, warn};
+use utility::{human_format, PbusError, Target};
+
+pub mod alerting;
+pub mod config_diff;
+pub mod config_file;
+pub mod logging;
+pub mod migrations;
+pub mod overrides;
+pub mod retry;
+pub mod scratch;
+pub mod secrets;
+pub mod validation;
+
+pub use crate::alerting::{AlertChannel, AlertRules, AlertingConfig, ChannelKind};
+pub use crate::config_diff::ConfigDiff;
+pub use crate::config_file::ConfigLock;
+use crate::config_file::{
+    backup_path, config_exists, config_path, corrupt_path, read_document, read_raw_document,
+    write_atomic, write_document, ConfigFormat,
+};
+pub use crate::logging::{init_logging, LoggingConfig};
+pub use crate::migrations::{migrate_config, CONFIG_VERSION};
+pub use crate::retry::{CircuitBreakerPolicy, RetryPolicy};
+pub use crate::scratch::ScratchServer;
+use crate::secrets::PgPassEntry;
+pub use crate::secrets::{SecretRef, SecretsFile};
+pub use crate::validation::{validate_config, ValidationIssue, ValidationReport};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    version: u64,
     databases: Vec<Database>,
-    update: i32,
     base_path: String,
+    #[serde(default)]
+    logging: LoggingConfig,
+    #[serde(default)]
+    alerting: AlertingConfig,
+    /// Where restore drills restore to, drills can't run without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scratch: Option<ScratchServer>,
 }
 
 impl Config {
     pub fn new(databases: Vec<Database>, basepath: &str) -> Config {
         Config {
+            version: CONFIG_VERSION,
             databases,
-            update: 0,
             base_path: basepath.to_string(),
+            logging: LoggingConfig::default(),
+            alerting: AlertingConfig::default(),
+            scratch: None,
         }
     }
 
@@ -26,12 +65,9 @@ impl Config {
     }
 
     pub fn get_database(&mut self, database_name: &String) -> Option<&mut Database> {
-        for database in &mut self.databases {
-            if &database.database_name == database_name {
-                return Some(database);
-            }
-        }
-        None
+        self.databases
+            .iter_mut()
+            .find(|database| &database.database_name == database_name)
     }
 
     pub fn get_databases(&self) -> &Vec<Database> {
@@ -50,7 +86,16 @@ impl Config {
         }
     }
 
-    fn get_database_names(&self) -> Vec<String> {
+    /// Takes a database out of the config, returning it if it was there
+    pub fn remove_database(&mut self, database_name: &str) -> Option<Database> {
+        let index = self
+            .databases
+            .iter()
+            .position(|database| database.database_name == database_name)?;
+        Some(self.databases.remove(index))
+    }
+
+    pub fn get_database_names(&self) -> Vec<String> {
         let mut database_names = Vec::new();
         for database in &self.databases {
             database_names.push(database.database_name.clone());
@@ -68,7 +113,7 @@ impl Config {
         targets
     }
 
-    fn get_database_targets(&self, database_name: String) -> Option<&Vec<Target>> {
+    pub fn get_database_targets(&self, database_name: String) -> Option<&Vec<Target>> {
         for database in &self.databases {
             if database.database_name == database_name {
                 return Some(&database.targets);
@@ -77,28 +122,82 @@ impl Config {
         None
     }
 
-    pub fn write_config(&self, base_mount_point: &str) -> Result<(), Box<dyn Error>> {
-        let json_str = serde_json::to_string_pretty(&self)?;
+    /// Atomically replaces the config file, keeping the previous version as a .bak copy
+    pub fn write_config(&self, base_mount_point: &str) -> Result<(), PbusError> {
+        let _lock = ConfigLock::acquire(base_mount_point)?;
 
-        fs::write(format!("{}config.json", base_mount_point), json_str)?;
+        self.write_config_locked(base_mount_point)
+    }
+
+    fn write_config_locked(&self, base_mount_point: &str) -> Result<(), PbusError> {
+        let document = serde_json::to_value(self).map_err(PbusError::config)?;
+
+        // Only a config that still parses is worth keeping as the last good copy
+        if Config::read_raw_config(base_mount_point).is_ok() {
+            fs::copy(config_path(base_mount_point), backup_path(base_mount_point))?;
+        }
+
+        write_document(base_mount_point, &document)?;
 
         Ok(())
     }
 
-    pub fn read_config(base_mount_point: &str) -> Result<Config, Box<dyn Error>> {
-        let json_str = fs::read_to_string(format!("{}config.json", base_mount_point))?;
+    /// Reads the config the program runs with, environment references and
+    /// `PBUS_` overrides applied
+    pub fn read_config(base_mount_point: &str) -> Result<Config, PbusError> {
+        let document = read_document(base_mount_point)?;
+
+        let config: Config = serde_json::from_value(document).map_err(PbusError::config)?;
+
+        Ok(config)
+    }
+
+    /// Reads the config as written in the file, without touching the environment
+    fn read_raw_config(base_mount_point: &str) -> Result<Config, PbusError> {
+        let document = read_raw_document(base_mount_point)?;
 
-        let config: Config = serde_json::from_str(&json_str)?;
+        let config: Config = serde_json::from_value(document).map_err(PbusError::config)?;
 
         Ok(config)
     }
 
-    pub fn get_update(&self) -> i32 {
-        self.update
+    /// Reads, modifies and writes back the config while holding the config lock,
+    /// so concurrent edits from other processes are not lost
+    ///
+    /// Works on the file as written, so `${VAR}` references are kept and `PBUS_`
+    /// overrides are not persisted.
+    pub fn edit_config<F>(base_mount_point: &str, edit: F) -> Result<Config, PbusError>
+    where
+        F: FnOnce(&mut Config) -> Result<(), PbusError>,
+    {
+        let _lock = ConfigLock::acquire(base_mount_point)?;
+
+        let mut config = Config::read_raw_config(base_mount_point)?;
+        edit(&mut config)?;
+        config.write_config_locked(base_mount_point)?;
+
+        Ok(config)
     }
 
-    pub fn set_update(&mut self, update: i32) {
-        self.update = update;
+    /// Replaces an unparsable config file with the last good copy
+    ///
+    /// The broken file is kept with a .corrupt suffix for inspection.
+    pub fn restore_backup(base_mount_point: &str) -> Result<Config, PbusError> {
+        let _lock = ConfigLock::acquire(base_mount_point)?;
+
+        let path = config_path(base_mount_point);
+        let contents = fs::read_to_string(backup_path(base_mount_point))?;
+        let document = ConfigFormat::from_path(&path)?.parse(&contents)?;
+        let config: Config = serde_json::from_value(document).map_err(PbusError::config)?;
+
+        fs::copy(&path, corrupt_path(base_mount_point))?;
+        write_atomic(&path, contents.as_bytes())?;
+
+        Ok(config)
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
     }
 
     pub fn get_base_path(&self) -> &String {
@@ -108,6 +207,30 @@ impl Config {
     pub fn set_base_path(&mut self, base_path: String) {
         self.base_path = base_path;
     }
+
+    pub fn get_logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
+    pub fn set_logging(&mut self, logging: LoggingConfig) {
+        self.logging = logging;
+    }
+
+    pub fn get_alerting(&self) -> &AlertingConfig {
+        &self.alerting
+    }
+
+    pub fn set_alerting(&mut self, alerting: AlertingConfig) {
+        self.alerting = alerting;
+    }
+
+    pub fn get_scratch(&self) -> Option<&ScratchServer> {
+        self.scratch.as_ref()
+    }
+
+    pub fn set_scratch(&mut self, scratch: Option<ScratchServer>) {
+        self.scratch = scratch;
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -117,19 +240,55 @@ pub struct Database {
     pub server_port: u16,
     pub database_user: String,
     pub database_name: String,
-    pub database_password: String,
+    /// Plaintext or a reference such as `env:PGPASSWORD`, see `SecretRef`
+    pub database_password: SecretRef,
     pub targets: Vec<Target>,
+    #[serde(with = "human_format::duration_secs")]
     pub update_interval: u64,
+    #[serde(with = "human_format::system_time")]
     pub last_updated: SystemTime,
+    /// Longest any target may trail the source, in seconds; targets can override it
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "human_format::optional_duration_secs"
+    )]
+    pub rpo: Option<u64>,
+    /// How often the worker scrubs the segments of every target, in seconds;
+    /// no scheduled scrubs if unset
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "human_format::optional_duration_secs"
+    )]
+    pub scrub_interval: Option<u64>,
+    /// How long backups should reach back, in seconds; only shown, segments are
+    /// never deleted
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "human_format::optional_duration_secs"
+    )]
+    pub retention: Option<u64>,
+    /// When this database raises alerts, no alerts if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<AlertRules>,
+    /// How failed runs are retried
+    #[serde(default, skip_serializing_if = "RetryPolicy::is_default")]
+    pub retry: RetryPolicy,
+    /// When the database is paused after repeated failures
+    #[serde(default, skip_serializing_if = "CircuitBreakerPolicy::is_default")]
+    pub circuit_breaker: CircuitBreakerPolicy,
 }
 
 impl Database {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         database_host: String,
         server_port: u16,
         database_user: String,
         database_name: String,
-        database_password: String,
+        database_password: SecretRef,
         targets: Vec<Target>,
         update_interval: u64,
         last_updated: SystemTime,
@@ -143,109 +302,81 @@ impl Database {
             targets,
             update_interval,
             last_updated,
+            rpo: None,
+            scrub_interval: None,
+            retention: None,
+            alerts: None,
+            retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerPolicy::default(),
         }
     }
 
-    fn add_target(&mut self, target: Target) {
-        self.targets.push(target);
-    }
-
-    pub fn get_targets(&self) -> &Vec<Target> {
-        &self.targets
+    /// Recovery point objective of a target, its own or else the database's
+    pub fn get_target_rpo(&self, target_name: &str) -> Option<Duration> {
+        let target = self
+            .targets
+            .iter()
+            .find(|target| target.get_name() == target_name)?;
+        target.get_rpo().or(self.rpo).map(Duration::from_secs)
     }
 
-    pub fn get_target(&mut self, target_name: String) -> Option<&mut Target> {
-        for target in &mut self.targets {
-            if target.get_name().to_string() == target_name.to_string() {
-                return Some(target);
-            }
-        }
-        None
+    /// Looks up the password, only call this right before connecting
+    pub fn resolve_password(&self, base_mount_point: &str) -> Result<String, PbusError> {
+        let entry = PgPassEntry {
+            host: &self.database_host,
+            port: self.server_port,
+            database: &self.database_name,
+            user: &self.database_user,
+        };
+        self.database_password
+            .resolve(base_mount_point, &entry)
+            .map_err(|e| e.context(format!("password of {}", self.database_name)))
     }
 
-    fn get_target_fields(&self, target_name: String) -> Option<&HashMap<String, String>> {
-        for target in &self.targets {
-            if target.get_name().to_string() == target_name {
-                return Some(target.get_fields());
-            }
-        }
-        None
+    pub fn add_target(&mut self, target: Target) {
+        self.targets.push(target);
     }
 
-    fn get_target_field(&self, target_name: String, field_name: String) -> Option<&String> {
-        for target in &self.targets {
-            if target.get_name().to_string() == target_name {
-                return target.get_field(field_name);
-            }
-        }
-        None
+    pub fn get_targets(&self) -> &Vec<Target> {
+        &self.targets
     }
 
-    pub fn get_target_last_id(&self, target_name: String) -> Option<i32> {
-        for target in &self.targets {
-            if target.get_name().to_string() == target_name {
-                return Some(target.get_last_id());
-            }
-        }
-        None
+    /// Takes a target out of the database, returning it if it was there
+    pub fn remove_target(&mut self, target_name: &str) -> Option<Target> {
+        let index = self
+            .targets
+            .iter()
+            .position(|target| target.get_name() == target_name)?;
+        Some(self.targets.remove(index))
     }
 
-    pub fn set_target_last_id(&mut self, target_name: String, last_id: &i32) {
-        for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
-                target.set_last_id(*last_id);
-            }
-        }
+    pub fn get_target(&mut self, target_name: String) -> Option<&mut Target> {
+        self.targets
+            .iter_mut()
+            .find(|target| *target.get_name() == target_name)
     }
 
-    pub fn get_target_last_updated(&self, target_name: String) -> Option<&SystemTime> {
+    pub fn get_target_fields(&self, target_name: String) -> Option<&HashMap<String, String>> {
         for target in &self.targets {
-            if target.get_name().to_string() == target_name {
-                return Some(target.get_last_updated());
+            if *target.get_name() == target_name {
+                return Some(target.get_fields());
             }
         }
         None
     }
 
-    pub fn set_target_last_updated(&mut self, target_name: String, last_updated: SystemTime) {
-        for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
-                target.set_last_updated(last_updated);
-            }
-        }
-    }
-
-    pub fn get_target_last_checked(&self, target_name: String) -> Option<&SystemTime> {
+    pub fn get_target_field(&self, target_name: String, field_name: String) -> Option<&String> {
         for target in &self.targets {
-            if target.get_name().to_string() == target_name {
-                return Some(target.get_last_checked());
+            if *target.get_name() == target_name {
+                return target.get_field(field_name);
             }
         }
         None
     }
 
-    pub fn set_target_last_checked(&mut self, target_name: String, last_checked: SystemTime) {
-        for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
-                target.set_last_checked(last_checked);
-            }
-        }
-    }
-    pub fn set_target_last_checked_time(&mut self, target_name: String, last_checked: u64) {
-        for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
-                target.set_last_checked(
-                    SystemTime::UNIX_EPOCH
-                        .checked_add(std::time::Duration::from_secs(last_checked))
-                        .unwrap(),
-                );
-            }
-        }
-    }
-
     pub fn get_target_update_interval(&self, target_name: String) -> Option<u64> {
         for target in &self.targets {
-            if target.get_name().to_string() == target_name {
+            if *target.get_name() == target_name {
                 return Some(self.update_interval);
             }
         }
@@ -254,7 +385,7 @@ impl Database {
 
     pub fn get_target_last_updated_time(&self, target_name: String) -> Option<u64> {
         for target in &self.targets {
-            if target.get_name().to_string() == target_name {
+            if *target.get_name() == target_name {
                 return Some(
                     self.last_updated
                         .duration_since(SystemTime::UNIX_EPOCH)
@@ -268,7 +399,7 @@ impl Database {
 
     pub fn set_target_last_updated_time(&mut self, target_name: String, last_updated: u64) {
         for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
+            if *target.get_name() == target_name {
                 self.last_updated = SystemTime::UNIX_EPOCH
                     .checked_add(std::time::Duration::from_secs(last_updated))
                     .unwrap();
@@ -278,7 +409,7 @@ impl Database {
 
     pub fn get_target_enabled(&self, target_name: String) -> Option<bool> {
         for target in &self.targets {
-            if target.get_name().to_string() == target_name {
+            if *target.get_name() == target_name {
                 return Some(target.get_enabled());
             }
         }
@@ -287,7 +418,7 @@ impl Database {
 
     pub fn set_target_enabled(&mut self, target_name: String, enabled: bool) {
         for target in &mut self.targets {
-            if target.get_name().to_string() == target_name {
+            if *target.get_name() == target_name {
                 target.set_enabled(enabled);
             }
         }
@@ -304,9 +435,9 @@ pub enum ConfigStatus {
     Existing,
 }
 
-pub fn check_config(base_mount_point: &str) -> Result<ConfigStatus, Box<dyn Error>> {
-    if fs::metadata(format!("{}/config.json", base_mount_point)).is_err() {
-        println!("Config file does not exist, creating new config file");
+pub fn check_config(base_mount_point: &str) -> Result<ConfigStatus, PbusError> {
+    if !config_exists(base_mount_point) {
+        info!("Config file does not exist, creating new config file");
 
         let config: Config = Config::new(Vec::new(), base_mount_point);
 
@@ -314,20 +445,27 @@ pub fn check_config(base_mount_point: &str) -> Result<ConfigStatus, Box<dyn Erro
 
         Ok(ConfigStatus::New)
     } else {
-        println!("Config file exists, reading config file");
-        let config = Config::read_config(base_mount_point)?;
-        Ok(ConfigStatus::Existing)
-    }
-}
-
-pub fn check_config_update(base_mount_point: &str) -> Result<bool, Box<dyn Error>> {
-    let config = Config::read_config(base_mount_point)?;
+        debug!("Config file exists, reading config file");
+        let migrated = match migrate_config(base_mount_point) {
+            Ok(migrated) => migrated,
+            Err(e) => {
+                warn!("Config file is invalid ({}), restoring last good copy", e);
+                Config::restore_backup(base_mount_point)?;
+                migrate_config(base_mount_point)?
+            }
+        };
+        if let Some(version) = migrated {
+            info!(
+                "Migrated config file from version {} to {}",
+                version, CONFIG_VERSION
+            );
+        }
 
-    let mut update = false;
+        let config = validate_config(base_mount_point)?;
+        for warning in validation::config_warnings(&config) {
+            warn!("Config: {}", warning);
+        }
 
-    if config.update == 1 {
-        update = true;
+        Ok(ConfigStatus::Existing)
     }
-
-    Ok(update)
 }
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::{FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+use utility::PbusError;
+
+use crate::config_file::read_document;
+
+const LOG_DIR: &str = "logs";
+const LOG_FILE_PREFIX: &str = "pbus";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// The `logging` section of the config
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// A level such as `info`, or directives like `info,pbus_timer=debug`.
+    /// `RUST_LOG` takes precedence when set.
+    pub level: String,
+    pub format: LogFormat,
+    /// Also write logs to `<base_mount_point>logs/`
+    pub file: bool,
+    pub rotation: LogRotation,
+    /// Log files kept before the oldest is deleted
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            file: false,
+            rotation: LogRotation::Daily,
+            max_files: 7,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// The logging section of the config file, or the defaults if the file is
+    /// missing or can't be read
+    ///
+    /// Logging has to be set up before the config is checked, so this doesn't
+    /// insist on a valid file.
+    pub fn load(base_mount_point: &str) -> LoggingConfig {
+        read_document(base_mount_point)
+            .ok()
+            .and_then(|document| document.get("logging").cloned())
+            .and_then(|logging| serde_json::from_value(logging).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn filter(&self) -> Result<EnvFilter, PbusError> {
+        if let Ok(filter) = EnvFilter::try_from_default_env() {
+            return Ok(filter);
+        }
+        EnvFilter::try_new(&self.level)
+            .map_err(|e| PbusError::config(format!("invalid log level `{}`: {}", self.level, e)))
+    }
+}
+
+/// Installs the global tracing subscriber
+///
+/// Logs go to stderr, and with `file` set also to rotating files under the base
+/// mount point. The returned guard flushes the file writer when dropped, so keep
+/// it alive until the process exits.
+pub fn init_logging(
+    base_mount_point: &str,
+    config: &LoggingConfig,
+) -> Result<Option<WorkerGuard>, PbusError> {
+    let mut layers = vec![fmt_layer(config.format, std::io::stderr, true)];
+
+    let guard = if config.file {
+        let rotation = match config.rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let dir = format!("{}{}", base_mount_point, LOG_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix("log")
+            .max_log_files(config.max_files.max(1))
+            .build(dir)
+            .map_err(PbusError::storage)?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        layers.push(fmt_layer(config.format, writer, false));
+        Some(guard)
+    } else {
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(config.filter()?)
+        .try_init()
+        .map_err(|e| PbusError::config(format!("can't set up logging: {}", e)))?;
+
+    Ok(guard)
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match (format, ansi) {
+        (LogFormat::Text, true) => layer.boxed(),
+        (LogFormat::Text, false) => layer.fmt_fields(PlainFields::default()).boxed(),
+        (LogFormat::Json, _) => layer.json().boxed(),
+    }
+}
+
+/// Span fields formatted without colours
+///
+/// Layers cache formatted span fields per field formatter type, so the file layer
+/// needs a type of its own or it would reuse the coloured fields of the stderr
+/// layer.
+#[derive(Default)]
+struct PlainFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for PlainFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
@@ -0,0 +1,197 @@
+use pbus_db_manager::{StateStore, TargetState};
+use serde_json::Value;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use utility::PbusError;
+
+use crate::config_file::{config_path, read_raw_document, write_document, ConfigLock};
+use crate::secrets::SecretRef;
+
+/// Version of the config format this build reads and writes
+///
+/// * 0 - targets carry `last_id`, `last_updated` and `last_checked`, plus a
+///   top-level `update` flag
+/// * 1 - runtime state lives in the state store, no `version` field yet
+/// * 2 - adds the `version` field
+/// * 3 - `update_interval` is a duration like `"15m"` and `last_updated` an
+///   RFC 3339 timestamp
+/// * 4 - `database_password` is a secret reference, see `SecretRef`
+pub const CONFIG_VERSION: u64 = 4;
+
+type Migration = fn(&mut Value, &StateStore) -> Result<(), PbusError>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4];
+
+/// Version of a config document
+///
+/// Documents from before the `version` field are told apart by their runtime
+/// fields.
+pub fn document_version(document: &Value) -> u64 {
+    if let Some(version) = document.get("version").and_then(Value::as_u64) {
+        return version;
+    }
+    if document.get("update").is_some() || targets(document).any(has_runtime_state) {
+        0
+    } else {
+        1
+    }
+}
+
+/// Upgrades a config document to `CONFIG_VERSION` one step at a time
+///
+/// Returns the version the document started at.
+pub fn migrate_document(document: &mut Value, state: &StateStore) -> Result<u64, PbusError> {
+    let version = document_version(document);
+    if version > CONFIG_VERSION {
+        return Err(PbusError::config(format!(
+            "config version {} is newer than the supported version {}",
+            version, CONFIG_VERSION
+        )));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(document, state)?;
+    }
+
+    Ok(version)
+}
+
+/// Upgrades the config file in place if it is from an older version
+///
+/// The original is kept next to it with a `.v<version>` suffix. Returns the version
+/// the file was migrated from, or `None` if it was already current.
+pub fn migrate_config(base_mount_point: &str) -> Result<Option<u64>, PbusError> {
+    let _lock = ConfigLock::acquire(base_mount_point)?;
+
+    let mut document = read_raw_document(base_mount_point)?;
+
+    if document_version(&document) == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let state = StateStore::open(base_mount_point)?;
+    let version = migrate_document(&mut document, &state)?;
+
+    let path = config_path(base_mount_point);
+    fs::copy(&path, format!("{}.v{}", path, version))?;
+    write_document(base_mount_point, &document)?;
+
+    Ok(Some(version))
+}
+
+fn targets(document: &Value) -> impl Iterator<Item = &Value> {
+    document["databases"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|database| database["targets"].as_array().into_iter().flatten())
+}
+
+fn has_runtime_state(target: &Value) -> bool {
+    target.get("last_id").is_some()
+        || target.get("last_updated").is_some()
+        || target.get("last_checked").is_some()
+}
+
+/// Moves each target's `last_id`, `last_updated` and `last_checked` into the state
+/// store and drops the `update` flag, which the config watcher replaced
+///
+/// Targets that already have state are left alone.
+fn v0_to_v1(document: &mut Value, state: &StateStore) -> Result<(), PbusError> {
+    if let Some(document) = document.as_object_mut() {
+        document.remove("update");
+    }
+
+    let databases = match document["databases"].as_array_mut() {
+        Some(databases) => databases,
+        None => return Ok(()),
+    };
+
+    for database in databases {
+        let database_name = database["database_name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let targets = match database["targets"].as_array_mut() {
+            Some(targets) => targets,
+            None => continue,
+        };
+
+        for target in targets {
+            if !has_runtime_state(target) {
+                continue;
+            }
+            let target_name = target["name"].as_str().unwrap_or_default().to_string();
+
+            if !state.has_target_state(&database_name, &target_name)? {
+                let mut target_state = TargetState::default();
+                if let Some(last_id) = target["last_id"].as_i64() {
+                    target_state.last_id = last_id;
+                }
+                if let Ok(last_updated) = serde_json::from_value(target["last_updated"].clone()) {
+                    target_state.last_updated = last_updated;
+                }
+                if let Ok(last_checked) = serde_json::from_value(target["last_checked"].clone()) {
+                    target_state.last_checked = last_checked;
+                }
+                state.set_target_state(&database_name, &target_name, &target_state)?;
+            }
+
+            if let Some(target) = target.as_object_mut() {
+                target.remove("last_id");
+                target.remove("last_updated");
+                target.remove("last_checked");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn v1_to_v2(document: &mut Value, _state: &StateStore) -> Result<(), PbusError> {
+    if let Some(document) = document.as_object_mut() {
+        document.insert("version".to_string(), Value::from(2));
+    }
+    Ok(())
+}
+
+/// Rewrites seconds and `{secs_since_epoch, nanos_since_epoch}` objects in the
+/// readable forms `human_format` produces
+fn v2_to_v3(document: &mut Value, _state: &StateStore) -> Result<(), PbusError> {
+    if let Some(databases) = document["databases"].as_array_mut() {
+        for database in databases {
+            if let Some(secs) = database["update_interval"].as_u64() {
+                let interval = humantime::format_duration(Duration::from_secs(secs));
+                database["update_interval"] = Value::from(interval.to_string());
+            }
+            if let Ok(last_updated) =
+                serde_json::from_value::<SystemTime>(database["last_updated"].clone())
+            {
+                let timestamp = humantime::format_rfc3339_seconds(last_updated);
+                database["last_updated"] = Value::from(timestamp.to_string());
+            }
+        }
+    }
+    if let Some(document) = document.as_object_mut() {
+        document.insert("version".to_string(), Value::from(3));
+    }
+    Ok(())
+}
+
+/// Marks plaintext passwords that read like a secret reference, such as
+/// `env:x`, as `plain:`, so they keep meaning the password itself
+fn v3_to_v4(document: &mut Value, _state: &StateStore) -> Result<(), PbusError> {
+    if let Some(databases) = document["databases"].as_array_mut() {
+        for database in databases {
+            if let Some(password) = database["database_password"].as_str() {
+                let password = SecretRef::Plain(password.to_string()).to_reference();
+                database["database_password"] = Value::from(password);
+            }
+        }
+    }
+    if let Some(document) = document.as_object_mut() {
+        document.insert("version".to_string(), Value::from(4));
+    }
+    Ok(())
+}
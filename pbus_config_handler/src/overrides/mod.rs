@@ -0,0 +1,174 @@
+use serde_json::Value;
+use utility::PbusError;
+
+/// Prefix of environment variables that override config values
+pub const ENV_PREFIX: &str = "PBUS_";
+
+/// Replaces `${VAR}` and `${VAR:-default}` in the string values of a parsed
+/// config document, looking variables up with `lookup`
+///
+/// Keys, numbers and booleans are left alone, so a value can neither break the
+/// document's syntax nor add keys to it; numbers and booleans are overridden
+/// with `PBUS_` variables instead. `$${` stands for a literal `${`. Every
+/// variable that is unset and has no default is reported in one error.
+pub fn interpolate_env<F>(document: &mut Value, lookup: F) -> Result<(), PbusError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut missing = Vec::new();
+    interpolate_value(document, &lookup, &mut missing)?;
+
+    if !missing.is_empty() {
+        return Err(PbusError::config(format!(
+            "environment variable(s) referenced by the config are not set: {}",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+fn interpolate_value<F>(
+    value: &mut Value,
+    lookup: &F,
+    missing: &mut Vec<String>,
+) -> Result<(), PbusError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match value {
+        Value::String(string) if string.contains("${") => {
+            *string = interpolate_str(string, lookup, missing)?;
+        }
+        Value::Array(elements) => {
+            for element in elements {
+                interpolate_value(element, lookup, missing)?;
+            }
+        }
+        Value::Object(map) => {
+            for element in map.values_mut() {
+                interpolate_value(element, lookup, missing)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str<F>(
+    contents: &str,
+    lookup: &F,
+    missing: &mut Vec<String>,
+) -> Result<String, PbusError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                return Err(PbusError::config(format!(
+                    "unterminated `${{` in config value `{}`",
+                    contents
+                )))
+            }
+        };
+
+        let reference = &rest[start + 2..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+
+        match (lookup(name), default) {
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => {
+                if !missing.iter().any(|known| known == name) {
+                    missing.push(name.to_string());
+                }
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Applies `PBUS_` prefixed variables on top of a config document
+///
+/// The rest of the name is a path with `__` between segments, matched case
+/// insensitively. Array elements are picked by index or by their `database_name`
+/// or `name`, so `PBUS_DATABASES__SHOP__DATABASE_PASSWORD` sets the password of
+/// database `shop`. Variables whose first segment isn't a config key are left
+/// alone, they belong to other settings.
+pub fn apply_env_overrides<I>(document: &mut Value, vars: I) -> Result<(), PbusError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (key, value) in vars {
+        let path = match key.strip_prefix(ENV_PREFIX) {
+            Some(path) => path.to_lowercase(),
+            None => continue,
+        };
+        let segments: Vec<&str> = path.split("__").collect();
+
+        if document.get(segments[0]).is_none() {
+            continue;
+        }
+
+        let target = resolve(document, &segments)
+            .ok_or_else(|| PbusError::config(format!("{} does not match any config value", key)))?;
+        *target = match target {
+            Value::String(_) => Value::String(value),
+            _ => serde_json::from_str(&value).unwrap_or(Value::String(value)),
+        };
+    }
+
+    Ok(())
+}
+
+fn resolve<'a>(document: &'a mut Value, segments: &[&str]) -> Option<&'a mut Value> {
+    let mut current = document;
+
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        current = match current {
+            Value::Object(map) => {
+                if !map.contains_key(*segment) && !last {
+                    return None;
+                }
+                map.entry(segment.to_string()).or_insert(Value::Null)
+            }
+            Value::Array(elements) => {
+                let position = match segment.parse::<usize>() {
+                    Ok(index) if index < elements.len() => index,
+                    _ => elements.iter().position(|element| {
+                        ["database_name", "name"].iter().any(|field| {
+                            element[field]
+                                .as_str()
+                                .map(|name| name.eq_ignore_ascii_case(segment))
+                                .unwrap_or(false)
+                        })
+                    })?,
+                };
+                &mut elements[position]
+            }
+            _ => return None,
+        };
+    }
+
+    Some(current)
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use utility::human_format;
+
+/// How a run that failed with a retryable error is tried again, the `retry`
+/// section of a `Database`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts per run including the first one, 1 turns retries off
+    pub max_attempts: u32,
+    /// Wait before the first retry, in seconds
+    #[serde(with = "human_format::duration_secs")]
+    pub initial_backoff: u64,
+    /// Longest wait between two attempts, in seconds
+    #[serde(with = "human_format::duration_secs")]
+    pub max_backoff: u64,
+    /// Factor the wait grows by with every retry
+    pub multiplier: f64,
+    /// Share of the wait that is randomised, from 0 to 1, so that databases
+    /// failing together don't retry in lockstep
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: 1,
+            max_backoff: 60,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == RetryPolicy::default()
+    }
+
+    /// Wait before retry number `retry`, counted from 0, without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let secs = self.initial_backoff as f64 * self.multiplier.powi(retry as i32);
+        Duration::from_secs_f64(secs.min(self.max_backoff as f64))
+    }
+}
+
+/// When a database is paused after failing again and again, the
+/// `circuit_breaker` section of a `Database`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CircuitBreakerPolicy {
+    /// Failed runs in a row that open the circuit, 0 turns it off
+    pub failure_threshold: u32,
+    /// How long an open circuit waits before one run probes the database, in
+    /// seconds
+    #[serde(with = "human_format::duration_secs")]
+    pub probe_interval: u64,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> CircuitBreakerPolicy {
+        CircuitBreakerPolicy {
+            failure_threshold: 5,
+            probe_interval: 5 * 60,
+        }
+    }
+}
+
+impl CircuitBreakerPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == CircuitBreakerPolicy::default()
+    }
+}
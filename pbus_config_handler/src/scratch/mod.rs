@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use utility::PbusError;
+
+use crate::secrets::{PgPassEntry, SecretRef};
+
+/// Server restore drills write to, the `scratch` section of the config
+///
+/// Every drill restores into a schema of its own in `database` and drops it when
+/// it ends, so `user` needs the `CREATE` privilege there. Use a server the
+/// backed up databases don't run on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScratchServer {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub database: String,
+    /// Plaintext or a reference such as `env:PGPASSWORD`, see `SecretRef`
+    pub password: SecretRef,
+}
+
+impl ScratchServer {
+    /// Looks up the password, only call this right before connecting
+    pub fn resolve_password(&self, base_mount_point: &str) -> Result<String, PbusError> {
+        let entry = PgPassEntry {
+            host: &self.host,
+            port: self.port,
+            database: &self.database,
+            user: &self.user,
+        };
+        self.password
+            .resolve(base_mount_point, &entry)
+            .map_err(|e| e.context("password of the scratch server"))
+    }
+}
@@ -0,0 +1,344 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use utility::PbusError;
+
+use crate::config_file::write_atomic;
+
+const SECRETS_FILE: &str = "secrets.enc";
+const MASTER_KEY_ENV: &str = "PBUS_MASTER_KEY";
+const MASTER_KEY_FILE_ENV: &str = "PBUS_MASTER_KEY_FILE";
+/// Bytes of a ChaCha20-Poly1305 nonce
+const NONCE_LEN: usize = 12;
+
+/// Where a secret such as a database password comes from
+///
+/// Written in the config as a single string:
+/// * `env:NAME` - environment variable `NAME`
+/// * `file:/run/secrets/db` - contents of a file, e.g. a Docker or Kubernetes secret
+/// * `pgpass` or `pgpass:/path` - the matching line of a `.pgpass` file
+/// * `secret:NAME` - entry `NAME` of the encrypted secrets file
+/// * `plain:value` or anything else - the value itself
+///
+/// References are only resolved when a connection is made. `Debug` never prints a
+/// plaintext value.
+#[derive(Clone, PartialEq, Eq)]
+pub enum SecretRef {
+    Plain(String),
+    Env(String),
+    File(String),
+    PgPass(Option<String>),
+    Encrypted(String),
+}
+
+/// Connection details a `.pgpass` line is matched against
+pub struct PgPassEntry<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub database: &'a str,
+    pub user: &'a str,
+}
+
+impl SecretRef {
+    pub fn resolve(
+        &self,
+        base_mount_point: &str,
+        entry: &PgPassEntry,
+    ) -> Result<String, PbusError> {
+        match self {
+            SecretRef::Plain(value) => Ok(value.clone()),
+            SecretRef::Env(name) => std::env::var(name).map_err(|_| {
+                PbusError::config(format!("environment variable {} is not set", name))
+            }),
+            SecretRef::File(path) => {
+                let contents = fs::read_to_string(path).map_err(|e| {
+                    PbusError::config(format!("can't read secret file {}: {}", path, e))
+                })?;
+                Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+            }
+            SecretRef::PgPass(path) => {
+                let path = match path {
+                    Some(path) => PathBuf::from(path),
+                    None => default_pgpass_path()?,
+                };
+                let contents = fs::read_to_string(&path).map_err(|e| {
+                    PbusError::config(format!("can't read {}: {}", path.display(), e))
+                })?;
+                find_pgpass_password(&contents, entry).ok_or_else(|| {
+                    PbusError::config(format!("no matching entry in {}", path.display()))
+                })
+            }
+            SecretRef::Encrypted(name) => {
+                let secrets = SecretsFile::unlock(base_mount_point, &master_key()?)?;
+                secrets.get(name).cloned().ok_or_else(|| {
+                    PbusError::config(format!("secret {} is not in the secrets file", name))
+                })
+            }
+        }
+    }
+
+    /// Looks up a secret that isn't a database password, where `pgpass` has no
+    /// entry to match
+    pub fn resolve_secret(&self, base_mount_point: &str) -> Result<String, PbusError> {
+        if let SecretRef::PgPass(_) = self {
+            return Err(PbusError::config("pgpass only holds database passwords"));
+        }
+        let entry = PgPassEntry {
+            host: "",
+            port: 0,
+            database: "",
+            user: "",
+        };
+        self.resolve(base_mount_point, &entry)
+    }
+
+    pub fn is_plain(&self) -> bool {
+        matches!(self, SecretRef::Plain(_))
+    }
+
+    /// The reference as written in the config file
+    pub fn to_reference(&self) -> String {
+        match self {
+            SecretRef::Plain(value) => match value.parse() {
+                Ok(SecretRef::Plain(parsed)) if parsed == *value => value.clone(),
+                _ => format!("plain:{}", value),
+            },
+            SecretRef::Env(name) => format!("env:{}", name),
+            SecretRef::File(path) => format!("file:{}", path),
+            SecretRef::PgPass(None) => "pgpass".to_string(),
+            SecretRef::PgPass(Some(path)) => format!("pgpass:{}", path),
+            SecretRef::Encrypted(name) => format!("secret:{}", name),
+        }
+    }
+}
+
+impl FromStr for SecretRef {
+    type Err = String;
+
+    fn from_str(reference: &str) -> Result<SecretRef, String> {
+        if reference == "pgpass" {
+            return Ok(SecretRef::PgPass(None));
+        }
+        let (scheme, rest) = match reference.split_once(':') {
+            Some(parts) => parts,
+            None => return Ok(SecretRef::Plain(reference.to_string())),
+        };
+        let non_empty = |rest: &str| {
+            if rest.is_empty() {
+                Err(format!("`{}` is missing a name or path", reference))
+            } else {
+                Ok(rest.to_string())
+            }
+        };
+        match scheme {
+            "plain" => Ok(SecretRef::Plain(rest.to_string())),
+            "env" => Ok(SecretRef::Env(non_empty(rest)?)),
+            "file" => Ok(SecretRef::File(non_empty(rest)?)),
+            "pgpass" => Ok(SecretRef::PgPass(Some(non_empty(rest)?))),
+            "secret" => Ok(SecretRef::Encrypted(non_empty(rest)?)),
+            _ => Ok(SecretRef::Plain(reference.to_string())),
+        }
+    }
+}
+
+impl fmt::Debug for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Plain(_) => write!(f, "<redacted>"),
+            _ => write!(f, "{}", self.to_reference()),
+        }
+    }
+}
+
+impl Serialize for SecretRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_reference())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SecretRef, D::Error> {
+        let reference = String::deserialize(deserializer)?;
+        reference.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn default_pgpass_path() -> Result<PathBuf, PbusError> {
+    if let Ok(path) = std::env::var("PGPASSFILE") {
+        return Ok(PathBuf::from(path));
+    }
+    let home = std::env::var("HOME")
+        .map_err(|_| PbusError::config("HOME is not set, can't find .pgpass"))?;
+    Ok(PathBuf::from(home).join(".pgpass"))
+}
+
+/// Password of the first `.pgpass` line matching the connection
+///
+/// Lines are `hostname:port:database:username:password`, `*` matches anything and
+/// `\:` and `\\` escape.
+fn find_pgpass_password(contents: &str, entry: &PgPassEntry) -> Option<String> {
+    let port = entry.port.to_string();
+    let wanted = [entry.host, port.as_str(), entry.database, entry.user];
+
+    for line in contents.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_pgpass_line(line);
+        if fields.len() != 5 {
+            continue;
+        }
+        if fields[..4]
+            .iter()
+            .zip(wanted)
+            .all(|(field, wanted)| field == "*" || field == wanted)
+        {
+            return Some(fields[4].clone());
+        }
+    }
+    None
+}
+
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Master key of the secrets file, from `PBUS_MASTER_KEY` or the file named by
+/// `PBUS_MASTER_KEY_FILE`
+pub fn master_key() -> Result<String, PbusError> {
+    if let Ok(key) = std::env::var(MASTER_KEY_ENV) {
+        return Ok(key);
+    }
+    if let Ok(path) = std::env::var(MASTER_KEY_FILE_ENV) {
+        let key = fs::read_to_string(&path)
+            .map_err(|e| PbusError::config(format!("can't read {}: {}", path, e)))?;
+        return Ok(key.trim_end_matches(['\r', '\n']).to_string());
+    }
+    Err(PbusError::config(format!(
+        "the secrets file is locked, set {} or {}",
+        MASTER_KEY_ENV, MASTER_KEY_FILE_ENV
+    )))
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Named secrets kept encrypted at `<base_mount_point>secrets.enc`
+///
+/// The key is derived from the master key with Argon2 and the contents are sealed
+/// with ChaCha20-Poly1305, so a wrong master key or a tampered file fails to open.
+pub struct SecretsFile {
+    secrets: BTreeMap<String, String>,
+}
+
+impl SecretsFile {
+    /// Decrypts the secrets file, or starts an empty one if there is none yet
+    pub fn unlock(base_mount_point: &str, master_key: &str) -> Result<SecretsFile, PbusError> {
+        let path = secrets_path(base_mount_point);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(SecretsFile {
+                    secrets: BTreeMap::new(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let corrupt = |e: &dyn fmt::Display| PbusError::config(format!("{}: {}", path, e));
+        let file: EncryptedFile = serde_json::from_str(&contents).map_err(|e| corrupt(&e))?;
+        let salt = hex::decode(&file.salt).map_err(|e| corrupt(&e))?;
+        let nonce = hex::decode(&file.nonce).map_err(|e| corrupt(&e))?;
+        let ciphertext = hex::decode(&file.ciphertext).map_err(|e| corrupt(&e))?;
+        if nonce.len() != NONCE_LEN {
+            return Err(corrupt(&format!(
+                "nonce is {} bytes, expected {}",
+                nonce.len(),
+                NONCE_LEN
+            )));
+        }
+        let plaintext = cipher(master_key, &salt)?
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| PbusError::config("can't decrypt the secrets file, wrong master key?"))?;
+
+        Ok(SecretsFile {
+            secrets: serde_json::from_slice(&plaintext).map_err(|e| corrupt(&e))?,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.secrets.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        self.secrets.keys().collect()
+    }
+
+    pub fn set(&mut self, name: String, value: String) {
+        self.secrets.insert(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.secrets.remove(name)
+    }
+
+    /// Encrypts and writes the secrets with a fresh salt and nonce
+    pub fn save(&self, base_mount_point: &str, master_key: &str) -> Result<(), PbusError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let plaintext = serde_json::to_vec(&self.secrets).map_err(PbusError::config)?;
+        let ciphertext = cipher(master_key, &salt)?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| PbusError::config("can't encrypt the secrets file"))?;
+
+        let file = EncryptedFile {
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+        write_atomic(
+            &secrets_path(base_mount_point),
+            serde_json::to_string_pretty(&file)
+                .map_err(PbusError::config)?
+                .as_bytes(),
+        )
+    }
+}
+
+fn secrets_path(base_mount_point: &str) -> String {
+    format!("{}{}", base_mount_point, SECRETS_FILE)
+}
+
+fn cipher(master_key: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, PbusError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(master_key.as_bytes(), salt, &mut key)
+        .map_err(|e| PbusError::config(format!("can't derive the secrets key: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
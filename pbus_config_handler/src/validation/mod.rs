@@ -0,0 +1,644 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use serde_json::Value;
+use utility::PbusError;
+
+use crate::alerting::{AlertingConfig, ChannelKind};
+use crate::config_file::{config_path, read_document, ConfigFormat};
+use crate::migrations::CONFIG_VERSION;
+use crate::scratch::ScratchServer;
+use crate::secrets::SecretRef;
+use crate::{Config, Database};
+
+/// Column the capture query pages through, see `DbHandler::get_rows`
+pub const CURSOR_COLUMN: &str = "id";
+
+const CURSOR_TYPES: [&str; 3] = ["integer", "bigint", "smallint"];
+
+/// True for column types the cursor column can have
+pub fn is_cursor_type(data_type: &str) -> bool {
+    CURSOR_TYPES.contains(&data_type)
+}
+
+/// One problem found in a config document
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    /// Location of the offending value, e.g. `$.databases[0].update_interval`
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}: {}", line, self.path, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Every problem found in a config document, not just the first
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, lines: &HashMap<String, usize>, path: String, message: String) {
+        let line = lines.get(&path).copied();
+        self.issues.push(ValidationIssue {
+            path,
+            line,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "config has {} problem(s):", self.issues.len())?;
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationReport {}
+
+impl From<ValidationReport> for PbusError {
+    fn from(report: ValidationReport) -> PbusError {
+        PbusError::config(report)
+    }
+}
+
+/// Reads and validates the config file the program runs with
+///
+/// Line numbers are only reported for JSON files.
+pub fn validate_config(base_mount_point: &str) -> Result<Config, PbusError> {
+    let path = config_path(base_mount_point);
+    let document = read_document(base_mount_point)?;
+
+    let lines = match ConfigFormat::from_path(&path)? {
+        ConfigFormat::Json => locate_lines(&fs::read_to_string(&path)?),
+        _ => HashMap::new(),
+    };
+
+    Ok(validate_document(document, &lines)?)
+}
+
+/// Parses a JSON config document and checks it for problems the type system
+/// can't catch
+///
+/// Returns the config only if no problems were found.
+pub fn validate_config_str(json_str: &str) -> Result<Config, ValidationReport> {
+    let document = match serde_json::from_str(json_str) {
+        Ok(document) => document,
+        Err(e) => {
+            return Err(ValidationReport {
+                issues: vec![ValidationIssue {
+                    path: "$".to_string(),
+                    line: Some(e.line()),
+                    message: e.to_string(),
+                }],
+            })
+        }
+    };
+
+    validate_document(document, &locate_lines(json_str))
+}
+
+/// Things worth fixing that don't make a config invalid, such as passwords
+/// written into the file in plaintext
+pub fn config_warnings(config: &Config) -> Vec<ValidationIssue> {
+    let mut passwords: Vec<(String, &SecretRef)> = config
+        .get_databases()
+        .iter()
+        .enumerate()
+        .map(|(i, database)| {
+            (
+                format!("$.databases[{}].database_password", i),
+                &database.database_password,
+            )
+        })
+        .collect();
+    for (i, channel) in config.get_alerting().channels.iter().enumerate() {
+        if let ChannelKind::Email(email) = &channel.kind {
+            if let Some(password) = &email.password {
+                passwords.push((format!("$.alerting.channels[{}].password", i), password));
+            }
+        }
+    }
+    if let Some(scratch) = config.get_scratch() {
+        passwords.push(("$.scratch.password".to_string(), &scratch.password));
+    }
+
+    passwords
+        .into_iter()
+        .filter(|(_, password)| password.is_plain())
+        .map(|(path, _)| ValidationIssue {
+            path,
+            line: None,
+            message: "password is stored in plaintext, use a reference such as `env:NAME` or `secret:NAME`".to_string(),
+        })
+        .collect()
+}
+
+fn validate_document(
+    document: Value,
+    lines: &HashMap<String, usize>,
+) -> Result<Config, ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    let config: Config = match serde_path_to_error::deserialize(document) {
+        Ok(config) => config,
+        Err(e) => {
+            let path = json_path(&e.path().to_string());
+            report.push(lines, path, e.into_inner().to_string());
+            return Err(report);
+        }
+    };
+
+    check_document(&config, lines, &mut report);
+
+    if report.is_valid() {
+        Ok(config)
+    } else {
+        Err(report)
+    }
+}
+
+fn check_document(config: &Config, lines: &HashMap<String, usize>, report: &mut ValidationReport) {
+    if config.get_version() != CONFIG_VERSION {
+        report.push(
+            lines,
+            "$.version".to_string(),
+            format!(
+                "config version {} is not the supported version {}, restart pbus_core to migrate it",
+                config.get_version(),
+                CONFIG_VERSION
+            ),
+        );
+    }
+
+    let base_path = config.get_base_path();
+    if !fs::metadata(base_path)
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false)
+    {
+        report.push(
+            lines,
+            "$.base_path".to_string(),
+            format!("directory `{}` does not exist", base_path),
+        );
+    }
+
+    let level = &config.get_logging().level;
+    if let Err(e) = tracing_subscriber::EnvFilter::try_new(level) {
+        report.push(
+            lines,
+            "$.logging.level".to_string(),
+            format!("invalid log level `{}`: {}", level, e),
+        );
+    }
+
+    check_alerting(config.get_alerting(), lines, report);
+    if let Some(scratch) = config.get_scratch() {
+        check_scratch(scratch, lines, report);
+    }
+
+    let mut database_names = HashSet::new();
+    for (i, database) in config.get_databases().iter().enumerate() {
+        let path = format!("$.databases[{}]", i);
+
+        if !database_names.insert(&database.database_name) {
+            report.push(
+                lines,
+                format!("{}.database_name", path),
+                format!("duplicate database `{}`", database.database_name),
+            );
+        }
+        for (field, value) in [
+            ("database_host", &database.database_host),
+            ("database_user", &database.database_user),
+            ("database_name", &database.database_name),
+        ] {
+            if value.trim().is_empty() {
+                report.push(
+                    lines,
+                    format!("{}.{}", path, field),
+                    "must not be empty".to_string(),
+                );
+            }
+        }
+        if database.server_port == 0 {
+            report.push(
+                lines,
+                format!("{}.server_port", path),
+                "port must be between 1 and 65535".to_string(),
+            );
+        }
+        if database.update_interval == 0 {
+            report.push(
+                lines,
+                format!("{}.update_interval", path),
+                "interval must be at least one second".to_string(),
+            );
+        }
+        check_rpo(
+            lines,
+            report,
+            format!("{}.rpo", path),
+            database.rpo,
+            database.update_interval,
+        );
+        if database.scrub_interval == Some(0) {
+            report.push(
+                lines,
+                format!("{}.scrub_interval", path),
+                "interval must be at least one second".to_string(),
+            );
+        }
+        if database.retention == Some(0) {
+            report.push(
+                lines,
+                format!("{}.retention", path),
+                "retention must be at least one second".to_string(),
+            );
+        }
+        if let Some(rules) = &database.alerts {
+            for (j, name) in rules.channels.iter().enumerate() {
+                if config.get_alerting().get_channel(name).is_none() {
+                    report.push(
+                        lines,
+                        format!("{}.alerts.channels[{}]", path, j),
+                        format!("unknown alert channel `{}`", name),
+                    );
+                }
+            }
+            if rules.disk_usage > 100 {
+                report.push(
+                    lines,
+                    format!("{}.alerts.disk_usage", path),
+                    "disk usage is a percentage and can't exceed 100".to_string(),
+                );
+            }
+        }
+        check_retry(lines, report, &path, database);
+
+        let mut target_names = HashSet::new();
+        for (j, target) in database.get_targets().iter().enumerate() {
+            let target_path = format!("{}.targets[{}]", path, j);
+
+            if !target_names.insert(target.get_name()) {
+                report.push(
+                    lines,
+                    format!("{}.name", target_path),
+                    format!("duplicate target `{}`", target.get_name()),
+                );
+            }
+            if !is_identifier(target.get_name()) {
+                report.push(
+                    lines,
+                    format!("{}.name", target_path),
+                    format!("`{}` is not a valid table name", target.get_name()),
+                );
+            }
+            check_rpo(
+                lines,
+                report,
+                format!("{}.rpo", target_path),
+                target.get_rpo(),
+                database.update_interval,
+            );
+
+            // An empty field list means the columns were never discovered
+            let fields = target.get_fields();
+            if fields.is_empty() {
+                continue;
+            }
+            match fields.get(CURSOR_COLUMN) {
+                None => report.push(
+                    lines,
+                    format!("{}.fields", target_path),
+                    format!("cursor column `{}` is missing", CURSOR_COLUMN),
+                ),
+                Some(data_type) if !is_cursor_type(data_type) => report.push(
+                    lines,
+                    format!("{}.fields.{}", target_path, CURSOR_COLUMN),
+                    format!(
+                        "cursor column `{}` has type `{}`, expected an integer type",
+                        CURSOR_COLUMN, data_type
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+fn check_alerting(
+    alerting: &AlertingConfig,
+    lines: &HashMap<String, usize>,
+    report: &mut ValidationReport,
+) {
+    if alerting.repeat_interval == 0 {
+        report.push(
+            lines,
+            "$.alerting.repeat_interval".to_string(),
+            "interval must be at least one second".to_string(),
+        );
+    }
+
+    let mut channel_names = HashSet::new();
+    for (i, channel) in alerting.channels.iter().enumerate() {
+        let path = format!("$.alerting.channels[{}]", i);
+
+        if !channel_names.insert(&channel.name) {
+            report.push(
+                lines,
+                format!("{}.name", path),
+                format!("duplicate alert channel `{}`", channel.name),
+            );
+        }
+        match &channel.kind {
+            ChannelKind::Webhook { url } | ChannelKind::Slack { url } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    report.push(
+                        lines,
+                        format!("{}.url", path),
+                        format!("`{}` is not an http(s) URL", url),
+                    );
+                }
+            }
+            ChannelKind::Email(email) => {
+                if email.smtp_host.trim().is_empty() {
+                    report.push(
+                        lines,
+                        format!("{}.smtp_host", path),
+                        "must not be empty".to_string(),
+                    );
+                }
+                if email.to.is_empty() {
+                    report.push(
+                        lines,
+                        format!("{}.to", path),
+                        "needs at least one recipient".to_string(),
+                    );
+                }
+                for (field, address) in std::iter::once(("from".to_string(), &email.from)).chain(
+                    email
+                        .to
+                        .iter()
+                        .enumerate()
+                        .map(|(j, address)| (format!("to[{}]", j), address)),
+                ) {
+                    if !address.contains('@') {
+                        report.push(
+                            lines,
+                            format!("{}.{}", path, field),
+                            format!("`{}` is not an email address", address),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_scratch(
+    scratch: &ScratchServer,
+    lines: &HashMap<String, usize>,
+    report: &mut ValidationReport,
+) {
+    for (field, value) in [
+        ("host", &scratch.host),
+        ("user", &scratch.user),
+        ("database", &scratch.database),
+    ] {
+        if value.trim().is_empty() {
+            report.push(
+                lines,
+                format!("$.scratch.{}", field),
+                "must not be empty".to_string(),
+            );
+        }
+    }
+    if scratch.port == 0 {
+        report.push(
+            lines,
+            "$.scratch.port".to_string(),
+            "port must be between 1 and 65535".to_string(),
+        );
+    }
+}
+
+fn check_retry(
+    lines: &HashMap<String, usize>,
+    report: &mut ValidationReport,
+    path: &str,
+    database: &Database,
+) {
+    let retry = &database.retry;
+    if retry.max_attempts == 0 {
+        report.push(
+            lines,
+            format!("{}.retry.max_attempts", path),
+            "needs at least one attempt".to_string(),
+        );
+    }
+    if retry.initial_backoff > retry.max_backoff {
+        report.push(
+            lines,
+            format!("{}.retry.initial_backoff", path),
+            "is longer than max_backoff".to_string(),
+        );
+    }
+    if retry.multiplier < 1.0 {
+        report.push(
+            lines,
+            format!("{}.retry.multiplier", path),
+            "must be at least 1".to_string(),
+        );
+    }
+    if !(0.0..=1.0).contains(&retry.jitter) {
+        report.push(
+            lines,
+            format!("{}.retry.jitter", path),
+            "must be between 0 and 1".to_string(),
+        );
+    }
+    if database.circuit_breaker.failure_threshold > 0
+        && database.circuit_breaker.probe_interval == 0
+    {
+        report.push(
+            lines,
+            format!("{}.circuit_breaker.probe_interval", path),
+            "interval must be at least one second".to_string(),
+        );
+    }
+}
+
+/// An RPO shorter than the update interval would be breached between every two runs
+fn check_rpo(
+    lines: &HashMap<String, usize>,
+    report: &mut ValidationReport,
+    path: String,
+    rpo: Option<u64>,
+    update_interval: u64,
+) {
+    match rpo {
+        Some(0) => report.push(lines, path, "rpo must be at least one second".to_string()),
+        Some(rpo) if rpo < update_interval => report.push(
+            lines,
+            path,
+            "rpo is shorter than the update interval and can never be met".to_string(),
+        ),
+        _ => {}
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn json_path(path: &str) -> String {
+    if path == "." || path.is_empty() {
+        "$".to_string()
+    } else {
+        format!("$.{}", path)
+    }
+}
+
+/// Maps the JSON path of every value in a document to the line it starts on
+///
+/// Only called on documents that already parsed, so it doesn't need to report
+/// syntax errors.
+fn locate_lines(json_str: &str) -> HashMap<String, usize> {
+    let mut locator = LineLocator {
+        chars: json_str.chars().collect(),
+        pos: 0,
+        line: 1,
+        lines: HashMap::new(),
+    };
+    locator.value("$".to_string());
+    locator.lines
+}
+
+struct LineLocator {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    lines: HashMap<String, usize>,
+}
+
+impl LineLocator {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn value(&mut self, path: String) {
+        self.skip_whitespace();
+        self.lines.insert(path.clone(), self.line);
+        match self.peek() {
+            Some('{') => self.object(path),
+            Some('[') => self.array(path),
+            Some('"') => {
+                self.string();
+            }
+            _ => {
+                while matches!(self.peek(), Some(c) if !",}] \t\r\n".contains(c)) {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    fn object(&mut self, path: String) {
+        self.bump();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('"') => {
+                    let key = self.string();
+                    self.skip_whitespace();
+                    self.bump(); // ':'
+                    self.value(format!("{}.{}", path, key));
+                }
+                Some(',') => {
+                    self.bump();
+                }
+                _ => {
+                    self.bump();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn array(&mut self, path: String) {
+        self.bump();
+        let mut index = 0;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(']') | None => {
+                    self.bump();
+                    return;
+                }
+                Some(',') => {
+                    self.bump();
+                }
+                _ => {
+                    self.value(format!("{}[{}]", path, index));
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        self.bump();
+        let mut contents = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '"' => break,
+                '\\' => {
+                    if let Some(escaped) = self.bump() {
+                        contents.push(escaped);
+                    }
+                }
+                _ => contents.push(c),
+            }
+        }
+        contents
+    }
+}
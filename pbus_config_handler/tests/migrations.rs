@@ -0,0 +1,189 @@
+use pbus_config_handler::migrations::{document_version, migrate_document};
+use pbus_config_handler::{
+    check_config, migrate_config, Config, ConfigStatus, SecretRef, CONFIG_VERSION,
+};
+use pbus_db_manager::{StateStore, TargetState};
+use serde_json::Value;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+const FIXTURES: [&str; 5] = [
+    include_str!("fixtures/config_v0.json"),
+    include_str!("fixtures/config_v1.json"),
+    include_str!("fixtures/config_v2.json"),
+    include_str!("fixtures/config_v3.json"),
+    include_str!("fixtures/config_v4.json"),
+];
+
+fn base_mount_point(dir: &TempDir) -> String {
+    format!("{}/", dir.path().display())
+}
+
+fn parse(fixture: &str) -> Value {
+    serde_json::from_str(fixture).unwrap()
+}
+
+#[test]
+fn detects_every_historical_version() {
+    for (version, fixture) in FIXTURES.iter().enumerate() {
+        assert_eq!(document_version(&parse(fixture)), version as u64);
+    }
+}
+
+#[test]
+fn every_version_migrates_to_the_current_format() {
+    for (version, fixture) in FIXTURES.iter().enumerate() {
+        let dir = TempDir::new().unwrap();
+        let state = StateStore::open(&base_mount_point(&dir)).unwrap();
+
+        let mut document = parse(fixture);
+        let from = migrate_document(&mut document, &state).unwrap();
+
+        assert_eq!(from, version as u64);
+        assert_eq!(document, parse(FIXTURES[CONFIG_VERSION as usize]));
+
+        let config: Config = serde_json::from_value(document).unwrap();
+        assert_eq!(config.get_version(), CONFIG_VERSION);
+        assert_eq!(config.get_databases()[0].get_targets().len(), 2);
+    }
+}
+
+#[test]
+fn v0_runtime_state_moves_to_state_store() {
+    let dir = TempDir::new().unwrap();
+    let state = StateStore::open(&base_mount_point(&dir)).unwrap();
+
+    let mut document = parse(FIXTURES[0]);
+    migrate_document(&mut document, &state).unwrap();
+
+    let orders = state.get_target_state("shop", "orders").unwrap();
+    assert_eq!(orders.last_id, 42);
+    assert_eq!(
+        orders.last_checked,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1700000600)
+    );
+    assert!(state.has_target_state("shop", "customers").unwrap());
+}
+
+#[test]
+fn migrate_config_rewrites_file_and_keeps_original() {
+    let dir = TempDir::new().unwrap();
+    let base = base_mount_point(&dir);
+    fs::write(format!("{}config.json", base), FIXTURES[0]).unwrap();
+
+    assert_eq!(migrate_config(&base).unwrap(), Some(0));
+    assert_eq!(
+        fs::read_to_string(format!("{}config.json.v0", base)).unwrap(),
+        FIXTURES[0]
+    );
+    assert_eq!(
+        Config::read_config(&base).unwrap().get_version(),
+        CONFIG_VERSION
+    );
+
+    // Already current, nothing left to do
+    assert_eq!(migrate_config(&base).unwrap(), None);
+}
+
+#[test]
+fn startup_moves_old_cursors_out_of_the_config_file() {
+    let dir = TempDir::new().unwrap();
+    let base = base_mount_point(&dir);
+    let mut document = parse(FIXTURES[0]);
+    document["base_path"] = Value::from(base.clone());
+    fs::write(format!("{}config.json", base), document.to_string()).unwrap();
+    // A target the state store already tracks keeps its newer cursor
+    let customers = TargetState {
+        last_id: 7,
+        last_updated: SystemTime::UNIX_EPOCH + Duration::from_secs(1700005000),
+        last_checked: SystemTime::UNIX_EPOCH + Duration::from_secs(1700005000),
+    };
+    StateStore::open(&base)
+        .unwrap()
+        .set_target_state("shop", "customers", &customers)
+        .unwrap();
+
+    assert!(matches!(
+        check_config(&base).unwrap(),
+        ConfigStatus::Existing
+    ));
+
+    let state = StateStore::open(&base).unwrap();
+    let orders = state.get_target_state("shop", "orders").unwrap();
+    assert_eq!(orders.last_id, 42);
+    assert_eq!(
+        orders.last_updated,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000)
+    );
+    assert_eq!(
+        orders.last_checked,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1700000600)
+    );
+    assert_eq!(
+        state.get_target_state("shop", "customers").unwrap(),
+        customers
+    );
+
+    let document: Value =
+        serde_json::from_str(&fs::read_to_string(format!("{}config.json", base)).unwrap()).unwrap();
+    assert!(document.get("update").is_none());
+    for target in document["databases"][0]["targets"].as_array().unwrap() {
+        for field in ["last_id", "last_updated", "last_checked"] {
+            assert!(target.get(field).is_none(), "{} was kept", field);
+        }
+    }
+
+    // Starting again leaves the state alone
+    state
+        .set_target_state(
+            "shop",
+            "orders",
+            &TargetState {
+                last_id: 50,
+                ..orders
+            },
+        )
+        .unwrap();
+    check_config(&base).unwrap();
+    assert_eq!(
+        state.get_target_state("shop", "orders").unwrap().last_id,
+        50
+    );
+}
+
+#[test]
+fn rejects_newer_versions() {
+    let dir = TempDir::new().unwrap();
+    let state = StateStore::open(&base_mount_point(&dir)).unwrap();
+
+    let mut document = parse(FIXTURES[CONFIG_VERSION as usize]);
+    document["version"] = Value::from(CONFIG_VERSION + 1);
+
+    assert!(migrate_document(&mut document, &state).is_err());
+}
+
+#[test]
+fn v3_plaintext_passwords_that_read_like_references_are_kept() {
+    let dir = TempDir::new().unwrap();
+    let state = StateStore::open(&base_mount_point(&dir)).unwrap();
+
+    for (password, migrated) in [
+        ("secret", "secret"),
+        ("env:PGPASSWORD", "plain:env:PGPASSWORD"),
+        ("pgpass", "plain:pgpass"),
+        ("plain:x", "plain:plain:x"),
+        ("secret:", "plain:secret:"),
+    ] {
+        let mut document = parse(FIXTURES[3]);
+        document["databases"][0]["database_password"] = Value::from(password);
+        migrate_document(&mut document, &state).unwrap();
+
+        assert_eq!(document["databases"][0]["database_password"], migrated);
+        let config: Config = serde_json::from_value(document).unwrap();
+        assert_eq!(
+            config.get_databases()[0].database_password,
+            SecretRef::Plain(password.to_string())
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use pbus_config_handler::overrides::{apply_env_overrides, interpolate_env};
+use serde_json::{json, Value};
+
+fn env(name: &str) -> Option<String> {
+    match name {
+        "PGHOST" => Some("db.internal".to_string()),
+        "PGPASSWORD" => Some(r#"p"a\ss}word"#.to_string()),
+        "INJECTED" => Some(r#"x", "base_path": "/tmp/evil"#.to_string()),
+        _ => None,
+    }
+}
+
+fn interpolated(mut document: Value) -> Value {
+    interpolate_env(&mut document, env).unwrap();
+    document
+}
+
+#[test]
+fn substitutes_variables_and_defaults() {
+    assert_eq!(
+        interpolated(json!({
+            "database_host": "${PGHOST}",
+            "database_name": "${PGDATABASE:-shop}",
+            "base_path": "/srv/${PGHOST}/pbus/"
+        })),
+        json!({
+            "database_host": "db.internal",
+            "database_name": "shop",
+            "base_path": "/srv/db.internal/pbus/"
+        })
+    );
+}
+
+#[test]
+fn keeps_quotes_and_backslashes_inside_the_value() {
+    let document = interpolated(json!({
+        "databases": [{ "database_password": "${PGPASSWORD}" }],
+        "base_path": "/srv/pbus/",
+        "note": "${INJECTED}"
+    }));
+
+    assert_eq!(
+        document["databases"][0]["database_password"],
+        json!(r#"p"a\ss}word"#)
+    );
+    assert_eq!(document["note"], json!(r#"x", "base_path": "/tmp/evil"#));
+    assert_eq!(document["base_path"], json!("/srv/pbus/"));
+}
+
+#[test]
+fn escapes_literal_references() {
+    assert_eq!(
+        interpolated(json!({ "url": "https://example.com/$${PGHOST}/${PGHOST}" })),
+        json!({ "url": "https://example.com/${PGHOST}/db.internal" })
+    );
+}
+
+#[test]
+fn leaves_keys_and_other_values_alone() {
+    let document = json!({
+        "${PGHOST}": 5432,
+        "enabled": true,
+        "port": null
+    });
+    assert_eq!(interpolated(document.clone()), document);
+}
+
+#[test]
+fn reports_every_missing_variable_once() {
+    let mut document = json!({
+        "database_user": "${PGUSER}",
+        "databases": [{ "database_name": "${PGDATABASE}", "database_user": "${PGUSER}" }]
+    });
+
+    let error = interpolate_env(&mut document, env).unwrap_err().to_string();
+    assert!(error.contains("PGUSER, PGDATABASE"), "{}", error);
+}
+
+#[test]
+fn rejects_unterminated_references() {
+    let mut document = json!({ "database_host": "${PGHOST" });
+    let error = interpolate_env(&mut document, env).unwrap_err().to_string();
+    assert!(error.contains("unterminated"), "{}", error);
+}
+
+#[test]
+fn overrides_values_by_path() {
+    let mut document = json!({
+        "databases": [{ "database_name": "shop", "server_port": 5432 }]
+    });
+    apply_env_overrides(
+        &mut document,
+        [
+            (
+                "PBUS_DATABASES__SHOP__SERVER_PORT".to_string(),
+                "6432".to_string(),
+            ),
+            ("HOME".to_string(), "/root".to_string()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(document["databases"][0]["server_port"], json!(6432));
+}
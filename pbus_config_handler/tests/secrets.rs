@@ -0,0 +1,150 @@
+use pbus_config_handler::secrets::PgPassEntry;
+use pbus_config_handler::{SecretRef, SecretsFile};
+use serde_json::Value;
+use std::fs;
+use tempfile::TempDir;
+
+const MASTER_KEY: &str = "correct horse battery staple";
+
+fn base_mount_point(dir: &TempDir) -> String {
+    format!("{}/", dir.path().display())
+}
+
+fn entry<'a>(host: &'a str, database: &'a str, user: &'a str) -> PgPassEntry<'a> {
+    PgPassEntry {
+        host,
+        port: 5432,
+        database,
+        user,
+    }
+}
+
+fn saved_secrets(dir: &TempDir) -> String {
+    let base = base_mount_point(dir);
+    let mut secrets = SecretsFile::unlock(&base, MASTER_KEY).unwrap();
+    secrets.set("shop".to_string(), "s3cr:t\"pass".to_string());
+    secrets.save(&base, MASTER_KEY).unwrap();
+    base
+}
+
+#[test]
+fn secrets_survive_a_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let base = saved_secrets(&dir);
+
+    let contents = fs::read_to_string(format!("{}secrets.enc", base)).unwrap();
+    assert!(!contents.contains("s3cr"));
+
+    let secrets = SecretsFile::unlock(&base, MASTER_KEY).unwrap();
+    assert_eq!(secrets.get("shop").unwrap(), "s3cr:t\"pass");
+    assert_eq!(secrets.names(), ["shop"]);
+}
+
+#[test]
+fn wrong_master_key_fails_to_unlock() {
+    let dir = TempDir::new().unwrap();
+    let base = saved_secrets(&dir);
+
+    assert!(SecretsFile::unlock(&base, "wrong key").is_err());
+}
+
+#[test]
+fn tampered_secrets_file_fails_to_unlock() {
+    let dir = TempDir::new().unwrap();
+    let base = saved_secrets(&dir);
+    let path = format!("{}secrets.enc", base);
+    let original: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+    let mut flipped = original.clone();
+    let ciphertext = original["ciphertext"].as_str().unwrap();
+    let last = if ciphertext.ends_with('0') { "1" } else { "0" };
+    flipped["ciphertext"] = format!("{}{}", &ciphertext[..ciphertext.len() - 1], last).into();
+    fs::write(&path, flipped.to_string()).unwrap();
+    assert!(SecretsFile::unlock(&base, MASTER_KEY).is_err());
+
+    // A truncated nonce is reported, not a panic
+    let mut truncated = original;
+    truncated["nonce"] = "0011".into();
+    fs::write(&path, truncated.to_string()).unwrap();
+    let error = SecretsFile::unlock(&base, MASTER_KEY)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(error.contains("nonce is 2 bytes"), "{}", error);
+}
+
+#[test]
+fn missing_secrets_file_unlocks_empty() {
+    let dir = TempDir::new().unwrap();
+    let secrets = SecretsFile::unlock(&base_mount_point(&dir), MASTER_KEY).unwrap();
+    assert!(secrets.names().is_empty());
+}
+
+#[test]
+fn pgpass_picks_the_first_matching_line() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("pgpass");
+    fs::write(
+        &path,
+        "# comment\n\
+         \n\
+         db.internal:5432:other:postgres:not-this\n\
+         db.internal:5432:shop:postgres:pa\\:ss\\\\word\n\
+         *:*:*:*:fallback\n\
+         broken:line\n",
+    )
+    .unwrap();
+    let pgpass = SecretRef::PgPass(Some(path.display().to_string()));
+    let base = base_mount_point(&dir);
+
+    assert_eq!(
+        pgpass
+            .resolve(&base, &entry("db.internal", "shop", "postgres"))
+            .unwrap(),
+        "pa:ss\\word"
+    );
+    assert_eq!(
+        pgpass
+            .resolve(&base, &entry("elsewhere", "shop", "postgres"))
+            .unwrap(),
+        "fallback"
+    );
+    assert!(pgpass.resolve_secret(&base).is_err());
+}
+
+#[test]
+fn pgpass_without_a_match_is_an_error() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("pgpass");
+    fs::write(&path, "db.internal:5432:shop:postgres:secret\n").unwrap();
+    let pgpass = SecretRef::PgPass(Some(path.display().to_string()));
+
+    assert!(pgpass
+        .resolve(
+            &base_mount_point(&dir),
+            &entry("db.internal", "shop", "admin")
+        )
+        .is_err());
+}
+
+#[test]
+fn references_round_trip_through_the_config_form() {
+    for reference in [
+        "secret",
+        "plain:env:PGPASSWORD",
+        "plain:pgpass",
+        "env:PGPASSWORD",
+        "file:/run/secrets/db",
+        "pgpass",
+        "pgpass:/etc/pgpass",
+        "secret:shop",
+    ] {
+        let parsed: SecretRef = reference.parse().unwrap();
+        assert_eq!(parsed.to_reference(), reference);
+    }
+    assert_eq!(
+        "plain:secret".parse::<SecretRef>().unwrap().to_reference(),
+        "secret"
+    );
+    assert!("env:".parse::<SecretRef>().is_err());
+}
@@ -0,0 +1,460 @@
+use pbus_config_handler::validation::{config_warnings, validate_config_str};
+use pbus_config_handler::{ValidationIssue, CONFIG_VERSION};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+/// A config without problems, storing its data in `dir`
+fn valid_document(dir: &TempDir) -> Value {
+    json!({
+        "version": CONFIG_VERSION,
+        "databases": [
+            {
+                "database_host": "localhost",
+                "server_port": 5432,
+                "database_user": "postgres",
+                "database_name": "shop",
+                "database_password": "env:PGPASSWORD",
+                "targets": [
+                    {
+                        "name": "orders",
+                        "fields": { "id": "integer", "total": "numeric" },
+                        "enabled": true
+                    }
+                ],
+                "update_interval": "1m",
+                "last_updated": "2023-11-14T22:13:20Z"
+            }
+        ],
+        "base_path": dir.path().display().to_string(),
+        "alerting": {
+            "channels": [
+                { "name": "ops", "type": "webhook", "url": "https://example.com/hook" }
+            ]
+        }
+    })
+}
+
+fn issues(document: &Value) -> Vec<ValidationIssue> {
+    match validate_config_str(&serde_json::to_string_pretty(document).unwrap()) {
+        Ok(_) => Vec::new(),
+        Err(report) => report.issues,
+    }
+}
+
+/// Validates the valid document after `edit` and expects exactly one issue at `path`
+fn assert_rejected(edit: impl FnOnce(&mut Value), path: &str, message: &str) {
+    let dir = TempDir::new().unwrap();
+    let mut document = valid_document(&dir);
+    edit(&mut document);
+
+    let issues = issues(&document);
+    assert_eq!(issues.len(), 1, "expected one issue, got {:?}", issues);
+    assert_eq!(issues[0].path, path);
+    assert!(
+        issues[0].message.contains(message),
+        "`{}` doesn't mention `{}`",
+        issues[0].message,
+        message
+    );
+}
+
+#[test]
+fn accepts_a_valid_config() {
+    let dir = TempDir::new().unwrap();
+    assert_eq!(issues(&valid_document(&dir)), Vec::new());
+}
+
+#[test]
+fn reports_every_issue_at_once() {
+    let dir = TempDir::new().unwrap();
+    let mut document = valid_document(&dir);
+    document["databases"][0]["server_port"] = json!(0);
+    document["databases"][0]["update_interval"] = json!(0);
+    document["databases"][0]["targets"][0]["name"] = json!("1orders");
+
+    let paths: Vec<String> = issues(&document)
+        .into_iter()
+        .map(|issue| issue.path)
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "$.databases[0].server_port",
+            "$.databases[0].update_interval",
+            "$.databases[0].targets[0].name",
+        ]
+    );
+}
+
+#[test]
+fn locates_nested_paths() {
+    let contents = format!(
+        r#"{{
+  "version": {},
+  "databases": [
+    {{
+      "database_host": "localhost",
+      "server_port": 5432,
+      "database_user": "postgres",
+      "database_name": "shop",
+      "database_password": "env:PGPASSWORD",
+      "targets": [
+        {{ "name": "customers", "fields": {{}}, "enabled": true }},
+        {{
+          "name": "orders",
+          "fields": {{
+            "id": "text"
+          }},
+          "enabled": true,
+          "rpo": 0
+        }}
+      ],
+      "update_interval": "1m",
+      "last_updated": "2023-11-14T22:13:20Z"
+    }}
+  ],
+  "base_path": "/nonexistent/pbus",
+  "logging": {{ "level": "info" }},
+  "alerting": {{ "channels": [{{ "name": "ops", "type": "webhook", "url": "ftp://x" }}] }}
+}}"#,
+        CONFIG_VERSION
+    );
+
+    let report = validate_config_str(&contents).unwrap_err();
+    let lines: Vec<(&str, Option<usize>)> = report
+        .issues
+        .iter()
+        .map(|issue| (issue.path.as_str(), issue.line))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            ("$.base_path", Some(25)),
+            ("$.alerting.channels[0].url", Some(27)),
+            ("$.databases[0].targets[1].rpo", Some(18)),
+            ("$.databases[0].targets[1].fields.id", Some(15)),
+        ]
+    );
+}
+
+#[test]
+fn reports_the_line_of_syntax_errors() {
+    let report = validate_config_str("{\n  \"version\": 1,\n  \"databases\": [\n}").unwrap_err();
+    assert_eq!(report.issues.len(), 1);
+    assert_eq!(report.issues[0].path, "$");
+    assert_eq!(report.issues[0].line, Some(4));
+}
+
+#[test]
+fn reports_the_path_of_type_errors() {
+    assert_rejected(
+        |document| document["databases"][0]["server_port"] = json!("postgres"),
+        "$.databases[0].server_port",
+        "invalid type",
+    );
+}
+
+#[test]
+fn rejects_other_versions() {
+    assert_rejected(
+        |document| document["version"] = json!(CONFIG_VERSION + 1),
+        "$.version",
+        "restart pbus_core to migrate it",
+    );
+}
+
+#[test]
+fn rejects_missing_base_path() {
+    assert_rejected(
+        |document| document["base_path"] = json!("/nonexistent/pbus"),
+        "$.base_path",
+        "does not exist",
+    );
+}
+
+#[test]
+fn rejects_invalid_log_level() {
+    assert_rejected(
+        |document| document["logging"] = json!({ "level": "info,=[" }),
+        "$.logging.level",
+        "invalid log level",
+    );
+}
+
+#[test]
+fn rejects_zero_repeat_interval() {
+    assert_rejected(
+        |document| document["alerting"]["repeat_interval"] = json!(0),
+        "$.alerting.repeat_interval",
+        "at least one second",
+    );
+}
+
+#[test]
+fn rejects_duplicate_channels() {
+    assert_rejected(
+        |document| {
+            let channel = document["alerting"]["channels"][0].clone();
+            document["alerting"]["channels"]
+                .as_array_mut()
+                .unwrap()
+                .push(channel);
+        },
+        "$.alerting.channels[1].name",
+        "duplicate alert channel `ops`",
+    );
+}
+
+#[test]
+fn rejects_non_http_webhooks() {
+    assert_rejected(
+        |document| document["alerting"]["channels"][0]["url"] = json!("example.com/hook"),
+        "$.alerting.channels[0].url",
+        "is not an http(s) URL",
+    );
+}
+
+#[test]
+fn rejects_incomplete_email_channels() {
+    let email = |smtp_host: &str, from: &str, to: Value| {
+        json!({
+            "name": "ops",
+            "type": "email",
+            "smtp_host": smtp_host,
+            "from": from,
+            "to": to
+        })
+    };
+    assert_rejected(
+        |document| {
+            document["alerting"]["channels"][0] =
+                email(" ", "pbus@example.com", json!(["ops@example.com"]))
+        },
+        "$.alerting.channels[0].smtp_host",
+        "must not be empty",
+    );
+    assert_rejected(
+        |document| {
+            document["alerting"]["channels"][0] = email("smtp", "pbus@example.com", json!([]))
+        },
+        "$.alerting.channels[0].to",
+        "at least one recipient",
+    );
+    assert_rejected(
+        |document| {
+            document["alerting"]["channels"][0] = email("smtp", "pbus", json!(["ops@example.com"]))
+        },
+        "$.alerting.channels[0].from",
+        "not an email address",
+    );
+    assert_rejected(
+        |document| {
+            document["alerting"]["channels"][0] = email("smtp", "pbus@example.com", json!(["ops"]))
+        },
+        "$.alerting.channels[0].to[0]",
+        "not an email address",
+    );
+}
+
+#[test]
+fn rejects_incomplete_scratch_server() {
+    let scratch = json!({
+        "host": "scratch",
+        "port": 5432,
+        "user": "pbus",
+        "database": "drills",
+        "password": "env:SCRATCH_PASSWORD"
+    });
+    for field in ["host", "user", "database"] {
+        let mut scratch = scratch.clone();
+        scratch[field] = json!("");
+        assert_rejected(
+            |document| document["scratch"] = scratch,
+            &format!("$.scratch.{}", field),
+            "must not be empty",
+        );
+    }
+    assert_rejected(
+        |document| {
+            document["scratch"] = scratch;
+            document["scratch"]["port"] = json!(0);
+        },
+        "$.scratch.port",
+        "between 1 and 65535",
+    );
+}
+
+#[test]
+fn rejects_duplicate_databases() {
+    assert_rejected(
+        |document| {
+            let mut database = document["databases"][0].clone();
+            database["targets"] = json!([]);
+            document["databases"].as_array_mut().unwrap().push(database);
+        },
+        "$.databases[1].database_name",
+        "duplicate database `shop`",
+    );
+}
+
+#[test]
+fn rejects_empty_connection_fields() {
+    for field in ["database_host", "database_user", "database_name"] {
+        assert_rejected(
+            |document| document["databases"][0][field] = json!("  "),
+            &format!("$.databases[0].{}", field),
+            "must not be empty",
+        );
+    }
+}
+
+#[test]
+fn rejects_zero_port() {
+    assert_rejected(
+        |document| document["databases"][0]["server_port"] = json!(0),
+        "$.databases[0].server_port",
+        "between 1 and 65535",
+    );
+}
+
+#[test]
+fn rejects_zero_intervals() {
+    assert_rejected(
+        |document| document["databases"][0]["update_interval"] = json!(0),
+        "$.databases[0].update_interval",
+        "at least one second",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["scrub_interval"] = json!(0),
+        "$.databases[0].scrub_interval",
+        "at least one second",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["retention"] = json!(0),
+        "$.databases[0].retention",
+        "at least one second",
+    );
+}
+
+#[test]
+fn rejects_rpo_that_can_never_be_met() {
+    assert_rejected(
+        |document| document["databases"][0]["rpo"] = json!(0),
+        "$.databases[0].rpo",
+        "at least one second",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["rpo"] = json!("30s"),
+        "$.databases[0].rpo",
+        "shorter than the update interval",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["targets"][0]["rpo"] = json!("30s"),
+        "$.databases[0].targets[0].rpo",
+        "shorter than the update interval",
+    );
+}
+
+#[test]
+fn rejects_invalid_alert_rules() {
+    assert_rejected(
+        |document| document["databases"][0]["alerts"] = json!({ "channels": ["pager"] }),
+        "$.databases[0].alerts.channels[0]",
+        "unknown alert channel `pager`",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["alerts"] = json!({ "disk_usage": 101 }),
+        "$.databases[0].alerts.disk_usage",
+        "can't exceed 100",
+    );
+}
+
+#[test]
+fn rejects_invalid_retry_policy() {
+    assert_rejected(
+        |document| document["databases"][0]["retry"] = json!({ "max_attempts": 0 }),
+        "$.databases[0].retry.max_attempts",
+        "at least one attempt",
+    );
+    assert_rejected(
+        |document| {
+            document["databases"][0]["retry"] =
+                json!({ "initial_backoff": "2m", "max_backoff": "1m" })
+        },
+        "$.databases[0].retry.initial_backoff",
+        "longer than max_backoff",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["retry"] = json!({ "multiplier": 0.5 }),
+        "$.databases[0].retry.multiplier",
+        "at least 1",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["retry"] = json!({ "jitter": 1.5 }),
+        "$.databases[0].retry.jitter",
+        "between 0 and 1",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["circuit_breaker"] = json!({ "probe_interval": 0 }),
+        "$.databases[0].circuit_breaker.probe_interval",
+        "at least one second",
+    );
+}
+
+#[test]
+fn rejects_invalid_targets() {
+    assert_rejected(
+        |document| {
+            let target = document["databases"][0]["targets"][0].clone();
+            document["databases"][0]["targets"]
+                .as_array_mut()
+                .unwrap()
+                .push(target);
+        },
+        "$.databases[0].targets[1].name",
+        "duplicate target `orders`",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["targets"][0]["name"] = json!("orders; DROP TABLE x"),
+        "$.databases[0].targets[0].name",
+        "not a valid table name",
+    );
+}
+
+#[test]
+fn rejects_unusable_cursor_columns() {
+    assert_rejected(
+        |document| document["databases"][0]["targets"][0]["fields"] = json!({ "total": "numeric" }),
+        "$.databases[0].targets[0].fields",
+        "cursor column `id` is missing",
+    );
+    assert_rejected(
+        |document| document["databases"][0]["targets"][0]["fields"]["id"] = json!("uuid"),
+        "$.databases[0].targets[0].fields.id",
+        "expected an integer type",
+    );
+    // Fields that were never discovered aren't checked
+    assert_rejected(
+        |document| {
+            document["databases"][0]["targets"][0]["fields"] = json!({});
+            document["databases"][0]["server_port"] = json!(0);
+        },
+        "$.databases[0].server_port",
+        "between 1 and 65535",
+    );
+}
+
+#[test]
+fn warns_about_plaintext_passwords() {
+    let dir = TempDir::new().unwrap();
+    let mut document = valid_document(&dir);
+    let config = validate_config_str(&document.to_string()).unwrap();
+    assert_eq!(config_warnings(&config), Vec::new());
+
+    document["databases"][0]["database_password"] = json!("hunter2");
+    let config = validate_config_str(&document.to_string()).unwrap();
+    let warnings = config_warnings(&config);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].path, "$.databases[0].database_password");
+    assert!(warnings[0].message.contains("plaintext"));
+}
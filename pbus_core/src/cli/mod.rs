@@ -0,0 +1,215 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
+
+/// Incremental backups of PostgreSQL tables
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// Directory holding the config, catalog, state and segment files
+    #[arg(long, env = "PBUS_DATA_DIR", default_value = "../data/", global = true)]
+    pub data_dir: String,
+
+    /// Config file to use instead of the one in the data directory
+    #[arg(long, env = "PBUS_CONFIG_FILE", global = true)]
+    pub config: Option<String>,
+
+    /// Serve Prometheus metrics on this address while running, e.g. `0.0.0.0:9187`
+    #[arg(long, env = "PBUS_METRICS_ADDR", global = true)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The data directory as a base mount point, which always ends in a slash
+    pub fn base_mount_point(&self) -> String {
+        if self.data_dir.ends_with('/') {
+            self.data_dir.clone()
+        } else {
+            format!("{}/", self.data_dir)
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the scheduler until stopped (the default)
+    Run,
+    /// Back up targets once, right now
+    BackupNow(TargetFilter),
+    /// Write a target's backed up rows to a file, stdout or a database
+    Restore(RestoreArgs),
+    /// Scrub segment files against their checksums and the source
+    Verify(TargetFilter),
+    /// Restore targets into a scratch schema and compare them against the source
+    Drill(TargetFilter),
+    /// Show how far each backup trails its source and flag RPO breaches
+    Freshness(TargetFilter),
+    /// Show targets, runs, segments or per-target statistics
+    List(ListArgs),
+    /// Connect to a new database, pick its tables and add it to the config
+    AddDatabase(AddDatabaseArgs),
+    /// Show firing alerts or test the alert channels
+    #[command(subcommand)]
+    Alerts(AlertsCommand),
+    /// Inspect and maintain the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Control the running scheduler through its socket
+    #[command(subcommand)]
+    Daemon(DaemonCommand),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TargetFilter {
+    /// Only this database
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Only this target
+    #[arg(long)]
+    pub target: Option<String>,
+}
+
+impl TargetFilter {
+    pub fn matches(&self, database_name: &str, target_name: &str) -> bool {
+        self.database
+            .as_deref()
+            .is_none_or(|name| name == database_name)
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|name| name == target_name)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    #[arg(long)]
+    pub database: String,
+    #[arg(long)]
+    pub target: String,
+    /// Only restore rows with a cursor up to and including this value
+    #[arg(long)]
+    pub to_cursor: Option<i64>,
+    /// Write JSON lines to this file instead of stdout
+    #[arg(long, conflicts_with = "into")]
+    pub output: Option<String>,
+    /// Insert the rows into this configured database, skipping existing rows
+    #[arg(long)]
+    pub into: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct AddDatabaseArgs {
+    #[arg(long, default_value = "localhost")]
+    pub host: String,
+    #[arg(long, default_value_t = 5432)]
+    pub port: u16,
+    #[arg(long)]
+    pub user: String,
+    /// Name of the database on the server, also its name in the config
+    #[arg(long)]
+    pub name: String,
+    /// Password or a reference such as `env:PGPASSWORD`, prompted for if left out
+    #[arg(long)]
+    pub password: Option<String>,
+    /// How often the targets are backed up, e.g. `15m`
+    #[arg(long, default_value = "15m", value_parser = humantime::parse_duration)]
+    pub interval: std::time::Duration,
+    /// Tables to back up, comma separated; asked for if left out
+    #[arg(long, value_delimiter = ',', conflicts_with = "all")]
+    pub targets: Vec<String>,
+    /// Back up every table that has a usable cursor column
+    #[arg(long)]
+    pub all: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    #[arg(value_enum, default_value_t = ListKind::Targets)]
+    pub kind: ListKind,
+    #[command(flatten)]
+    pub filter: TargetFilter,
+    /// Most runs to show per target
+    #[arg(long, default_value_t = 10)]
+    pub limit: u32,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ListKind {
+    Targets,
+    Runs,
+    Segments,
+    /// Totals and lag per target
+    Stats,
+    /// Circuit breaker state per database
+    Databases,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Check the config file and report every problem found
+    Validate,
+    /// Print the config the program runs with, passwords redacted
+    Show,
+    /// Print the path of the config file
+    Path,
+    /// Upgrade the config file to the current format
+    Migrate,
+    /// Manage the encrypted secrets file
+    #[command(subcommand)]
+    Secret(SecretCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SecretCommand {
+    /// Store a secret, read from the first line of stdin
+    Set { name: String },
+    /// Delete a secret
+    Remove { name: String },
+    /// List the names of stored secrets
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AlertsCommand {
+    /// List the alerts that are firing
+    List,
+    /// Send a test alert through the configured channels
+    Test {
+        /// Only this channel
+        #[arg(long)]
+        channel: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DaemonCommand {
+    /// Show when each target runs next, and which are paused or suspended
+    Status,
+    /// Re-read the config file now
+    Reload,
+    /// Stop scheduling a target, or every target of a database, until resumed
+    Pause(PauseArgs),
+    /// Schedule paused targets again
+    Resume(PauseArgs),
+    /// Have the scheduler back a target up right now
+    RunNow {
+        #[arg(long)]
+        database: String,
+        #[arg(long)]
+        target: String,
+    },
+    /// Stop the scheduler
+    Shutdown,
+}
+
+#[derive(Args, Debug)]
+pub struct PauseArgs {
+    #[arg(long)]
+    pub database: String,
+    /// Only this target instead of the whole database
+    #[arg(long)]
+    pub target: Option<String>,
+}
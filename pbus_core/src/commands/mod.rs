@@ -0,0 +1,880 @@
+use pbus_config_handler::config_file::config_path;
+use pbus_config_handler::secrets::master_key;
+use pbus_config_handler::*;
+use pbus_db_manager::{Catalog, EventLog, RunStats, RunStatus, StateStore};
+use pbus_timer::alerting::Alerter;
+use pbus_timer::control::{self, Request, Response};
+use pbus_timer::drill::run_drill;
+use pbus_timer::events::Event;
+use pbus_timer::freshness::check_freshness;
+use pbus_timer::onboarding::{self, TableCandidate};
+use pbus_timer::restore::{replay_segments, restore_segments, segment_rows, Replay};
+use pbus_timer::scrub::scrub_target;
+use pbus_timer::{backup_target, worker_manager};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::{
+    AddDatabaseArgs, AlertsCommand, ConfigCommand, DaemonCommand, ListArgs, ListKind, RestoreArgs,
+    SecretCommand, TargetFilter,
+};
+use crate::metrics;
+use crate::{EXIT_CONFIG, EXIT_FAILURE, EXIT_STALE, EXIT_VERIFY};
+
+/// How often the event log is checked for the progress of a run
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most events read from the log at once
+const PROGRESS_LIMIT: u32 = 500;
+
+/// Reads the config, creating or migrating it first if needed
+///
+/// Problems with the config are reported here and turned into `EXIT_CONFIG`.
+pub fn load_config(base_mount_point: &str) -> Result<Config, ExitCode> {
+    let config = check_config(base_mount_point).and_then(|_| Config::read_config(base_mount_point));
+    config.map_err(|e| {
+        eprintln!("{}", e);
+        ExitCode::from(EXIT_CONFIG)
+    })
+}
+
+pub async fn run(
+    base_mount_point: &str,
+    metrics_addr: Option<SocketAddr>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    if let Err(code) = load_config(base_mount_point) {
+        return Ok(code);
+    }
+    if let Some(addr) = metrics_addr {
+        metrics::spawn_server(addr).await?;
+    }
+    worker_manager(base_mount_point).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Backs up every enabled target matching `filter`, or the named target even if
+/// it is disabled
+///
+/// The runs go through the worker if one is running, so the two never capture
+/// the same target at once, and run in this process otherwise. On a terminal the
+/// rows captured so far are shown while a run is going.
+pub async fn backup_now(
+    base_mount_point: &str,
+    config: &Config,
+    filter: &TargetFilter,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+    let state = StateStore::open(base_mount_point)?;
+    if std::io::stderr().is_terminal() {
+        let log = EventLog::open(base_mount_point)?;
+        let (after, filter) = (log.last_id()?, filter.clone());
+        std::thread::spawn(move || show_progress(&log, after, &filter));
+    }
+
+    let mut matched = 0;
+    let mut failed = 0;
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            if !filter.matches(&database.database_name, target.get_name()) {
+                continue;
+            }
+            if !target.get_enabled() && filter.target.is_none() {
+                continue;
+            }
+            matched += 1;
+
+            match run_now(
+                base_mount_point,
+                &catalog,
+                &state,
+                database,
+                target.get_name(),
+            )
+            .await
+            {
+                Ok(stats) => println!(
+                    "{}.{}: captured {} rows, backed up to id {}",
+                    database.database_name,
+                    target.get_name(),
+                    stats.rows_captured,
+                    stats.cursor_after
+                ),
+                Err(e) => {
+                    failed += 1;
+                    eprintln!(
+                        "{}.{}: backup failed: {}",
+                        database.database_name,
+                        target.get_name(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    if matched == 0 {
+        return Err("No target matches".into());
+    }
+    Ok(if failed > 0 {
+        ExitCode::from(EXIT_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Has the worker back up a target, or backs it up here if no worker listens
+async fn run_now(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+) -> Result<RunStats, Box<dyn Error>> {
+    let request = Request::RunNow {
+        database: database.database_name.clone(),
+        target: target_name.to_string(),
+    };
+    match control::send(base_mount_point, &request).await? {
+        Some(Response::Started { run_id }) => finished_run(catalog, run_id).await,
+        Some(response) => Err(format!("the worker answered a run with {:?}", response).into()),
+        None => Ok(backup_target(base_mount_point, catalog, state, database, target_name).await?),
+    }
+}
+
+/// Waits for a run the worker started, a failed run is an error
+async fn finished_run(catalog: &Catalog, run_id: i64) -> Result<RunStats, Box<dyn Error>> {
+    let run = control::wait_for_run(catalog, run_id).await?;
+    match run.status {
+        RunStatus::Succeeded => Ok(run.stats()),
+        _ => Err(run
+            .error
+            .unwrap_or_else(|| "the run failed".to_string())
+            .into()),
+    }
+}
+
+/// Prints the running total of every batch written for the targets of `filter`
+/// after the event `after`
+///
+/// Follows the event log, which has the batches of runs in the worker as well
+/// as of runs in this process.
+fn show_progress(log: &EventLog, mut after: i64, filter: &TargetFilter) {
+    let mut rows_so_far = 0;
+    loop {
+        std::thread::sleep(PROGRESS_INTERVAL);
+        let Ok(logged) = log.get_events_after(after, PROGRESS_LIMIT) else {
+            continue;
+        };
+        for event in logged {
+            after = event.id;
+            if !filter.matches(&event.database_name, &event.target_name) {
+                continue;
+            }
+            match serde_json::from_str(&event.payload) {
+                Ok(Event::RunStarted { .. }) => rows_so_far = 0,
+                Ok(Event::BatchWritten {
+                    database,
+                    target,
+                    rows,
+                    cursor,
+                    ..
+                }) => {
+                    rows_so_far += rows;
+                    eprintln!(
+                        "{}.{}: {} rows so far, at id {}",
+                        database, target, rows_so_far, cursor
+                    );
+                }
+                Ok(_) | Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Replays a target's active segments in cursor order
+///
+/// Every segment is checked against its checksum first, a corrupt segment stops
+/// the restore before anything is written.
+pub async fn restore(
+    base_mount_point: &str,
+    config: &Config,
+    args: &RestoreArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+
+    let segments = match replay_segments(
+        base_mount_point,
+        &catalog,
+        &args.database,
+        &args.target,
+        args.to_cursor,
+    )? {
+        Replay::Segments { segments, gaps } => {
+            for (from, to) in gaps {
+                eprintln!("Warning: no segment covers cursor {} to {}", from, to);
+            }
+            segments
+        }
+        Replay::Corrupt(segment) => {
+            eprintln!("Segment {} is corrupt, run verify", segment.path);
+            return Ok(ExitCode::from(EXIT_VERIFY));
+        }
+    };
+
+    match (&args.into, &args.output) {
+        (Some(database_name), _) => {
+            let restored = restore_segments(
+                base_mount_point,
+                config,
+                database_name,
+                &args.database,
+                &args.target,
+                &segments,
+                args.to_cursor,
+            )
+            .await?;
+            eprintln!(
+                "Restored {} of {} rows into {}",
+                restored.inserted, restored.rows, database_name
+            );
+        }
+        (None, output) => {
+            let writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(std::io::stdout()),
+            };
+            let mut writer = BufWriter::new(writer);
+            let mut rows = 0;
+            for segment in &segments {
+                for row in segment_rows(base_mount_point, segment, args.to_cursor)? {
+                    writeln!(writer, "{}", serde_json::to_string(&row)?)?;
+                    rows += 1;
+                }
+            }
+            writer.flush()?;
+            eprintln!("Restored {} rows", rows);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Scrubs the matching targets and raises or resolves their verification
+/// alerts, see `scrub_target`
+///
+/// Every scrub is recorded as a run. Exits with `EXIT_VERIFY` if a scrub found
+/// problems and with `EXIT_FAILURE` if one couldn't finish.
+pub async fn verify(
+    base_mount_point: &str,
+    config: &Config,
+    filter: &TargetFilter,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+    let alerter = Alerter::new(base_mount_point, config)?;
+
+    let mut matched = 0;
+    let mut dirty = 0;
+    let mut failed = 0;
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            if !filter.matches(&database.database_name, target.get_name()) {
+                continue;
+            }
+            matched += 1;
+            let name = format!("{}.{}", database.database_name, target.get_name());
+            match scrub_target(base_mount_point, &catalog, database, target.get_name()).await {
+                Ok(report) => {
+                    let problems = report.problems();
+                    for problem in &problems {
+                        println!("{}: {}", name, problem);
+                    }
+                    if problems.is_empty() {
+                        println!("{}: {}", name, report);
+                    } else {
+                        dirty += 1;
+                    }
+                    alerter
+                        .check_verification(database, target.get_name(), &problems)
+                        .await;
+                }
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("{}: scrub failed: {}", name, e);
+                }
+            }
+        }
+    }
+
+    if matched == 0 {
+        return Err("No target matches".into());
+    }
+    Ok(if dirty > 0 {
+        ExitCode::from(EXIT_VERIFY)
+    } else if failed > 0 {
+        ExitCode::from(EXIT_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Runs a restore drill of every matching target, see `run_drill`
+///
+/// Exits with `EXIT_VERIFY` if a drill failed and with `EXIT_FAILURE` if one
+/// couldn't run.
+pub async fn drill(
+    base_mount_point: &str,
+    config: &Config,
+    filter: &TargetFilter,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+
+    let mut matched = 0;
+    let mut failed = 0;
+    let mut errors = 0;
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            if !filter.matches(&database.database_name, target.get_name()) {
+                continue;
+            }
+            matched += 1;
+            let name = format!("{}.{}", database.database_name, target.get_name());
+            match run_drill(
+                base_mount_point,
+                &catalog,
+                config,
+                &database.database_name,
+                target.get_name(),
+            )
+            .await
+            {
+                Ok(report) if report.passed() => println!(
+                    "{}: passed, restored {} rows from {} segments, {} sampled rows match the source",
+                    name, report.restored, report.segments, report.sampled
+                ),
+                Ok(report) => {
+                    failed += 1;
+                    println!("{}: failed, {}", name, report.problems().join("; "));
+                }
+                Err(e) => {
+                    errors += 1;
+                    eprintln!("{}: drill failed: {}", name, e);
+                }
+            }
+        }
+    }
+
+    if matched == 0 {
+        return Err("No target matches".into());
+    }
+    Ok(if failed > 0 {
+        ExitCode::from(EXIT_VERIFY)
+    } else if errors > 0 {
+        ExitCode::from(EXIT_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Reports the freshness of the matching targets, exiting with `EXIT_STALE` if
+/// any is past its RPO
+pub async fn freshness(
+    base_mount_point: &str,
+    config: &Config,
+    filter: &TargetFilter,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+    let state = StateStore::open(base_mount_point)?;
+
+    let mut breached = 0;
+    for database in config.get_databases() {
+        if !database
+            .get_targets()
+            .iter()
+            .any(|target| filter.matches(&database.database_name, target.get_name()))
+        {
+            continue;
+        }
+
+        for freshness in check_freshness(base_mount_point, &catalog, &state, database).await? {
+            if !filter.matches(&freshness.database_name, &freshness.target_name) {
+                continue;
+            }
+            if freshness.rpo_breached {
+                breached += 1;
+            }
+            println!(
+                "{}.{}\t{}\tage={}\trpo={}\tcursor={}/{}\tcursor_lag={}\twal_lag={}{}",
+                freshness.database_name,
+                freshness.target_name,
+                if freshness.rpo_breached {
+                    "STALE"
+                } else {
+                    "ok"
+                },
+                freshness
+                    .age
+                    .map(format_duration)
+                    .unwrap_or_else(|| "never".to_string()),
+                freshness
+                    .rpo
+                    .map(format_duration)
+                    .unwrap_or_else(|| "-".to_string()),
+                freshness.captured_cursor,
+                optional(freshness.source_cursor),
+                optional(freshness.cursor_lag),
+                optional(freshness.wal_lag_bytes),
+                freshness
+                    .source_error
+                    .map(|e| format!("\tsource error: {}", e))
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(if breached > 0 {
+        ExitCode::from(EXIT_STALE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+pub fn list(
+    base_mount_point: &str,
+    config: &Config,
+    args: &ListArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let catalog = Catalog::open(base_mount_point)?;
+    let state = StateStore::open(base_mount_point)?;
+
+    if let ListKind::Databases = args.kind {
+        for database in config.get_databases() {
+            let database_name = &database.database_name;
+            if args
+                .filter
+                .database
+                .as_deref()
+                .is_some_and(|name| name != database_name)
+            {
+                continue;
+            }
+            let circuit = state.get_circuit(database_name)?;
+            let status = match circuit.next_probe {
+                Some(next_probe) if circuit.is_open() => {
+                    format!("open\tnext_probe={}", format_time(next_probe))
+                }
+                _ => "closed".to_string(),
+            };
+            println!(
+                "{}\tcircuit={}\tfailures={}\t{}",
+                database_name,
+                status,
+                circuit.failures,
+                circuit.last_error.unwrap_or_default()
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            let (database_name, target_name) = (&database.database_name, target.get_name());
+            if !args.filter.matches(database_name, target_name) {
+                continue;
+            }
+
+            match args.kind {
+                ListKind::Targets => {
+                    let target_state = state.get_target_state(database_name, target_name)?;
+                    println!(
+                        "{}.{}\tenabled={}\tlast_id={}\tlast_updated={}\tlast_checked={}",
+                        database_name,
+                        target_name,
+                        target.get_enabled(),
+                        target_state.last_id,
+                        format_time(target_state.last_updated),
+                        format_time(target_state.last_checked)
+                    );
+                }
+                ListKind::Runs => {
+                    for run in catalog.get_runs(database_name, target_name, args.limit)? {
+                        println!(
+                            "{}.{}\t#{}\t{}\t{:?}\tstarted={}\ttook={}\tcursor={}->{}\trows={}\tbytes={}\tretries={}\t{}",
+                            database_name,
+                            target_name,
+                            run.id,
+                            run.kind.as_str(),
+                            run.status,
+                            format_time(run.started_at),
+                            run.duration()
+                                .map(|duration| humantime::format_duration(duration).to_string())
+                                .unwrap_or_else(|| "-".to_string()),
+                            run.cursor_before,
+                            run.cursor_after
+                                .map(|cursor| cursor.to_string())
+                                .unwrap_or_else(|| "?".to_string()),
+                            run.rows_captured,
+                            run.bytes_written,
+                            run.retries,
+                            run.error.unwrap_or_default()
+                        );
+                    }
+                }
+                ListKind::Stats => {
+                    let now = SystemTime::now();
+                    let stats = catalog.get_target_stats(
+                        database_name,
+                        target_name,
+                        SystemTime::UNIX_EPOCH,
+                    )?;
+                    println!(
+                        "{}.{}\truns={}\tsucceeded={}\tfailed={}\trows={}\tbytes={}\tavg_rows={:.1}\tavg_took={}\tlag={}",
+                        database_name,
+                        target_name,
+                        stats.runs,
+                        stats.succeeded,
+                        stats.failed,
+                        stats.rows_captured,
+                        stats.bytes_written,
+                        stats.avg_rows_per_run,
+                        humantime::format_duration(stats.avg_duration),
+                        stats
+                            .lag(now)
+                            .map(format_duration)
+                            .unwrap_or_else(|| "never backed up".to_string())
+                    );
+                }
+                ListKind::Databases => unreachable!("listed per database above"),
+                ListKind::Segments => {
+                    for segment in catalog.get_segments(database_name, target_name)? {
+                        println!(
+                            "{}\tcursor=({}, {}]\trows={}\tbytes={}",
+                            segment.path,
+                            segment.cursor_start,
+                            segment.cursor_end,
+                            segment.row_count,
+                            segment.size_bytes
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn config(base_mount_point: &str, command: &ConfigCommand) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        ConfigCommand::Validate => match validate_config(base_mount_point) {
+            Ok(config) => {
+                for warning in validation::config_warnings(&config) {
+                    eprintln!("warning: {}", warning);
+                }
+                println!("Config is valid");
+            }
+            Err(e) => {
+                eprint!("{}", e);
+                return Ok(ExitCode::from(EXIT_CONFIG));
+            }
+        },
+        ConfigCommand::Show => {
+            let config = match load_config(base_mount_point) {
+                Ok(config) => config,
+                Err(code) => return Ok(code),
+            };
+            let mut document = serde_json::to_value(&config)?;
+            for (database, value) in config
+                .get_databases()
+                .iter()
+                .zip(document["databases"].as_array_mut().into_iter().flatten())
+            {
+                if database.database_password.is_plain() {
+                    value["database_password"] = "<redacted>".into();
+                }
+            }
+            for (channel, value) in config.get_alerting().channels.iter().zip(
+                document["alerting"]["channels"]
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten(),
+            ) {
+                if let ChannelKind::Email(email) = &channel.kind {
+                    if email.password.as_ref().is_some_and(SecretRef::is_plain) {
+                        value["password"] = "<redacted>".into();
+                    }
+                }
+            }
+            if config
+                .get_scratch()
+                .is_some_and(|scratch| scratch.password.is_plain())
+            {
+                document["scratch"]["password"] = "<redacted>".into();
+            }
+            println!("{}", serde_json::to_string_pretty(&document)?);
+        }
+        ConfigCommand::Path => println!("{}", config_path(base_mount_point)),
+        ConfigCommand::Migrate => match migrate_config(base_mount_point)? {
+            Some(version) => println!(
+                "Migrated config file from version {} to {}",
+                version, CONFIG_VERSION
+            ),
+            None => println!("Config file is already at version {}", CONFIG_VERSION),
+        },
+        ConfigCommand::Secret(command) => {
+            let master_key = master_key()?;
+            let mut secrets = SecretsFile::unlock(base_mount_point, &master_key)?;
+            match command {
+                SecretCommand::Set { name } => {
+                    let mut value = String::new();
+                    std::io::stdin().lock().read_line(&mut value)?;
+                    secrets.set(
+                        name.clone(),
+                        value.trim_end_matches(['\r', '\n']).to_string(),
+                    );
+                    secrets.save(base_mount_point, &master_key)?;
+                    println!("Stored secret {}, reference it as secret:{}", name, name);
+                }
+                SecretCommand::Remove { name } => {
+                    if secrets.remove(name).is_none() {
+                        return Err(format!("No secret named {}", name).into());
+                    }
+                    secrets.save(base_mount_point, &master_key)?;
+                }
+                SecretCommand::List => {
+                    for name in secrets.names() {
+                        println!("{}", name);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn alerts(
+    base_mount_point: &str,
+    config: &Config,
+    command: &AlertsCommand,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        AlertsCommand::List => {
+            let state = StateStore::open(base_mount_point)?;
+            for alert in state.get_alerts()? {
+                println!(
+                    "{}\tsince={}\tnotified={}\t{}",
+                    alert.key,
+                    format_time(alert.since),
+                    alert
+                        .last_notified
+                        .map(format_time)
+                        .unwrap_or_else(|| "never".to_string()),
+                    alert.message
+                );
+            }
+        }
+        AlertsCommand::Test { channel } => {
+            let alerter = Alerter::new(base_mount_point, config)?;
+            let sent = alerter.send_test(channel.as_deref()).await?;
+            println!("Sent a test alert to {} channel(s)", sent);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Tests the connection, shows the tables found and adds the database with the
+/// chosen ones as targets
+pub async fn add_database(
+    base_mount_point: &str,
+    args: &AddDatabaseArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let interactive = std::io::stdin().is_terminal();
+
+    let password = match &args.password {
+        Some(password) => password.clone(),
+        None if interactive => {
+            rpassword::prompt_password("Password (or a reference such as env:PGPASSWORD): ")?
+        }
+        None => return Err("--password is required when not run interactively".into()),
+    };
+
+    let database = Database::new(
+        args.host.clone(),
+        args.port,
+        args.user.clone(),
+        args.name.clone(),
+        password.parse::<SecretRef>()?,
+        Vec::new(),
+        args.interval.as_secs(),
+        SystemTime::now(),
+    );
+
+    let candidates = onboarding::discover_tables(base_mount_point, &database).await?;
+    println!(
+        "Connected to {}, found {} tables:",
+        args.name,
+        candidates.len()
+    );
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!(
+            "  {:>3}. {:<30} {} columns, primary key ({}), cursor {}{}",
+            i + 1,
+            candidate.name,
+            candidate.fields.len(),
+            candidate.primary_key.join(", "),
+            candidate.cursor_column.as_deref().unwrap_or("none"),
+            candidate
+                .problem
+                .as_ref()
+                .map(|problem| format!(" - {}", problem))
+                .unwrap_or_default()
+        );
+    }
+
+    let selected = if args.all {
+        candidates
+            .iter()
+            .filter(|candidate| candidate.is_selectable())
+            .map(|candidate| candidate.name.clone())
+            .collect()
+    } else if !args.targets.is_empty() {
+        args.targets.clone()
+    } else if interactive {
+        prompt_targets(&candidates)?
+    } else {
+        return Err("--targets or --all is required when not run interactively".into());
+    };
+    if selected.is_empty() {
+        return Err("No targets selected".into());
+    }
+
+    onboarding::add_database(base_mount_point, database, &candidates, &selected)?;
+    println!("Added {} with targets {}", args.name, selected.join(", "));
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Asks for table numbers or names until the answer names only usable tables
+fn prompt_targets(candidates: &[TableCandidate]) -> Result<Vec<String>, Box<dyn Error>> {
+    loop {
+        print!("Tables to back up (numbers or names, comma separated, * for all usable): ");
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err("No targets selected".into());
+        }
+
+        if answer.trim() == "*" {
+            return Ok(candidates
+                .iter()
+                .filter(|candidate| candidate.is_selectable())
+                .map(|candidate| candidate.name.clone())
+                .collect());
+        }
+
+        let mut selected = Vec::new();
+        let mut unknown = Vec::new();
+        for choice in answer.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let candidate = match choice.parse::<usize>() {
+                Ok(number) => candidates.get(number.wrapping_sub(1)),
+                Err(_) => candidates.iter().find(|candidate| candidate.name == choice),
+            };
+            match candidate {
+                Some(candidate) if candidate.is_selectable() => {
+                    selected.push(candidate.name.clone())
+                }
+                _ => unknown.push(choice),
+            }
+        }
+
+        if unknown.is_empty() {
+            return Ok(selected);
+        }
+        println!("Can't back up: {}", unknown.join(", "));
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    humantime::format_duration(Duration::from_secs(duration.as_secs())).to_string()
+}
+
+fn optional(value: Option<i64>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn format_time(time: SystemTime) -> String {
+    if time == SystemTime::UNIX_EPOCH {
+        return "never".to_string();
+    }
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Sends a request to the running scheduler and prints its answer
+pub async fn daemon(
+    base_mount_point: &str,
+    command: &DaemonCommand,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let request = match command {
+        DaemonCommand::Status => Request::Status,
+        DaemonCommand::Reload => Request::Reload,
+        DaemonCommand::Pause(args) => Request::Pause {
+            database: args.database.clone(),
+            target: args.target.clone(),
+        },
+        DaemonCommand::Resume(args) => Request::Resume {
+            database: args.database.clone(),
+            target: args.target.clone(),
+        },
+        DaemonCommand::RunNow { database, target } => Request::RunNow {
+            database: database.clone(),
+            target: target.clone(),
+        },
+        DaemonCommand::Shutdown => Request::Shutdown,
+    };
+    let response = control::send(base_mount_point, &request)
+        .await?
+        .ok_or_else(|| {
+            format!(
+                "No scheduler is running on {}",
+                control::socket_path(base_mount_point).display()
+            )
+        })?;
+
+    match response {
+        Response::Status(status) => {
+            println!(
+                "pid={}\tstarted_at={}\tcycles={}",
+                status.pid, status.started_at, status.cycles
+            );
+            for target in status.targets {
+                println!(
+                    "{}.{}\t{}\tnext_hit={}",
+                    target.database,
+                    target.target,
+                    target.schedule.as_str(),
+                    target.next_hit.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        Response::Started { run_id } => {
+            if let DaemonCommand::RunNow { database, target } = command {
+                let catalog = Catalog::open(base_mount_point)?;
+                let stats = finished_run(&catalog, run_id).await?;
+                println!(
+                    "{}.{}: captured {} rows, backed up to id {}",
+                    database, target, stats.rows_captured, stats.cursor_after
+                );
+            }
+        }
+        Response::Done | Response::Error(_) => {}
+    }
+    Ok(ExitCode::SUCCESS)
+}
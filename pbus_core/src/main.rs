@@ -1,44 +1,94 @@
-#![allow(unused_imports, dead_code)]
+use clap::Parser;
+use pbus_config_handler::config_file::set_config_file;
+use pbus_config_handler::{init_logging, LoggingConfig};
+use std::process::ExitCode;
+use utility::PbusError;
 
-use pbus_config_handler::*;
-use pbus_timer::*;
-use std::error::Error;
-use std::thread;
-use tokio_postgres::NoTls;
+mod cli;
+mod commands;
+mod metrics;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let base_mount_point = "../data/";
+use crate::cli::{Cli, Command};
 
-    let config_status: ConfigStatus = check_config(base_mount_point)?;
+/// The command ran into an error
+pub const EXIT_FAILURE: u8 = 1;
+/// Verification or a restore drill found corrupt or missing segments, or rows
+/// that differ from the source
+pub const EXIT_VERIFY: u8 = 3;
+/// At least one target is past its RPO
+pub const EXIT_STALE: u8 = 4;
+/// The config file is missing, unreadable or invalid (`EX_CONFIG` from sysexits.h),
+/// so systemd can be told not to restart on it
+pub const EXIT_CONFIG: u8 = 78;
 
-    let config = Config::read_config(base_mount_point)?;
+#[tokio::main]
+async fn main() -> ExitCode {
+    // clap exits with 2 on usage errors
+    let cli = Cli::parse();
+    let base_mount_point = cli.base_mount_point();
 
-    match config_status {
-        ConfigStatus::New => {
-            config.write_config(base_mount_point)?;
-        }
-        ConfigStatus::Existing => {
-            // Handle existing config file case
-            // let mut config = Config::read_config()?;
+    if let Some(path) = &cli.config {
+        if let Err(e) = set_config_file(path) {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CONFIG);
         }
     }
 
-      ("/backup-system/pbus_software/data/").await;
-
-    // Start the worker manager thread
-    // let worker_manager = tokio::spawn(async move {
-    //     worker_manager("/backup-system/pbus_software/data/config.json").await;
-    // });
-
-    // println!("Started Server Successfully!");
+    // Set up before the config is checked so that checking it is logged too
+    let _log_guard = match init_logging(&base_mount_point, &LoggingConfig::load(&base_mount_point))
+    {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
 
-    // loop {
-    //     // asks the user for input
-    //     let mut input = String::new();
-    //     std::io::stdin().read_line(&mut input).unwrap();
-    //     let input = input.trim();
-    // }
+    let result = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => commands::run(&base_mount_point, cli.metrics_addr).await,
+        Command::Config(command) => commands::config(&base_mount_point, &command),
+        Command::Daemon(command) => commands::daemon(&base_mount_point, &command).await,
+        command => {
+            let config = match commands::load_config(&base_mount_point) {
+                Ok(config) => config,
+                Err(code) => return code,
+            };
+            match command {
+                Command::BackupNow(filter) => {
+                    commands::backup_now(&base_mount_point, &config, &filter).await
+                }
+                Command::Restore(args) => {
+                    commands::restore(&base_mount_point, &config, &args).await
+                }
+                Command::Verify(filter) => {
+                    commands::verify(&base_mount_point, &config, &filter).await
+                }
+                Command::Drill(filter) => {
+                    commands::drill(&base_mount_point, &config, &filter).await
+                }
+                Command::Freshness(filter) => {
+                    commands::freshness(&base_mount_point, &config, &filter).await
+                }
+                Command::List(args) => commands::list(&base_mount_point, &config, &args),
+                Command::AddDatabase(args) => {
+                    commands::add_database(&base_mount_point, &args).await
+                }
+                Command::Alerts(command) => {
+                    commands::alerts(&base_mount_point, &config, &command).await
+                }
+                Command::Run | Command::Config(_) | Command::Daemon(_) => unreachable!(),
+            }
+        }
+    };
 
-    Ok(())
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            match e.downcast_ref::<PbusError>() {
+                Some(PbusError::Config(_)) => ExitCode::from(EXIT_CONFIG),
+                _ => ExitCode::from(EXIT_FAILURE),
+            }
+        }
+    }
 }
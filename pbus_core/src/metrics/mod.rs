@@ -0,0 +1,36 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::Router;
+use pbus_timer::metrics::metrics;
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// Serves `GET /metrics` in the Prometheus text format on `addr`
+///
+/// Binds before returning, so a bad address is reported at startup. The server
+/// itself runs in the background.
+pub async fn spawn_server(addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("can't serve metrics on {}: {}", addr, e))?;
+    info!("Serving metrics on http://{}/metrics", addr);
+
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics().encode(),
+            )
+        }),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Metrics server stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
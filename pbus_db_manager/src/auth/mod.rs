@@ -0,0 +1,329 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use utility::PbusError;
+
+use crate::{from_secs, to_secs};
+
+pub use pbus_models::Role;
+
+const AUTH_FILE: &str = "auth.db";
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = FULL;
+
+CREATE TABLE IF NOT EXISTS users (
+    name TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_name TEXT NOT NULL,
+    label TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL,
+    last_used INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    user_name TEXT,
+    action TEXT NOT NULL,
+    resource TEXT NOT NULL,
+    status INTEGER NOT NULL
+);
+";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub name: String,
+    /// PHC string of the password hash
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: SystemTime,
+}
+
+/// A token that authenticates as its user, only its hash is stored
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_name: String,
+    /// What the token is for, chosen by its user
+    pub label: String,
+    pub created_at: SystemTime,
+    pub last_used: Option<SystemTime>,
+}
+
+/// A mutating request made to the web API, whether it was allowed or not
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: SystemTime,
+    /// `None` if the request couldn't be authenticated
+    pub user_name: Option<String>,
+    /// The HTTP method
+    pub action: String,
+    /// The path that was requested
+    pub resource: String,
+    /// HTTP status of the answer
+    pub status: u16,
+}
+
+/// Users, API tokens and the audit log of the web API
+///
+/// Stored as SQLite at `<base_mount_point>auth.db`, next to the catalog and the
+/// state store. Hashing is left to the caller, this only keeps the results.
+pub struct AuthStore {
+    conn: Connection,
+}
+
+impl AuthStore {
+    pub fn open(base_mount_point: &str) -> Result<AuthStore, PbusError> {
+        let conn = Connection::open(format!("{}{}", base_mount_point, AUTH_FILE))
+            .map_err(PbusError::storage)?;
+        conn.execute_batch(SCHEMA).map_err(PbusError::storage)?;
+        Ok(AuthStore { conn })
+    }
+
+    pub fn get_user(&self, name: &str) -> Result<Option<User>, PbusError> {
+        let user = self
+            .conn
+            .query_row(
+                "SELECT name, password_hash, role, created_at FROM users WHERE name = ?1",
+                params![name],
+                user_from_row,
+            )
+            .optional()
+            .map_err(PbusError::storage)?;
+        Ok(user)
+    }
+
+    pub fn get_users(&self) -> Result<Vec<User>, PbusError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, password_hash, role, created_at FROM users ORDER BY name")
+            .map_err(PbusError::storage)?;
+        let users = stmt
+            .query_map([], user_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<User>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(users)
+    }
+
+    pub fn add_user(&self, user: &User) -> Result<(), PbusError> {
+        if self.get_user(&user.name)?.is_some() {
+            return Err(PbusError::config(format!(
+                "user {} already exists",
+                user.name
+            )));
+        }
+        self.conn
+            .execute(
+                "INSERT INTO users (name, password_hash, role, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    user.name,
+                    user.password_hash,
+                    user.role.as_str(),
+                    to_secs(user.created_at)
+                ],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    /// Replaces the password hash and role of an existing user, returns false if
+    /// there is no such user
+    ///
+    /// Refuses to give the last admin another role.
+    pub fn update_user(&self, user: &User) -> Result<bool, PbusError> {
+        if user.role != Role::Admin && self.is_last_admin(&user.name)? {
+            return Err(last_admin(&user.name));
+        }
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE users SET password_hash = ?1, role = ?2 WHERE name = ?3",
+                params![user.password_hash, user.role.as_str(), user.name],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(updated > 0)
+    }
+
+    /// Removes a user and their tokens, returns false if there is no such user
+    ///
+    /// Refuses to remove the last admin.
+    pub fn remove_user(&mut self, name: &str) -> Result<bool, PbusError> {
+        if self.is_last_admin(name)? {
+            return Err(last_admin(name));
+        }
+        let tx = self.conn.transaction().map_err(PbusError::storage)?;
+        tx.execute("DELETE FROM tokens WHERE user_name = ?1", params![name])
+            .map_err(PbusError::storage)?;
+        let removed = tx
+            .execute("DELETE FROM users WHERE name = ?1", params![name])
+            .map_err(PbusError::storage)?;
+        tx.commit().map_err(PbusError::storage)?;
+        Ok(removed > 0)
+    }
+
+    /// Whether `name` is the only admin, who can't be removed or given another
+    /// role, as nobody could manage users afterwards
+    pub fn is_last_admin(&self, name: &str) -> Result<bool, PbusError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM users WHERE role = ?1")
+            .map_err(PbusError::storage)?;
+        let admins = stmt
+            .query_map(params![Role::Admin.as_str()], |row| row.get::<_, String>(0))
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(admins == [name])
+    }
+
+    pub fn add_token(
+        &self,
+        user_name: &str,
+        label: &str,
+        token_hash: &str,
+    ) -> Result<ApiToken, PbusError> {
+        let now = SystemTime::now();
+        self.conn.execute(
+            "INSERT INTO tokens (user_name, label, token_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![user_name, label, token_hash, to_secs(now)],
+        ).map_err(PbusError::storage)?;
+        Ok(ApiToken {
+            id: self.conn.last_insert_rowid(),
+            user_name: user_name.to_string(),
+            label: label.to_string(),
+            created_at: now,
+            last_used: None,
+        })
+    }
+
+    /// The token with this hash and the user it belongs to, and marks it as used
+    pub fn use_token(&self, token_hash: &str) -> Result<Option<(ApiToken, User)>, PbusError> {
+        let token = self
+            .conn
+            .query_row(
+                "SELECT id, user_name, label, created_at, last_used FROM tokens WHERE token_hash = ?1",
+                params![token_hash],
+                token_from_row,
+            )
+            .optional().map_err(PbusError::storage)?;
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let user = match self.get_user(&token.user_name)? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        self.conn
+            .execute(
+                "UPDATE tokens SET last_used = ?1 WHERE id = ?2",
+                params![to_secs(SystemTime::now()), token.id],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(Some((token, user)))
+    }
+
+    /// Tokens of a user, oldest first
+    pub fn get_tokens(&self, user_name: &str) -> Result<Vec<ApiToken>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, user_name, label, created_at, last_used FROM tokens WHERE user_name = ?1 ORDER BY id",
+        ).map_err(PbusError::storage)?;
+        let tokens = stmt
+            .query_map(params![user_name], token_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<ApiToken>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(tokens)
+    }
+
+    /// Revokes a token of a user, returns false if they have no such token
+    pub fn remove_token(&self, user_name: &str, id: i64) -> Result<bool, PbusError> {
+        let removed = self
+            .conn
+            .execute(
+                "DELETE FROM tokens WHERE id = ?1 AND user_name = ?2",
+                params![id, user_name],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(removed > 0)
+    }
+
+    pub fn record_audit(
+        &self,
+        user_name: Option<&str>,
+        action: &str,
+        resource: &str,
+        status: u16,
+    ) -> Result<(), PbusError> {
+        self.conn.execute(
+            "INSERT INTO audit (at, user_name, action, resource, status) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                to_secs(SystemTime::now()),
+                user_name,
+                action,
+                resource,
+                status
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    /// Most recent audit entries, newest first
+    pub fn get_audit(&self, limit: u32) -> Result<Vec<AuditEntry>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, at, user_name, action, resource, status FROM audit ORDER BY id DESC LIMIT ?1",
+        ).map_err(PbusError::storage)?;
+        let entries = stmt
+            .query_map(params![limit], |row| {
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    at: from_secs(row.get(1)?),
+                    user_name: row.get(2)?,
+                    action: row.get(3)?,
+                    resource: row.get(4)?,
+                    status: row.get(5)?,
+                })
+            })
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<AuditEntry>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(entries)
+    }
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    let role: String = row.get(2)?;
+    Ok(User {
+        name: row.get(0)?,
+        password_hash: row.get(1)?,
+        // An unknown role grants the least
+        role: Role::parse(&role).unwrap_or(Role::Viewer),
+        created_at: from_secs(row.get(3)?),
+    })
+}
+
+fn token_from_row(row: &Row) -> rusqlite::Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get(0)?,
+        user_name: row.get(1)?,
+        label: row.get(2)?,
+        created_at: from_secs(row.get(3)?),
+        last_used: row.get::<_, Option<i64>>(4)?.map(from_secs),
+    })
+}
+
+fn last_admin(name: &str) -> PbusError {
+    PbusError::config(format!("{} is the last admin", name))
+}
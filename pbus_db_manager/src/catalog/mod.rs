@@ -0,0 +1,617 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use utility::PbusError;
+
+use crate::{from_secs, to_secs};
+
+const CATALOG_FILE: &str = "catalog.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    database_name TEXT NOT NULL,
+    target_name TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    status TEXT NOT NULL,
+    error TEXT,
+    cursor_before INTEGER NOT NULL DEFAULT 0,
+    cursor_after INTEGER,
+    rows_captured INTEGER NOT NULL DEFAULT 0,
+    bytes_written INTEGER NOT NULL DEFAULT 0,
+    retries INTEGER NOT NULL DEFAULT 0,
+    kind TEXT NOT NULL DEFAULT 'backup'
+);
+CREATE INDEX IF NOT EXISTS runs_target ON runs (database_name, target_name, started_at);
+
+CREATE TABLE IF NOT EXISTS segments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    database_name TEXT NOT NULL,
+    target_name TEXT NOT NULL,
+    path TEXT NOT NULL,
+    cursor_start INTEGER NOT NULL,
+    cursor_end INTEGER NOT NULL,
+    lsn_start TEXT,
+    lsn_end TEXT,
+    schema_version TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    row_count INTEGER NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS segments_target ON segments (database_name, target_name, cursor_start);
+";
+
+/// Columns added to `runs` after the first release, added to older catalogs on open
+const RUN_STATS_COLUMNS: [(&str, &str); 6] = [
+    ("cursor_before", "INTEGER NOT NULL DEFAULT 0"),
+    ("cursor_after", "INTEGER"),
+    ("rows_captured", "INTEGER NOT NULL DEFAULT 0"),
+    ("bytes_written", "INTEGER NOT NULL DEFAULT 0"),
+    ("retries", "INTEGER NOT NULL DEFAULT 0"),
+    ("kind", "TEXT NOT NULL DEFAULT 'backup'"),
+];
+
+/// What a run did to a target
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RunKind {
+    /// Captured new rows into segments
+    Backup,
+    /// Checked the segments against their checksums and the source
+    Scrub,
+    /// Restored the segments into a scratch database and compared them against
+    /// the source
+    Drill,
+}
+
+impl RunKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunKind::Backup => "backup",
+            RunKind::Scrub => "scrub",
+            RunKind::Drill => "drill",
+        }
+    }
+
+    fn parse(kind: &str) -> RunKind {
+        match kind {
+            "scrub" => RunKind::Scrub,
+            "drill" => RunKind::Drill,
+            _ => RunKind::Backup,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Succeeded => "succeeded",
+            RunStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> RunStatus {
+        match status {
+            "succeeded" => RunStatus::Succeeded,
+            "failed" => RunStatus::Failed,
+            _ => RunStatus::Running,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentStatus {
+    Active,
+    Corrupt,
+    Expired,
+}
+
+impl SegmentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SegmentStatus::Active => "active",
+            SegmentStatus::Corrupt => "corrupt",
+            SegmentStatus::Expired => "expired",
+        }
+    }
+
+    fn parse(status: &str) -> SegmentStatus {
+        match status {
+            "corrupt" => SegmentStatus::Corrupt,
+            "expired" => SegmentStatus::Expired,
+            _ => SegmentStatus::Active,
+        }
+    }
+}
+
+/// A single scheduled (or manual) run against one target
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupRun {
+    pub id: i64,
+    pub kind: RunKind,
+    pub database_name: String,
+    pub target_name: String,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    /// Cursor of the target when the run started
+    pub cursor_before: i64,
+    /// Cursor the run left the target at, `None` while running
+    pub cursor_after: Option<i64>,
+    pub rows_captured: i64,
+    pub bytes_written: i64,
+    pub retries: u32,
+}
+
+impl BackupRun {
+    pub fn duration(&self) -> Option<Duration> {
+        self.finished_at?.duration_since(self.started_at).ok()
+    }
+
+    /// What the run recorded when it finished
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            cursor_after: self.cursor_after.unwrap_or(self.cursor_before),
+            rows_captured: self.rows_captured,
+            bytes_written: self.bytes_written,
+            retries: self.retries,
+        }
+    }
+}
+
+/// What a run did, recorded when it finishes
+///
+/// A scrub leaves the cursor where its last segment ends and counts the rows
+/// it compared against the source as captured, a drill the rows it restored.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub cursor_after: i64,
+    pub rows_captured: i64,
+    pub bytes_written: i64,
+    pub retries: u32,
+}
+
+/// Totals over the runs of a target
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TargetStats {
+    pub runs: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub rows_captured: i64,
+    pub bytes_written: i64,
+    pub retries: u64,
+    pub avg_rows_per_run: f64,
+    pub avg_duration: Duration,
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<SystemTime>,
+}
+
+impl TargetStats {
+    /// How far the backup trails behind `now`, `None` if it never succeeded
+    pub fn lag(&self, now: SystemTime) -> Option<Duration> {
+        Some(now.duration_since(self.last_success?).unwrap_or_default())
+    }
+}
+
+/// A file of captured rows written by a run
+///
+/// Holds the rows whose cursor lies in `(cursor_start, cursor_end]`, so the
+/// segments of a target chain together without gaps. `path` is relative to the
+/// base mount point so the data directory can be moved.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Segment {
+    pub id: i64,
+    pub run_id: i64,
+    pub database_name: String,
+    pub target_name: String,
+    pub path: String,
+    pub cursor_start: i64,
+    pub cursor_end: i64,
+    pub lsn_start: Option<String>,
+    pub lsn_end: Option<String>,
+    pub schema_version: String,
+    pub checksum: String,
+    pub row_count: i64,
+    pub size_bytes: i64,
+    pub status: SegmentStatus,
+    pub created_at: SystemTime,
+}
+
+/// Local metadata catalog of backup runs and segments
+///
+/// Stored as SQLite at `<base_mount_point>catalog.db`. Restore, retention and the
+/// web server query this instead of scanning the data directory.
+pub struct Catalog {
+    conn: Connection,
+}
+
+impl Catalog {
+    pub fn open(base_mount_point: &str) -> Result<Catalog, PbusError> {
+        let conn = Connection::open(format!("{}{}", base_mount_point, CATALOG_FILE))
+            .map_err(PbusError::storage)?;
+        conn.execute_batch(SCHEMA).map_err(PbusError::storage)?;
+        add_missing_columns(&conn).map_err(PbusError::storage)?;
+        Ok(Catalog { conn })
+    }
+
+    pub fn start_run(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        kind: RunKind,
+        cursor_before: i64,
+    ) -> Result<i64, PbusError> {
+        self.conn.execute(
+            "INSERT INTO runs (database_name, target_name, kind, started_at, status, cursor_before) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                database_name,
+                target_name,
+                kind.as_str(),
+                to_secs(SystemTime::now()),
+                RunStatus::Running.as_str(),
+                cursor_before
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn finish_run(
+        &self,
+        run_id: i64,
+        status: RunStatus,
+        stats: &RunStats,
+        error: Option<&str>,
+    ) -> Result<(), PbusError> {
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?1, status = ?2, error = ?3, cursor_after = ?4, rows_captured = ?5, bytes_written = ?6, retries = ?7 WHERE id = ?8",
+            params![
+                to_secs(SystemTime::now()),
+                status.as_str(),
+                error,
+                stats.cursor_after,
+                stats.rows_captured,
+                stats.bytes_written,
+                stats.retries,
+                run_id
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    pub fn get_run(&self, run_id: i64) -> Result<Option<BackupRun>, PbusError> {
+        let run = self
+            .conn
+            .query_row(
+                "SELECT * FROM runs WHERE id = ?1",
+                params![run_id],
+                run_from_row,
+            )
+            .optional()
+            .map_err(PbusError::storage)?;
+        Ok(run)
+    }
+
+    /// Most recent runs of a target, newest first
+    pub fn get_runs(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        limit: u32,
+    ) -> Result<Vec<BackupRun>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM runs WHERE database_name = ?1 AND target_name = ?2 ORDER BY id DESC LIMIT ?3",
+        ).map_err(PbusError::storage)?;
+        let runs = stmt
+            .query_map(params![database_name, target_name, limit], run_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<BackupRun>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(runs)
+    }
+
+    /// Most recent backup of a target that succeeded
+    pub fn get_last_successful_run(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<Option<BackupRun>, PbusError> {
+        let run = self
+            .conn
+            .query_row(
+                "SELECT * FROM runs WHERE database_name = ?1 AND target_name = ?2 AND kind = ?3 AND status = ?4 ORDER BY id DESC LIMIT 1",
+                params![
+                    database_name,
+                    target_name,
+                    RunKind::Backup.as_str(),
+                    RunStatus::Succeeded.as_str()
+                ],
+                run_from_row,
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(run)
+    }
+
+    /// Most recent run of a kind against a target, finished or not
+    pub fn get_last_run(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        kind: RunKind,
+    ) -> Result<Option<BackupRun>, PbusError> {
+        let run = self
+            .conn
+            .query_row(
+                "SELECT * FROM runs WHERE database_name = ?1 AND target_name = ?2 AND kind = ?3 ORDER BY id DESC LIMIT 1",
+                params![database_name, target_name, kind.as_str()],
+                run_from_row,
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(run)
+    }
+
+    /// Failed backups of a target since its last successful one
+    pub fn count_recent_failures(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<u32, PbusError> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM runs
+             WHERE database_name = ?1 AND target_name = ?2 AND kind = ?5 AND status = ?3
+               AND id > COALESCE(
+                   (SELECT MAX(id) FROM runs WHERE database_name = ?1 AND target_name = ?2 AND kind = ?5 AND status = ?4),
+                   0)",
+            params![
+                database_name,
+                target_name,
+                RunStatus::Failed.as_str(),
+                RunStatus::Succeeded.as_str(),
+                RunKind::Backup.as_str()
+            ],
+            |row| row.get(0),
+        ).map_err(PbusError::storage)?;
+        Ok(count)
+    }
+
+    /// Totals over the finished backups of a target started at or after `since`
+    pub fn get_target_stats(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        since: SystemTime,
+    ) -> Result<TargetStats, PbusError> {
+        let stats = self.conn.query_row(
+            "SELECT COUNT(*),
+                    COUNT(*) FILTER (WHERE status = ?4),
+                    COUNT(*) FILTER (WHERE status = ?5),
+                    COALESCE(SUM(rows_captured), 0),
+                    COALESCE(SUM(bytes_written), 0),
+                    COALESCE(SUM(retries), 0),
+                    COALESCE(AVG(rows_captured), 0.0),
+                    COALESCE(AVG(finished_at - started_at), 0.0),
+                    MAX(finished_at) FILTER (WHERE status = ?4),
+                    MAX(finished_at) FILTER (WHERE status = ?5)
+             FROM runs
+             WHERE database_name = ?1 AND target_name = ?2 AND kind = ?6 AND started_at >= ?3 AND finished_at IS NOT NULL",
+            params![
+                database_name,
+                target_name,
+                to_secs(since),
+                RunStatus::Succeeded.as_str(),
+                RunStatus::Failed.as_str(),
+                RunKind::Backup.as_str()
+            ],
+            |row| {
+                let avg_duration: f64 = row.get(7)?;
+                let last_success: Option<i64> = row.get(8)?;
+                let last_failure: Option<i64> = row.get(9)?;
+                Ok(TargetStats {
+                    runs: row.get(0)?,
+                    succeeded: row.get(1)?,
+                    failed: row.get(2)?,
+                    rows_captured: row.get(3)?,
+                    bytes_written: row.get(4)?,
+                    retries: row.get(5)?,
+                    avg_rows_per_run: row.get(6)?,
+                    avg_duration: Duration::from_secs_f64(avg_duration.max(0.0)),
+                    last_success: last_success.map(from_secs),
+                    last_failure: last_failure.map(from_secs),
+                })
+            },
+        ).map_err(PbusError::storage)?;
+        Ok(stats)
+    }
+
+    /// Records a segment and returns its id. `segment.id` is ignored.
+    pub fn add_segment(&self, segment: &Segment) -> Result<i64, PbusError> {
+        self.conn.execute(
+            "INSERT INTO segments (run_id, database_name, target_name, path, cursor_start, cursor_end, lsn_start, lsn_end, schema_version, checksum, row_count, size_bytes, status, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                segment.run_id,
+                segment.database_name,
+                segment.target_name,
+                segment.path,
+                segment.cursor_start,
+                segment.cursor_end,
+                segment.lsn_start,
+                segment.lsn_end,
+                segment.schema_version,
+                segment.checksum,
+                segment.row_count,
+                segment.size_bytes,
+                segment.status.as_str(),
+                to_secs(segment.created_at),
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Active segments of a target in cursor order
+    pub fn get_segments(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<Vec<Segment>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM segments WHERE database_name = ?1 AND target_name = ?2 AND status = ?3 ORDER BY cursor_start, id",
+        ).map_err(PbusError::storage)?;
+        let segments = stmt
+            .query_map(
+                params![database_name, target_name, SegmentStatus::Active.as_str()],
+                segment_from_row,
+            )
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<Segment>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(segments)
+    }
+
+    pub fn get_run_segments(&self, run_id: i64) -> Result<Vec<Segment>, PbusError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM segments WHERE run_id = ?1 ORDER BY cursor_start, id")
+            .map_err(PbusError::storage)?;
+        let segments = stmt
+            .query_map(params![run_id], segment_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<Segment>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(segments)
+    }
+
+    pub fn get_latest_segment(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<Option<Segment>, PbusError> {
+        let segment = self
+            .conn
+            .query_row(
+                "SELECT * FROM segments WHERE database_name = ?1 AND target_name = ?2 AND status = ?3 ORDER BY cursor_end DESC, id DESC LIMIT 1",
+                params![database_name, target_name, SegmentStatus::Active.as_str()],
+                segment_from_row,
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(segment)
+    }
+
+    /// Active segments created before `before`, oldest first, for retention
+    pub fn get_segments_older_than(&self, before: SystemTime) -> Result<Vec<Segment>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM segments WHERE created_at < ?1 AND status = ?2 ORDER BY created_at, id",
+        ).map_err(PbusError::storage)?;
+        let segments = stmt
+            .query_map(
+                params![to_secs(before), SegmentStatus::Active.as_str()],
+                segment_from_row,
+            )
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<Segment>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(segments)
+    }
+
+    /// Segments of a database that failed verification, by target
+    pub fn get_corrupt_segments(&self, database_name: &str) -> Result<Vec<Segment>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM segments WHERE database_name = ?1 AND status = ?2 ORDER BY target_name, cursor_start, id",
+        ).map_err(PbusError::storage)?;
+        let segments = stmt
+            .query_map(
+                params![database_name, SegmentStatus::Corrupt.as_str()],
+                segment_from_row,
+            )
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<Segment>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(segments)
+    }
+
+    pub fn set_segment_status(
+        &self,
+        segment_id: i64,
+        status: SegmentStatus,
+    ) -> Result<(), PbusError> {
+        self.conn
+            .execute(
+                "UPDATE segments SET status = ?1 WHERE id = ?2",
+                params![status.as_str(), segment_id],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+}
+
+fn run_from_row(row: &Row) -> rusqlite::Result<BackupRun> {
+    let finished_at: Option<i64> = row.get("finished_at")?;
+    let status: String = row.get("status")?;
+    let kind: String = row.get("kind")?;
+    Ok(BackupRun {
+        id: row.get("id")?,
+        kind: RunKind::parse(&kind),
+        database_name: row.get("database_name")?,
+        target_name: row.get("target_name")?,
+        started_at: from_secs(row.get("started_at")?),
+        finished_at: finished_at.map(from_secs),
+        status: RunStatus::parse(&status),
+        error: row.get("error")?,
+        cursor_before: row.get("cursor_before")?,
+        cursor_after: row.get("cursor_after")?,
+        rows_captured: row.get("rows_captured")?,
+        bytes_written: row.get("bytes_written")?,
+        retries: row.get("retries")?,
+    })
+}
+
+/// Brings a catalog created by an older version up to the current `runs` table
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('runs')")?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    for (name, definition) in RUN_STATS_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE runs ADD COLUMN {} {}",
+                name, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn segment_from_row(row: &Row) -> rusqlite::Result<Segment> {
+    let status: String = row.get("status")?;
+    Ok(Segment {
+        id: row.get("id")?,
+        run_id: row.get("run_id")?,
+        database_name: row.get("database_name")?,
+        target_name: row.get("target_name")?,
+        path: row.get("path")?,
+        cursor_start: row.get("cursor_start")?,
+        cursor_end: row.get("cursor_end")?,
+        lsn_start: row.get("lsn_start")?,
+        lsn_end: row.get("lsn_end")?,
+        schema_version: row.get("schema_version")?,
+        checksum: row.get("checksum")?,
+        row_count: row.get("row_count")?,
+        size_bytes: row.get("size_bytes")?,
+        status: SegmentStatus::parse(&status),
+        created_at: from_secs(row.get("created_at")?),
+    })
+}
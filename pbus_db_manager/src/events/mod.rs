@@ -0,0 +1,117 @@
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use utility::PbusError;
+
+use crate::{from_secs, to_secs};
+
+const EVENTS_FILE: &str = "events.db";
+
+/// How many events are kept, older ones are dropped as new ones come in
+const KEEP_EVENTS: i64 = 10_000;
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    database_name TEXT NOT NULL,
+    target_name TEXT NOT NULL,
+    payload TEXT NOT NULL
+);
+";
+
+/// An event as it was logged, the payload is the JSON of the event
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoggedEvent {
+    pub id: i64,
+    pub at: SystemTime,
+    pub kind: String,
+    pub database_name: String,
+    pub target_name: String,
+    pub payload: String,
+}
+
+/// Recent progress events of backup runs
+///
+/// Stored as SQLite at `<base_mount_point>events.db`, so processes other than
+/// the one running the backup can follow along. Only the last `KEEP_EVENTS`
+/// are kept, it is a feed and not a history; the catalog has the runs.
+pub struct EventLog {
+    conn: Connection,
+}
+
+impl EventLog {
+    pub fn open(base_mount_point: &str) -> Result<EventLog, PbusError> {
+        let conn = Connection::open(format!("{}{}", base_mount_point, EVENTS_FILE))
+            .map_err(PbusError::storage)?;
+        conn.execute_batch(SCHEMA).map_err(PbusError::storage)?;
+        Ok(EventLog { conn })
+    }
+
+    /// Appends an event and returns its id
+    pub fn append(
+        &self,
+        kind: &str,
+        database_name: &str,
+        target_name: &str,
+        payload: &str,
+    ) -> Result<i64, PbusError> {
+        self.conn.execute(
+            "INSERT INTO events (at, kind, database_name, target_name, payload) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                to_secs(SystemTime::now()),
+                kind,
+                database_name,
+                target_name,
+                payload
+            ],
+        ).map_err(PbusError::storage)?;
+        let id = self.conn.last_insert_rowid();
+        self.conn
+            .execute(
+                "DELETE FROM events WHERE id <= ?1",
+                params![id - KEEP_EVENTS],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(id)
+    }
+
+    /// Up to `limit` events after `after` in the order they happened
+    pub fn get_events_after(&self, after: i64, limit: u32) -> Result<Vec<LoggedEvent>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, at, kind, database_name, target_name, payload FROM events WHERE id > ?1 ORDER BY id LIMIT ?2",
+        ).map_err(PbusError::storage)?;
+        let events = stmt
+            .query_map(params![after, limit], event_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<LoggedEvent>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(events)
+    }
+
+    /// Id of the newest event, 0 if there is none
+    pub fn last_id(&self) -> Result<i64, PbusError> {
+        let id = self
+            .conn
+            .query_row("SELECT COALESCE(MAX(id), 0) FROM events", [], |row| {
+                row.get(0)
+            })
+            .map_err(PbusError::storage)?;
+        Ok(id)
+    }
+}
+
+fn event_from_row(row: &Row) -> rusqlite::Result<LoggedEvent> {
+    Ok(LoggedEvent {
+        id: row.get(0)?,
+        at: from_secs(row.get(1)?),
+        kind: row.get(2)?,
+        database_name: row.get(3)?,
+        target_name: row.get(4)?,
+        payload: row.get(5)?,
+    })
+}
@@ -1 +1,25 @@
+use std::time::{Duration, SystemTime};
 
+pub mod auth;
+pub mod catalog;
+pub mod events;
+pub mod segments;
+pub mod state;
+
+pub use crate::auth::{ApiToken, AuditEntry, AuthStore, Role, User};
+pub use crate::catalog::{
+    BackupRun, Catalog, RunKind, RunStats, RunStatus, Segment, SegmentStatus, TargetStats,
+};
+pub use crate::events::{EventLog, LoggedEvent};
+pub use crate::state::{ActiveAlert, CircuitState, StateStore, TargetState};
+
+// SQLite has no timestamp type, so times are stored as seconds since the epoch
+pub(crate) fn to_secs(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+pub(crate) fn from_secs(secs: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
@@ -0,0 +1,312 @@
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use utility::PbusError;
+
+use crate::{from_secs, to_secs};
+
+const STATE_FILE: &str = "state.db";
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = FULL;
+
+CREATE TABLE IF NOT EXISTS target_state (
+    database_name TEXT NOT NULL,
+    target_name TEXT NOT NULL,
+    last_id INTEGER NOT NULL,
+    last_updated INTEGER NOT NULL,
+    last_checked INTEGER NOT NULL,
+    PRIMARY KEY (database_name, target_name)
+);
+
+CREATE TABLE IF NOT EXISTS alerts (
+    key TEXT PRIMARY KEY,
+    database_name TEXT NOT NULL,
+    target_name TEXT,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    since INTEGER NOT NULL,
+    last_notified INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS circuits (
+    database_name TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_error TEXT,
+    opened_at INTEGER,
+    next_probe INTEGER
+);
+";
+
+/// Runtime progress of a target, kept out of the config file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TargetState {
+    pub last_id: i64,
+    pub last_updated: SystemTime,
+    pub last_checked: SystemTime,
+}
+
+impl Default for TargetState {
+    fn default() -> TargetState {
+        TargetState {
+            last_id: 0,
+            last_updated: SystemTime::UNIX_EPOCH,
+            last_checked: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+/// An alert that is firing, kept so it is neither sent twice nor forgotten
+/// when it resolves
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ActiveAlert {
+    /// Identifies the condition, e.g. `failures/shop/orders`
+    pub key: String,
+    pub database_name: String,
+    pub target_name: Option<String>,
+    pub kind: String,
+    pub message: String,
+    /// When the condition was first seen
+    pub since: SystemTime,
+    /// When the alert was last delivered, `None` if no channel took it yet
+    pub last_notified: Option<SystemTime>,
+}
+
+/// Circuit breaker of a database, see `CircuitBreakerPolicy`
+///
+/// Only stored while the database is failing; a database without a row is
+/// healthy.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct CircuitState {
+    /// Failed runs in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// When the circuit opened, `None` while it is closed
+    pub opened_at: Option<SystemTime>,
+    /// When the next run may probe the open circuit
+    pub next_probe: Option<SystemTime>,
+}
+
+impl CircuitState {
+    pub fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+}
+
+/// Volatile runtime state of the scheduler
+///
+/// Stored as SQLite at `<base_mount_point>state.db` so that the config file only holds
+/// what the user configured. Every write is a single transaction, so a crash leaves
+/// either the old or the new state behind.
+pub struct StateStore {
+    conn: Connection,
+}
+
+impl StateStore {
+    pub fn open(base_mount_point: &str) -> Result<StateStore, PbusError> {
+        let conn = Connection::open(format!("{}{}", base_mount_point, STATE_FILE))
+            .map_err(PbusError::storage)?;
+        conn.execute_batch(SCHEMA).map_err(PbusError::storage)?;
+        Ok(StateStore { conn })
+    }
+
+    /// State of a target, or the default state if it has never been hit
+    pub fn get_target_state(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<TargetState, PbusError> {
+        let state = self
+            .conn
+            .query_row(
+                "SELECT last_id, last_updated, last_checked FROM target_state WHERE database_name = ?1 AND target_name = ?2",
+                params![database_name, target_name],
+                |row| {
+                    Ok(TargetState {
+                        last_id: row.get(0)?,
+                        last_updated: from_secs(row.get(1)?),
+                        last_checked: from_secs(row.get(2)?),
+                    })
+                },
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(state.unwrap_or_default())
+    }
+
+    pub fn has_target_state(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<bool, PbusError> {
+        let count: i64 = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM target_state WHERE database_name = ?1 AND target_name = ?2",
+                params![database_name, target_name],
+                |row| row.get(0),
+            )
+            .map_err(PbusError::storage)?;
+        Ok(count > 0)
+    }
+
+    pub fn set_target_state(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        state: &TargetState,
+    ) -> Result<(), PbusError> {
+        self.conn.execute(
+            "INSERT INTO target_state (database_name, target_name, last_id, last_updated, last_checked)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (database_name, target_name) DO UPDATE SET
+                last_id = excluded.last_id,
+                last_updated = excluded.last_updated,
+                last_checked = excluded.last_checked",
+            params![
+                database_name,
+                target_name,
+                state.last_id,
+                to_secs(state.last_updated),
+                to_secs(state.last_checked)
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    pub fn remove_target_state(
+        &self,
+        database_name: &str,
+        target_name: &str,
+    ) -> Result<(), PbusError> {
+        self.conn
+            .execute(
+                "DELETE FROM target_state WHERE database_name = ?1 AND target_name = ?2",
+                params![database_name, target_name],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    pub fn get_alert(&self, key: &str) -> Result<Option<ActiveAlert>, PbusError> {
+        let alert = self
+            .conn
+            .query_row(
+                "SELECT key, database_name, target_name, kind, message, since, last_notified FROM alerts WHERE key = ?1",
+                params![key],
+                alert_from_row,
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(alert)
+    }
+
+    /// Every firing alert, oldest first
+    pub fn get_alerts(&self) -> Result<Vec<ActiveAlert>, PbusError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT key, database_name, target_name, kind, message, since, last_notified FROM alerts ORDER BY since",
+        ).map_err(PbusError::storage)?;
+        let alerts = stmt
+            .query_map([], alert_from_row)
+            .map_err(PbusError::storage)?
+            .collect::<Result<Vec<ActiveAlert>, _>>()
+            .map_err(PbusError::storage)?;
+        Ok(alerts)
+    }
+
+    pub fn set_alert(&self, alert: &ActiveAlert) -> Result<(), PbusError> {
+        self.conn.execute(
+            "INSERT INTO alerts (key, database_name, target_name, kind, message, since, last_notified)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (key) DO UPDATE SET
+                message = excluded.message,
+                last_notified = excluded.last_notified",
+            params![
+                alert.key,
+                alert.database_name,
+                alert.target_name,
+                alert.kind,
+                alert.message,
+                to_secs(alert.since),
+                alert.last_notified.map(to_secs)
+            ],
+        ).map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    pub fn remove_alert(&self, key: &str) -> Result<(), PbusError> {
+        self.conn
+            .execute("DELETE FROM alerts WHERE key = ?1", params![key])
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    /// Circuit of a database, closed if it has none stored
+    pub fn get_circuit(&self, database_name: &str) -> Result<CircuitState, PbusError> {
+        let circuit = self
+            .conn
+            .query_row(
+                "SELECT failures, last_error, opened_at, next_probe FROM circuits WHERE database_name = ?1",
+                params![database_name],
+                |row| {
+                    Ok(CircuitState {
+                        failures: row.get(0)?,
+                        last_error: row.get(1)?,
+                        opened_at: row.get::<_, Option<i64>>(2)?.map(from_secs),
+                        next_probe: row.get::<_, Option<i64>>(3)?.map(from_secs),
+                    })
+                },
+            )
+            .optional().map_err(PbusError::storage)?;
+        Ok(circuit.unwrap_or_default())
+    }
+
+    pub fn set_circuit(
+        &self,
+        database_name: &str,
+        circuit: &CircuitState,
+    ) -> Result<(), PbusError> {
+        self.conn
+            .execute(
+                "INSERT INTO circuits (database_name, failures, last_error, opened_at, next_probe)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (database_name) DO UPDATE SET
+                failures = excluded.failures,
+                last_error = excluded.last_error,
+                opened_at = excluded.opened_at,
+                next_probe = excluded.next_probe",
+                params![
+                    database_name,
+                    circuit.failures,
+                    circuit.last_error,
+                    circuit.opened_at.map(to_secs),
+                    circuit.next_probe.map(to_secs)
+                ],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+
+    /// Closes the circuit of a database
+    pub fn remove_circuit(&self, database_name: &str) -> Result<(), PbusError> {
+        self.conn
+            .execute(
+                "DELETE FROM circuits WHERE database_name = ?1",
+                params![database_name],
+            )
+            .map_err(PbusError::storage)?;
+        Ok(())
+    }
+}
+
+fn alert_from_row(row: &Row) -> rusqlite::Result<ActiveAlert> {
+    Ok(ActiveAlert {
+        key: row.get(0)?,
+        database_name: row.get(1)?,
+        target_name: row.get(2)?,
+        kind: row.get(3)?,
+        message: row.get(4)?,
+        since: from_secs(row.get(5)?),
+        last_notified: row.get::<_, Option<i64>>(6)?.map(from_secs),
+    })
+}
@@ -0,0 +1,203 @@
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus, Segment, SegmentStatus};
+use std::time::SystemTime;
+use tempfile::TempDir;
+
+fn open() -> (TempDir, Catalog) {
+    let dir = TempDir::new().unwrap();
+    let catalog = Catalog::open(&format!("{}/", dir.path().display())).unwrap();
+    (dir, catalog)
+}
+
+/// Records a segment covering `(cursor_start, cursor_end]` of a target
+fn add_segment(
+    catalog: &Catalog,
+    target_name: &str,
+    run_id: i64,
+    cursor_start: i64,
+    cursor_end: i64,
+) -> i64 {
+    catalog
+        .add_segment(&Segment {
+            id: 0,
+            run_id,
+            database_name: "shop".to_string(),
+            target_name: target_name.to_string(),
+            path: format!("segments/shop/{}/{}.jsonl", target_name, cursor_end),
+            cursor_start,
+            cursor_end,
+            lsn_start: None,
+            lsn_end: None,
+            schema_version: "v1".to_string(),
+            checksum: "00".to_string(),
+            row_count: cursor_end - cursor_start,
+            size_bytes: 100,
+            status: SegmentStatus::Active,
+            created_at: SystemTime::now(),
+        })
+        .unwrap()
+}
+
+fn cursors(segments: &[Segment]) -> Vec<(i64, i64)> {
+    segments
+        .iter()
+        .map(|segment| (segment.cursor_start, segment.cursor_end))
+        .collect()
+}
+
+#[test]
+fn runs_are_recorded_and_finished() {
+    let (_dir, catalog) = open();
+
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, 40)
+        .unwrap();
+    let run = catalog.get_run(run_id).unwrap().unwrap();
+    assert_eq!(run.status, RunStatus::Running);
+    assert_eq!(run.kind, RunKind::Backup);
+    assert_eq!(
+        (run.database_name.as_str(), run.target_name.as_str()),
+        ("shop", "orders")
+    );
+    assert_eq!(run.cursor_before, 40);
+    assert_eq!(run.cursor_after, None);
+    assert_eq!(run.finished_at, None);
+    assert_eq!(run.duration(), None);
+
+    let stats = RunStats {
+        cursor_after: 90,
+        rows_captured: 50,
+        bytes_written: 2048,
+        retries: 1,
+    };
+    catalog
+        .finish_run(run_id, RunStatus::Succeeded, &stats, None)
+        .unwrap();
+    let run = catalog.get_run(run_id).unwrap().unwrap();
+    assert_eq!(run.status, RunStatus::Succeeded);
+    assert_eq!(run.cursor_after, Some(90));
+    assert_eq!(
+        (run.rows_captured, run.bytes_written, run.retries),
+        (50, 2048, 1)
+    );
+    assert!(run.finished_at.is_some());
+    assert!(run.duration().is_some());
+
+    let failed_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, 90)
+        .unwrap();
+    catalog
+        .finish_run(
+            failed_id,
+            RunStatus::Failed,
+            &RunStats {
+                cursor_after: 90,
+                ..RunStats::default()
+            },
+            Some("connection refused"),
+        )
+        .unwrap();
+    let scrub_id = catalog
+        .start_run("shop", "orders", RunKind::Scrub, 0)
+        .unwrap();
+
+    let runs = catalog.get_runs("shop", "orders", 10).unwrap();
+    let ids: Vec<i64> = runs.iter().map(|run| run.id).collect();
+    assert_eq!(ids, [scrub_id, failed_id, run_id]);
+    assert_eq!(runs[1].error.as_deref(), Some("connection refused"));
+    assert_eq!(
+        catalog
+            .get_last_successful_run("shop", "orders")
+            .unwrap()
+            .unwrap()
+            .id,
+        run_id
+    );
+    assert_eq!(
+        catalog
+            .get_last_run("shop", "orders", RunKind::Backup)
+            .unwrap()
+            .unwrap()
+            .id,
+        failed_id
+    );
+    assert!(catalog.get_run(scrub_id + 1).unwrap().is_none());
+}
+
+#[test]
+fn segments_are_listed_per_target_in_cursor_order() {
+    let (_dir, catalog) = open();
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, 0)
+        .unwrap();
+
+    add_segment(&catalog, "orders", run_id, 20, 30);
+    add_segment(&catalog, "customers", run_id, 0, 5);
+    add_segment(&catalog, "orders", run_id, 0, 10);
+    add_segment(&catalog, "orders", run_id, 10, 20);
+
+    let orders = catalog.get_segments("shop", "orders").unwrap();
+    assert_eq!(cursors(&orders), [(0, 10), (10, 20), (20, 30)]);
+    assert!(orders.iter().all(|segment| segment.target_name == "orders"));
+    assert_eq!(
+        cursors(&catalog.get_segments("shop", "customers").unwrap()),
+        [(0, 5)]
+    );
+    assert!(catalog.get_segments("other", "orders").unwrap().is_empty());
+
+    let latest = catalog
+        .get_latest_segment("shop", "orders")
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.cursor_end, 30);
+    assert_eq!(catalog.get_run_segments(run_id).unwrap().len(), 4);
+}
+
+#[test]
+fn segment_status_changes_hide_them_from_restores() {
+    let (_dir, catalog) = open();
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, 0)
+        .unwrap();
+    let first = add_segment(&catalog, "orders", run_id, 0, 10);
+    let second = add_segment(&catalog, "orders", run_id, 10, 20);
+
+    catalog
+        .set_segment_status(second, SegmentStatus::Corrupt)
+        .unwrap();
+    assert_eq!(
+        cursors(&catalog.get_segments("shop", "orders").unwrap()),
+        [(0, 10)]
+    );
+    assert_eq!(
+        catalog
+            .get_latest_segment("shop", "orders")
+            .unwrap()
+            .unwrap()
+            .id,
+        first
+    );
+    let corrupt = catalog.get_corrupt_segments("shop").unwrap();
+    assert_eq!(corrupt.len(), 1);
+    assert_eq!(corrupt[0].id, second);
+    assert_eq!(corrupt[0].status, SegmentStatus::Corrupt);
+
+    // Marking it active again brings it back
+    catalog
+        .set_segment_status(second, SegmentStatus::Active)
+        .unwrap();
+    assert_eq!(
+        cursors(&catalog.get_segments("shop", "orders").unwrap()),
+        [(0, 10), (10, 20)]
+    );
+    assert!(catalog.get_corrupt_segments("shop").unwrap().is_empty());
+
+    catalog
+        .set_segment_status(first, SegmentStatus::Expired)
+        .unwrap();
+    assert_eq!(
+        cursors(&catalog.get_segments("shop", "orders").unwrap()),
+        [(10, 20)]
+    );
+    assert!(catalog.get_corrupt_segments("shop").unwrap().is_empty());
+    assert_eq!(catalog.get_run_segments(run_id).unwrap().len(), 2);
+}
@@ -0,0 +1,50 @@
+use pbus_db_manager::{StateStore, TargetState};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[test]
+fn target_state_is_upserted() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let state = StateStore::open(&base).unwrap();
+
+    assert!(!state.has_target_state("shop", "orders").unwrap());
+    assert_eq!(
+        state.get_target_state("shop", "orders").unwrap(),
+        TargetState::default()
+    );
+
+    let first = TargetState {
+        // Past what fits in an i32
+        last_id: 3_000_000_000,
+        last_updated: at(1700000000),
+        last_checked: at(1700000600),
+    };
+    state.set_target_state("shop", "orders", &first).unwrap();
+    assert!(state.has_target_state("shop", "orders").unwrap());
+    assert_eq!(state.get_target_state("shop", "orders").unwrap(), first);
+
+    let second = TargetState {
+        last_id: 3_000_000_100,
+        last_updated: at(1700001000),
+        last_checked: at(1700001000),
+    };
+    state.set_target_state("shop", "orders", &second).unwrap();
+    state
+        .set_target_state("shop", "customers", &TargetState::default())
+        .unwrap();
+
+    // Reopened, as the next run of the worker would
+    let state = StateStore::open(&base).unwrap();
+    assert_eq!(state.get_target_state("shop", "orders").unwrap(), second);
+    assert!(state.has_target_state("shop", "customers").unwrap());
+    assert!(!state.has_target_state("other", "orders").unwrap());
+
+    state.remove_target_state("shop", "orders").unwrap();
+    assert!(!state.has_target_state("shop", "orders").unwrap());
+    assert!(state.has_target_state("shop", "customers").unwrap());
+}
@@ -0,0 +1,202 @@
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus};
+use rusqlite::{params, Connection};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+/// A catalog and a second connection to it, to move runs back in time
+struct Runs {
+    _dir: TempDir,
+    catalog: Catalog,
+    conn: Connection,
+}
+
+impl Runs {
+    fn new() -> Runs {
+        let dir = TempDir::new().unwrap();
+        let base_mount_point = format!("{}/", dir.path().display());
+        let catalog = Catalog::open(&base_mount_point).unwrap();
+        let conn = Connection::open(format!("{}catalog.db", base_mount_point)).unwrap();
+        Runs {
+            _dir: dir,
+            catalog,
+            conn,
+        }
+    }
+
+    /// Records a run that started and finished at the given seconds since the
+    /// epoch, or is still running without `finished_at`
+    fn add(
+        &self,
+        target_name: &str,
+        kind: RunKind,
+        status: RunStatus,
+        started_at: i64,
+        finished_at: Option<i64>,
+        stats: RunStats,
+    ) {
+        let run_id = self
+            .catalog
+            .start_run("shop", target_name, kind, 0)
+            .unwrap();
+        if status != RunStatus::Running {
+            self.catalog
+                .finish_run(run_id, status, &stats, None)
+                .unwrap();
+        }
+        self.conn
+            .execute(
+                "UPDATE runs SET started_at = ?1, finished_at = ?2 WHERE id = ?3",
+                params![started_at, finished_at, run_id],
+            )
+            .unwrap();
+    }
+
+    fn backup(&self, status: RunStatus, started_at: i64, secs: i64, rows: i64, retries: u32) {
+        self.add(
+            "orders",
+            RunKind::Backup,
+            status,
+            started_at,
+            Some(started_at + secs),
+            RunStats {
+                cursor_after: 0,
+                rows_captured: rows,
+                bytes_written: rows * 10,
+                retries,
+            },
+        );
+    }
+}
+
+fn at(secs: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+}
+
+#[test]
+fn stats_sum_up_finished_backups_in_the_window() {
+    let runs = Runs::new();
+    runs.backup(RunStatus::Succeeded, 1000, 10, 100, 0);
+    runs.backup(RunStatus::Failed, 2000, 30, 0, 2);
+    runs.backup(RunStatus::Succeeded, 3000, 20, 50, 1);
+    // None of these count: still running, not a backup, another target
+    runs.add(
+        "orders",
+        RunKind::Backup,
+        RunStatus::Running,
+        4000,
+        None,
+        RunStats::default(),
+    );
+    runs.add(
+        "orders",
+        RunKind::Scrub,
+        RunStatus::Succeeded,
+        4000,
+        Some(4500),
+        RunStats::default(),
+    );
+    runs.add(
+        "customers",
+        RunKind::Backup,
+        RunStatus::Failed,
+        4000,
+        Some(4001),
+        RunStats::default(),
+    );
+
+    let stats = runs
+        .catalog
+        .get_target_stats("shop", "orders", at(0))
+        .unwrap();
+    assert_eq!((stats.runs, stats.succeeded, stats.failed), (3, 2, 1));
+    assert_eq!(stats.rows_captured, 150);
+    assert_eq!(stats.bytes_written, 1500);
+    assert_eq!(stats.retries, 3);
+    assert_eq!(stats.avg_rows_per_run, 50.0);
+    assert_eq!(stats.avg_duration, Duration::from_secs(20));
+    assert_eq!(stats.last_success, Some(at(3020)));
+    assert_eq!(stats.last_failure, Some(at(2030)));
+    assert_eq!(stats.lag(at(3100)), Some(Duration::from_secs(80)));
+
+    // Runs that started right at the start of the window are in it
+    let stats = runs
+        .catalog
+        .get_target_stats("shop", "orders", at(2000))
+        .unwrap();
+    assert_eq!((stats.runs, stats.succeeded, stats.failed), (2, 1, 1));
+    assert_eq!(stats.avg_duration, Duration::from_secs(25));
+
+    let stats = runs
+        .catalog
+        .get_target_stats("shop", "orders", at(2001))
+        .unwrap();
+    assert_eq!((stats.runs, stats.failed), (1, 0));
+    assert_eq!(stats.last_failure, None);
+
+    let stats = runs
+        .catalog
+        .get_target_stats("shop", "orders", at(5000))
+        .unwrap();
+    assert_eq!(stats.runs, 0);
+    assert_eq!(stats.avg_rows_per_run, 0.0);
+    assert_eq!(stats.avg_duration, Duration::ZERO);
+    assert_eq!(stats.last_success, None);
+    assert_eq!(stats.lag(at(5000)), None);
+}
+
+#[test]
+fn failures_are_counted_since_the_last_success() {
+    let runs = Runs::new();
+    let failures = || {
+        runs.catalog
+            .count_recent_failures("shop", "orders")
+            .unwrap()
+    };
+    assert_eq!(failures(), 0);
+
+    // Without any success every failure counts
+    runs.backup(RunStatus::Failed, 1000, 1, 0, 0);
+    runs.backup(RunStatus::Failed, 2000, 1, 0, 0);
+    assert_eq!(failures(), 2);
+
+    runs.backup(RunStatus::Succeeded, 3000, 1, 10, 0);
+    assert_eq!(failures(), 0);
+
+    runs.backup(RunStatus::Failed, 4000, 1, 0, 0);
+    // Failed scrubs and other targets are no backups of this one
+    runs.add(
+        "orders",
+        RunKind::Scrub,
+        RunStatus::Failed,
+        4100,
+        Some(4101),
+        RunStats::default(),
+    );
+    runs.add(
+        "customers",
+        RunKind::Backup,
+        RunStatus::Failed,
+        4100,
+        Some(4101),
+        RunStats::default(),
+    );
+    runs.backup(RunStatus::Failed, 5000, 1, 0, 0);
+    assert_eq!(failures(), 2);
+
+    // Running backups are neither failures nor successes
+    runs.add(
+        "orders",
+        RunKind::Backup,
+        RunStatus::Running,
+        6000,
+        None,
+        RunStats::default(),
+    );
+    assert_eq!(failures(), 2);
+    assert_eq!(
+        runs.catalog
+            .count_recent_failures("shop", "customers")
+            .unwrap(),
+        1
+    );
+}
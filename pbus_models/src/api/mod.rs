@@ -0,0 +1,365 @@
+//! Request and response bodies of the web API
+//!
+//! Durations are in seconds and points in time are RFC 3339 timestamps, like in
+//! the config file.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::Role;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct DatabaseView {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    /// The password reference, such as `env:PGPASSWORD`, or `<redacted>` for a
+    /// plaintext password
+    pub password: String,
+    pub update_interval: u64,
+    pub rpo: Option<u64>,
+    /// How far back backups should reach, unset if there is no such policy;
+    /// nothing is deleted because of it
+    pub retention: Option<u64>,
+    pub targets: Vec<TargetView>,
+    pub circuit: CircuitView,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct TargetView {
+    pub name: String,
+    pub enabled: bool,
+    /// The target's own RPO, the database's applies if unset
+    pub rpo: Option<u64>,
+    /// Column name to data type
+    pub fields: HashMap<String, String>,
+    /// Cursor the backup has reached
+    pub last_id: i64,
+    /// When the last run captured rows, unset if none ever did
+    pub last_updated: Option<String>,
+    /// When the last run finished, unset if there never was one
+    pub last_checked: Option<String>,
+}
+
+/// Circuit breaker of a database
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct CircuitView {
+    /// Runs of the database are paused while the circuit is open
+    pub open: bool,
+    /// Failed runs in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub opened_at: Option<String>,
+    pub next_probe: Option<String>,
+}
+
+/// A database to connect to and add to the config
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct NewDatabase {
+    /// Name of the database on the server, also its name in the config
+    pub name: String,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub user: String,
+    /// Plaintext or a reference such as `env:PGPASSWORD`
+    pub password: String,
+    #[serde(default = "default_update_interval")]
+    pub update_interval: u64,
+    #[serde(default)]
+    pub rpo: Option<u64>,
+    /// Tables to back up
+    #[serde(default)]
+    pub targets: Vec<String>,
+    /// Back up every table that can be backed up, instead of `targets`
+    #[serde(default)]
+    pub all: bool,
+}
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_port() -> u16 {
+    5432
+}
+
+fn default_update_interval() -> u64 {
+    60 * 60
+}
+
+/// Changes to a configured database, unset fields are kept
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct DatabaseUpdate {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub update_interval: Option<u64>,
+    /// 0 removes the RPO
+    pub rpo: Option<u64>,
+    /// 0 removes the retention
+    pub retention: Option<u64>,
+}
+
+/// A table of a configured database to add as a target
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct NewTarget {
+    pub name: String,
+    #[serde(default)]
+    pub rpo: Option<u64>,
+}
+
+/// Changes to a target, unset fields are kept
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct TargetUpdate {
+    pub enabled: Option<bool>,
+    /// 0 removes the target's own RPO
+    pub rpo: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct RunView {
+    pub id: i64,
+    pub database: String,
+    pub target: String,
+    /// `backup`, `scrub` or `drill`
+    pub kind: String,
+    /// `running`, `succeeded` or `failed`
+    pub status: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub cursor_before: i64,
+    pub cursor_after: Option<i64>,
+    pub rows_captured: i64,
+    pub bytes_written: i64,
+    /// Attempts that failed and were retried within the run
+    pub retries: u32,
+    pub error: Option<String>,
+}
+
+/// A segment file holding the rows of one run
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct SegmentView {
+    pub id: i64,
+    pub run_id: i64,
+    /// Relative to the data directory
+    pub path: String,
+    /// Rows with a cursor in `(cursor_start, cursor_end]`
+    pub cursor_start: i64,
+    pub cursor_end: i64,
+    pub row_count: i64,
+    pub size_bytes: i64,
+    /// `active`, `corrupt` or `expired`
+    pub status: String,
+    pub created_at: String,
+}
+
+/// How far the backup of a target trails its source
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct FreshnessView {
+    pub database: String,
+    pub target: String,
+    /// End of the last successful run
+    pub last_success: Option<String>,
+    /// Seconds since the last successful run
+    pub age: Option<u64>,
+    /// Cursor the backup has reached
+    pub captured_cursor: i64,
+    /// Highest cursor in the source table
+    pub source_cursor: Option<i64>,
+    pub cursor_lag: Option<i64>,
+    pub wal_lag_bytes: Option<i64>,
+    pub rpo: Option<u64>,
+    pub rpo_breached: bool,
+    /// Why the source could not be queried, if it couldn't
+    pub source_error: Option<String>,
+}
+
+/// What a backup started through the API captured
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct BackupResult {
+    pub cursor_after: i64,
+    pub rows_captured: i64,
+    pub bytes_written: i64,
+    pub retries: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct RestoreRequest {
+    /// Only restore rows with a cursor up to and including this value
+    pub to_cursor: Option<i64>,
+    /// Insert the rows into this configured database instead of returning them
+    pub into: Option<String>,
+}
+
+/// Outcome of a restore into a database
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct RestoreResult {
+    pub database: String,
+    /// Rows read from the segments
+    pub rows: usize,
+    /// Rows inserted, the others were already present
+    pub inserted: u64,
+    /// Cursor ranges no segment covers
+    pub gaps: Vec<CursorGap>,
+}
+
+/// Rows with a cursor in `(from, to]` are missing from the backup
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct CursorGap {
+    pub from: i64,
+    pub to: i64,
+}
+
+/// Outcome of a restore drill into a scratch schema
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct DrillResult {
+    pub database: String,
+    pub target: String,
+    /// The drill's entry in the run history
+    pub run_id: i64,
+    /// Every segment matched its checksum and restored, no cursor range is
+    /// missing and the sampled rows match the source
+    pub passed: bool,
+    /// Segments checked and restored
+    pub segments: usize,
+    /// Rows read from the segments
+    pub rows: usize,
+    /// Rows the scratch table held after the restore
+    pub restored: u64,
+    /// Highest cursor the replayed rows reach
+    pub cursor: Option<i64>,
+    pub gaps: Vec<CursorGap>,
+    /// The segment that doesn't match its checksum, if one doesn't
+    pub corrupt_segment: Option<String>,
+    /// Restored rows compared against the source
+    pub sampled: usize,
+    /// Ids of sampled rows that differ from the source
+    pub mismatched: Vec<i64>,
+    pub duration_ms: u64,
+}
+
+/// Space taken by backups and left on the data directory's file system
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct StorageView {
+    pub total_bytes: Option<u64>,
+    pub available_bytes: Option<u64>,
+    pub targets: Vec<TargetStorageView>,
+}
+
+/// The segments of one target, which together are what can be restored
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct TargetStorageView {
+    pub database: String,
+    pub target: String,
+    /// Active segments
+    pub segments: usize,
+    pub rows: i64,
+    pub bytes: i64,
+    /// When the oldest active segment was written, restores reach back this far
+    pub oldest_segment: Option<String>,
+    pub newest_segment: Option<String>,
+    /// Segments that failed verification
+    pub corrupt_segments: usize,
+    /// The database's retention, see `DatabaseView::retention`
+    pub retention: Option<u64>,
+    /// Active segments older than the retention, which are kept all the same
+    pub past_retention: usize,
+}
+
+/// The worker running next to the server
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct WorkerView {
+    pub pid: u32,
+    pub started_at: String,
+    /// Cycles run since the worker started
+    pub cycles: u64,
+    pub targets: Vec<ScheduleView>,
+}
+
+/// Where a target stands in the worker's schedule
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ScheduleView {
+    pub database: String,
+    pub target: String,
+    /// `scheduled`, `paused`, `disabled` or `suspended`, the last after an error
+    /// that needs a config change
+    pub schedule: String,
+    /// When the target runs next, if it is scheduled
+    pub next_hit: Option<String>,
+}
+
+/// The user a request authenticated as
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct SessionView {
+    pub user: String,
+    #[schema(value_type = String, example = "operator")]
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct UserView {
+    pub name: String,
+    #[schema(value_type = String, example = "operator")]
+    pub role: Role,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct NewUser {
+    pub name: String,
+    pub password: String,
+    /// `viewer`, `operator` or `admin`
+    #[schema(value_type = String, example = "viewer")]
+    pub role: Role,
+}
+
+/// Changes to a user, unset fields are kept
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct UserUpdate {
+    pub password: Option<String>,
+    #[schema(value_type = Option<String>, example = "operator")]
+    pub role: Option<Role>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct TokenView {
+    pub id: i64,
+    pub label: String,
+    pub created_at: String,
+    pub last_used: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct NewToken {
+    /// What the token is for
+    pub label: String,
+}
+
+/// A new token, the only time it is shown
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct CreatedToken {
+    pub id: i64,
+    pub label: String,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct AuditView {
+    pub id: i64,
+    pub at: String,
+    /// Unset if the request couldn't be authenticated
+    pub user: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub status: u16,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ErrorBody {
+    pub error: String,
+}
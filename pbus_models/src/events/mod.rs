@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Progress of a backup run
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    RunStarted {
+        database: String,
+        target: String,
+        run_id: i64,
+        cursor: i64,
+    },
+    /// A segment was written and added to the catalog
+    BatchWritten {
+        database: String,
+        target: String,
+        run_id: i64,
+        rows: i64,
+        bytes: i64,
+        /// The cursor after the batch
+        cursor: i64,
+    },
+    /// An attempt failed, `retrying` tells whether another one follows
+    Error {
+        database: String,
+        target: String,
+        run_id: i64,
+        class: String,
+        message: String,
+        retrying: bool,
+    },
+    /// Totals of the whole run
+    RunFinished {
+        database: String,
+        target: String,
+        run_id: i64,
+        status: String,
+        rows_captured: i64,
+        bytes_written: i64,
+        cursor: i64,
+        retries: u32,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::RunStarted { .. } => "run-started",
+            Event::BatchWritten { .. } => "batch-written",
+            Event::Error { .. } => "error",
+            Event::RunFinished { .. } => "run-finished",
+        }
+    }
+
+    pub fn database(&self) -> &str {
+        match self {
+            Event::RunStarted { database, .. }
+            | Event::BatchWritten { database, .. }
+            | Event::Error { database, .. }
+            | Event::RunFinished { database, .. } => database,
+        }
+    }
+
+    pub fn target(&self) -> &str {
+        match self {
+            Event::RunStarted { target, .. }
+            | Event::BatchWritten { target, .. }
+            | Event::Error { target, .. }
+            | Event::RunFinished { target, .. } => target,
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! Types shared by the web server, the worker and the web client
+//!
+//! Kept free of the storage and database crates, so the client doesn't pull
+//! them in.
+
+pub mod api;
+pub mod events;
+pub mod roles;
+
+pub use crate::events::Event;
+pub use crate::roles::Role;
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+/// What a user of the web API may do, each role can do everything the ones
+/// before it can
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the config, runs and backups
+    Viewer,
+    /// Also starts backups and changes targets and schedules
+    Operator,
+    /// Also restores, adds, removes and reconnects databases and manages users
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "operator" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
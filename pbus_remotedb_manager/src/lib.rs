@@ -1,7 +1,63 @@
 use std::error::Error;
 use tokio_postgres::NoTls;
-use tokio_postgres::{tls::NoTlsStream, Socket};
-use utility::Target;
+use tracing::error;
+use utility::{PbusError, Target};
+
+/// WAL position of the server, which `pg_current_wal_lsn` can't tell on a standby
+const CURRENT_LSN: &str =
+    "CASE WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn() ELSE pg_current_wal_lsn() END";
+
+/// Sorts a PostgreSQL error by its SQLSTATE class
+///
+/// Errors without a SQLSTATE are network trouble when they come from the socket,
+/// and otherwise rows that couldn't be converted.
+pub fn classify(e: tokio_postgres::Error) -> PbusError {
+    let message = e.to_string();
+    let code = match e.code() {
+        Some(code) => code.code().to_string(),
+        None => {
+            let io = e
+                .source()
+                .is_some_and(|source| source.downcast_ref::<std::io::Error>().is_some());
+            return if io || e.is_closed() {
+                PbusError::Connection {
+                    message,
+                    retryable: true,
+                }
+            } else {
+                PbusError::Query {
+                    message,
+                    retryable: false,
+                }
+            };
+        }
+    };
+
+    match &code[..2] {
+        // Connection exception, operator intervention, insufficient resources
+        "08" | "57" | "53" => PbusError::Connection {
+            message,
+            retryable: true,
+        },
+        // Invalid authorization, unknown database
+        "28" | "3D" => PbusError::Connection {
+            message,
+            retryable: false,
+        },
+        // Serialization failure, deadlock, lock not available
+        "40" | "55" => PbusError::Query {
+            message,
+            retryable: true,
+        },
+        // Undefined table or column and other syntax errors, except missing
+        // privileges which are a matter of the login
+        "42" if code != "42501" => PbusError::Schema(message),
+        _ => PbusError::Query {
+            message,
+            retryable: false,
+        },
+    }
+}
 
 #[derive(Debug)]
 pub struct TableField {
@@ -14,53 +70,57 @@ pub struct DbHandler {
 }
 
 impl DbHandler {
+    /// Connects to `dbname` on `host:port`
+    ///
+    /// The parameters are passed as they are, so a password may hold spaces or
+    /// quotes.
     pub async fn new(
-        host: &String,
-        user: &String,
-        dbname: &String,
-        password: &String,
-    ) -> Result<DbHandler, Box<dyn Error>> {
-        // Configure the connection parameters
-        let (client, connection) = tokio_postgres::connect(
-            format!(
-                "host={} user={} dbname={} password={}",
-                host, user, dbname, password
-            )
-            .as_str(),
-            NoTls,
-        )
-        .await?;
+        host: &str,
+        port: u16,
+        user: &str,
+        dbname: &str,
+        password: &str,
+    ) -> Result<DbHandler, PbusError> {
+        let (client, connection) = tokio_postgres::Config::new()
+            .host(host)
+            .port(port)
+            .user(user)
+            .password(password)
+            .dbname(dbname)
+            .connect(NoTls)
+            .await
+            .map_err(classify)?;
 
         // Spawn a new tokio runtime for the connection
         tokio::spawn(async move {
             if let Err(e) = connection.await {
-                eprintln!("Connection error: {}", e);
+                error!("Connection error: {}", e);
             }
         });
 
         Ok(DbHandler { client })
     }
 
-    pub async fn get_tables(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    pub async fn get_tables(&self) -> Result<Vec<String>, PbusError> {
         let mut tables = Vec::new();
-        let rows = self.client.query("SELECT table_name FROM information_schema.tables WHERE table_schema='public' AND table_type='BASE TABLE';", &[]).await?;
+        let rows = self.client.query("SELECT table_name FROM information_schema.tables WHERE table_schema='public' AND table_type='BASE TABLE';", &[]).await.map_err(classify)?;
         for row in rows {
             tables.push(row.get(0));
         }
         Ok(tables)
     }
-    pub async fn get_targets(&self) -> Result<Vec<Target>, Box<dyn Error>> {
+    pub async fn get_targets(&self) -> Result<Vec<Target>, PbusError> {
         let mut tables: Vec<Target> = Vec::new();
-        let rows = self.client.query("SELECT table_name FROM information_schema.tables WHERE table_schema='public' AND table_type='BASE TABLE';", &[]).await?;
+        let rows = self.client.query("SELECT table_name FROM information_schema.tables WHERE table_schema='public' AND table_type='BASE TABLE';", &[]).await.map_err(classify)?;
         for row in rows {
             tables.push(Target::new(row.get(0)));
         }
         Ok(tables)
     }
 
-    pub async fn get_table_fields(&self, table: String) -> Result<Vec<TableField>, Box<dyn Error>> {
+    pub async fn get_table_fields(&self, table: String) -> Result<Vec<TableField>, PbusError> {
         let mut fields = Vec::new();
-        let rows = self.client.query("SELECT * FROM information_schema.columns WHERE table_schema='public' AND table_name=$1;", &[&table]).await?;
+        let rows = self.client.query("SELECT * FROM information_schema.columns WHERE table_schema='public' AND table_name=$1;", &[&table]).await.map_err(classify)?;
         for row in rows {
             let name: String = row.get(3);
             let data_type: String = row.get(7);
@@ -71,12 +131,69 @@ impl DbHandler {
         Ok(fields)
     }
 
-    pub async fn get_table_data(&self, table: Target) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    /// Columns of the table's primary key, in key order
+    pub async fn get_primary_key(&self, table: String) -> Result<Vec<String>, PbusError> {
+        let rows = self
+            .client
+            .query(
+                "SELECT kcu.column_name::text FROM information_schema.table_constraints tc \
+                 JOIN information_schema.key_column_usage kcu \
+                 ON tc.constraint_name = kcu.constraint_name AND tc.table_schema = kcu.table_schema \
+                 WHERE tc.constraint_type = 'PRIMARY KEY' AND tc.table_schema = 'public' AND tc.table_name = $1 \
+                 ORDER BY kcu.ordinal_position;",
+                &[&table],
+            )
+            .await.map_err(classify)?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Highest cursor value in the table, `None` if it is empty
+    pub async fn get_max_cursor(&self, table: &Target) -> Result<Option<i64>, PbusError> {
+        let row = self
+            .client
+            .query_one(
+                format!("SELECT MAX(id)::bigint FROM {};", table.get_name()).as_str(),
+                &[],
+            )
+            .await
+            .map_err(classify)?;
+        Ok(row.get(0))
+    }
+
+    /// Current WAL position, the last replayed one on a standby
+    pub async fn get_current_lsn(&self) -> Result<String, PbusError> {
+        let row = self
+            .client
+            .query_one(format!("SELECT ({})::text;", CURRENT_LSN).as_str(), &[])
+            .await
+            .map_err(classify)?;
+        Ok(row.get(0))
+    }
+
+    /// Bytes of WAL written since `lsn`
+    pub async fn get_wal_lag_bytes(&self, lsn: &str) -> Result<i64, PbusError> {
+        let row = self
+            .client
+            .query_one(
+                format!(
+                    "SELECT pg_wal_lsn_diff({}, $1::text::pg_lsn)::bigint;",
+                    CURRENT_LSN
+                )
+                .as_str(),
+                &[&lsn],
+            )
+            .await
+            .map_err(classify)?;
+        Ok(row.get(0))
+    }
+
+    pub async fn get_table_data(&self, table: Target) -> Result<Vec<Vec<String>>, PbusError> {
         let mut data = Vec::new();
         let rows = self
             .client
             .query(format!("SELECT * FROM {};", table.get_name()).as_str(), &[])
-            .await?;
+            .await
+            .map_err(classify)?;
         for row in rows {
             let mut row_data = Vec::new();
             for i in 0..row.len() {
@@ -87,39 +204,204 @@ impl DbHandler {
         Ok(data)
     }
 
+    /// Up to `limit` rows with an id above `last_id` in id order, and the id of
+    /// the last one
     pub async fn get_rows(
         &self,
         table: &Target,
-        last_id: i32,
-    ) -> Result<(Vec<serde_json::Value>, i32), Box<dyn Error>> {
+        last_id: i64,
+        limit: u32,
+    ) -> Result<(Vec<serde_json::Value>, i64), PbusError> {
         let rows = self
             .client
             .query(
                 format!(
-                    "SELECT row_to_json({}) FROM {} WHERE id > {};",
+                    "SELECT row_to_json({}) FROM {} WHERE id > {} ORDER BY id LIMIT {};",
                     table.get_name(),
                     table.get_name(),
-                    last_id
+                    last_id,
+                    limit
                 )
                 .as_str(),
                 &[],
             )
             .await
-            .unwrap();
+            .map_err(classify)?;
 
         let row: Vec<serde_json::Value> = rows
             .iter()
             .map(|row| row.get(0))
             .collect::<Vec<serde_json::Value>>();
 
-        if row.len() == 0 {
+        if row.is_empty() {
             return Ok((row, last_id));
         }
 
         let js_row: serde_json::Value = rows[rows.len() - 1].get(0);
 
-        let last_id: i32 = js_row["id"].as_i64().unwrap() as i32;
+        let last_id = js_row["id"].as_i64().ok_or_else(|| {
+            PbusError::schema(format!(
+                "{} has no integer `id` column to page through",
+                table.get_name()
+            ))
+        })?;
 
         Ok((row, last_id))
     }
+
+    /// Rows with an id in `(after, up_to]` in id order, in the form `get_rows`
+    /// returns them
+    pub async fn get_rows_between(
+        &self,
+        table: &Target,
+        after: i64,
+        up_to: i64,
+    ) -> Result<Vec<serde_json::Value>, PbusError> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT row_to_json({}) FROM {} WHERE id > $1::bigint AND id <= $2::bigint ORDER BY id;",
+                    table.get_name(),
+                    table.get_name()
+                )
+                .as_str(),
+                &[&after, &up_to],
+            )
+            .await.map_err(classify)?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Rows of `table` with one of the ids, in id order
+    ///
+    /// `table` may be schema qualified, so rows restored into a scratch schema
+    /// can be compared against the source.
+    pub async fn get_rows_by_id(
+        &self,
+        table: &str,
+        ids: &[i64],
+    ) -> Result<Vec<serde_json::Value>, PbusError> {
+        let rows = self
+            .client
+            .query(
+                format!(
+                    "SELECT row_to_json(t) FROM {} t WHERE id = ANY($1::bigint[]) ORDER BY id;",
+                    table
+                )
+                .as_str(),
+                &[&ids],
+            )
+            .await
+            .map_err(classify)?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Names and types of the table's columns in column order, the types as
+    /// `CREATE TABLE` takes them
+    pub async fn get_column_definitions(
+        &self,
+        table: &Target,
+    ) -> Result<Vec<TableField>, PbusError> {
+        let rows = self
+            .client
+            .query(
+                "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod) FROM pg_attribute a \
+                 WHERE a.attrelid = $1::text::regclass AND a.attnum > 0 AND NOT a.attisdropped \
+                 ORDER BY a.attnum;",
+                &[&format!("public.{}", table.get_name())],
+            )
+            .await
+            .map_err(classify)?;
+        Ok(rows
+            .iter()
+            .map(|row| TableField {
+                name: row.get(0),
+                data_type: row.get(1),
+            })
+            .collect())
+    }
+
+    /// Creates `schema` holding an empty table named like `table` with the given
+    /// columns, and returns the table's qualified name
+    ///
+    /// Restore drills restore into it and drop it with `drop_schema`.
+    pub async fn create_scratch_table(
+        &self,
+        schema: &str,
+        table: &Target,
+        columns: &[TableField],
+    ) -> Result<String, PbusError> {
+        let qualified = format!("{}.{}", schema, table.get_name());
+        let columns = columns
+            .iter()
+            .map(|column| {
+                format!(
+                    "\"{}\" {}",
+                    column.name.replace('"', "\"\""),
+                    column.data_type
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.client
+            .batch_execute(
+                format!(
+                    "CREATE SCHEMA {}; CREATE TABLE {} ({});",
+                    schema, qualified, columns
+                )
+                .as_str(),
+            )
+            .await
+            .map_err(classify)?;
+        Ok(qualified)
+    }
+
+    pub async fn drop_schema(&self, schema: &str) -> Result<(), PbusError> {
+        self.client
+            .batch_execute(format!("DROP SCHEMA IF EXISTS {} CASCADE;", schema).as_str())
+            .await
+            .map_err(classify)?;
+        Ok(())
+    }
+
+    /// Inserts rows previously read with `get_rows` in one transaction, skipping
+    /// rows that conflict with existing ones
+    ///
+    /// A failure leaves none of the rows behind. Returns the number of rows
+    /// inserted.
+    pub async fn insert_rows(
+        &mut self,
+        table: &Target,
+        rows: &[serde_json::Value],
+    ) -> Result<u64, PbusError> {
+        self.insert_rows_into(table.get_name(), rows).await
+    }
+
+    /// Like `insert_rows`, into a table that may be schema qualified
+    pub async fn insert_rows_into(
+        &mut self,
+        table: &str,
+        rows: &[serde_json::Value],
+    ) -> Result<u64, PbusError> {
+        let transaction = self.client.transaction().await.map_err(classify)?;
+        let statement = transaction
+            .prepare(
+                format!(
+                    "INSERT INTO {} SELECT * FROM json_populate_record(NULL::{}, $1) ON CONFLICT DO NOTHING;",
+                    table, table
+                )
+                .as_str(),
+            )
+            .await.map_err(classify)?;
+
+        let mut inserted = 0;
+        for row in rows {
+            inserted += transaction
+                .execute(&statement, &[row])
+                .await
+                .map_err(classify)?;
+        }
+        transaction.commit().await.map_err(classify)?;
+        Ok(inserted)
+    }
 }
@@ -0,0 +1,530 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use pbus_config_handler::alerting::{EmailChannel, SmtpSecurity};
+use pbus_config_handler::*;
+use pbus_db_manager::{ActiveAlert, Catalog, RunKind, StateStore};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use utility::PbusError;
+
+use crate::freshness::TargetFreshness;
+
+/// Longest a channel may take to accept an alert
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    /// A target failed `AlertRules::failures` runs in a row
+    Failures,
+    /// A target is past its RPO
+    Freshness,
+    /// A scrub found segments that are corrupt, missing or differ from the source
+    Verification,
+    /// The disk holding the data directory is nearly full
+    DiskUsage,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::Failures => "failures",
+            AlertKind::Freshness => "freshness",
+            AlertKind::Verification => "verification",
+            AlertKind::DiskUsage => "disk_usage",
+        }
+    }
+}
+
+/// A condition one of the alert rules watches
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub database_name: String,
+    pub target_name: Option<String>,
+    /// What is wrong, only meaningful while the alert fires
+    pub message: String,
+}
+
+impl Alert {
+    /// Identifies the condition across runs, so it is only sent once
+    fn key(&self) -> String {
+        match &self.target_name {
+            Some(target_name) => format!(
+                "{}/{}/{}",
+                self.kind.as_str(),
+                self.database_name,
+                target_name
+            ),
+            None => format!("{}/{}", self.kind.as_str(), self.database_name),
+        }
+    }
+}
+
+/// What a channel is sent, webhooks get it as is
+#[derive(Serialize, Debug)]
+struct Notification {
+    /// `firing` or `resolved`
+    status: &'static str,
+    kind: &'static str,
+    database: String,
+    target: Option<String>,
+    message: String,
+    since: String,
+    /// Whether the alert was sent before and still fires
+    repeat: bool,
+}
+
+impl Notification {
+    fn new(alert: &Alert, status: &'static str, since: SystemTime, repeat: bool) -> Notification {
+        Notification {
+            status,
+            kind: alert.kind.as_str(),
+            database: alert.database_name.clone(),
+            target: alert.target_name.clone(),
+            message: alert.message.clone(),
+            since: humantime::format_rfc3339_seconds(since).to_string(),
+            repeat,
+        }
+    }
+
+    fn subject(&self) -> String {
+        let source = match &self.target {
+            Some(target) => format!("{}.{}", self.database, target),
+            None => self.database.clone(),
+        };
+        format!(
+            "[pbus] {} {} {}",
+            self.status.to_uppercase(),
+            self.kind,
+            source
+        )
+    }
+
+    fn text(&self) -> String {
+        match self.status {
+            "resolved" => format!(
+                "{}: resolved, had fired since {}",
+                self.subject(),
+                self.since
+            ),
+            _ => format!(
+                "{}: {} (since {})",
+                self.subject(),
+                self.message,
+                self.since
+            ),
+        }
+    }
+}
+
+/// Raises and resolves alerts and delivers them to the configured channels
+///
+/// Firing alerts are kept in the state store, so a condition is sent once, then
+/// again every `repeat_interval` while it lasts, and once more when it clears.
+/// This holds across restarts and between the worker and one-off commands.
+pub struct Alerter {
+    base_mount_point: String,
+    alerting: AlertingConfig,
+    state: StateStore,
+    http: reqwest::Client,
+}
+
+impl Alerter {
+    pub fn new(base_mount_point: &str, config: &Config) -> Result<Alerter, PbusError> {
+        let alerter = Alerter {
+            base_mount_point: base_mount_point.to_string(),
+            alerting: config.get_alerting().clone(),
+            state: StateStore::open(base_mount_point)?,
+            http: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .map_err(PbusError::config)?,
+        };
+        alerter.forget_removed(config);
+        Ok(alerter)
+    }
+
+    /// Picks up changed channels after the config was reloaded
+    pub fn set_config(&mut self, config: &Config) {
+        self.alerting = config.get_alerting().clone();
+        self.forget_removed(config);
+    }
+
+    /// Drops alerts of databases and targets that are no longer configured, as
+    /// nothing would ever resolve them
+    fn forget_removed(&self, config: &Config) {
+        let alerts = match self.state.get_alerts() {
+            Ok(alerts) => alerts,
+            Err(e) => {
+                warn!("Can't read alert state: {}", e);
+                return;
+            }
+        };
+        for alert in alerts {
+            // Disk usage alerts are raised for the data directory, see `check_disk_usage`
+            let configured = if alert.kind == AlertKind::DiskUsage.as_str() {
+                alert.database_name == self.base_mount_point
+            } else {
+                config
+                    .get_databases()
+                    .iter()
+                    .find(|database| database.database_name == alert.database_name)
+                    .is_some_and(|database| {
+                        alert.target_name.as_ref().is_none_or(|target_name| {
+                            database
+                                .get_targets()
+                                .iter()
+                                .any(|target| target.get_name() == target_name)
+                        })
+                    })
+            };
+            if !configured {
+                info!(alert = %alert.key, "Dropping alert of a removed target");
+                if let Err(e) = self.state.remove_alert(&alert.key) {
+                    warn!(alert = %alert.key, "Can't store alert state: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Alerts once a target has failed `failures` runs in a row
+    pub async fn check_failures(&self, catalog: &Catalog, database: &Database, target_name: &str) {
+        let Some(rules) = &database.alerts else {
+            return;
+        };
+        let failures = match catalog.count_recent_failures(&database.database_name, target_name) {
+            Ok(failures) => failures,
+            Err(e) => {
+                warn!(target = %target_name, "Can't count failed runs: {}", e);
+                return;
+            }
+        };
+        let last_error = catalog
+            .get_last_run(&database.database_name, target_name, RunKind::Backup)
+            .ok()
+            .flatten()
+            .and_then(|run| run.error)
+            .unwrap_or_default();
+
+        let alert = Alert {
+            kind: AlertKind::Failures,
+            database_name: database.database_name.clone(),
+            target_name: Some(target_name.to_string()),
+            message: format!(
+                "{} runs failed in a row, last error: {}",
+                failures, last_error
+            ),
+        };
+        let firing = rules.failures > 0 && failures >= rules.failures;
+        self.update(rules, alert, firing).await;
+    }
+
+    pub async fn check_freshness(&self, database: &Database, freshness: &TargetFreshness) {
+        let Some(rules) = &database.alerts else {
+            return;
+        };
+        let age = match freshness.age {
+            Some(age) => format!("last backup {} ago", format_secs(age)),
+            None => "never backed up".to_string(),
+        };
+        let alert = Alert {
+            kind: AlertKind::Freshness,
+            database_name: database.database_name.clone(),
+            target_name: Some(freshness.target_name.clone()),
+            message: format!(
+                "past its RPO of {}, {}",
+                format_secs(freshness.rpo.unwrap_or_default()),
+                age
+            ),
+        };
+        self.update(rules, alert, rules.freshness && freshness.rpo_breached)
+            .await;
+    }
+
+    /// Reports the outcome of scrubbing every segment of a target, see
+    /// `ScrubReport::problems`
+    pub async fn check_verification(
+        &self,
+        database: &Database,
+        target_name: &str,
+        problems: &[String],
+    ) {
+        let Some(rules) = &database.alerts else {
+            return;
+        };
+        let alert = Alert {
+            kind: AlertKind::Verification,
+            database_name: database.database_name.clone(),
+            target_name: Some(target_name.to_string()),
+            message: format!(
+                "scrub found {} problem(s): {}",
+                problems.len(),
+                problems.join("; ")
+            ),
+        };
+        self.update(rules, alert, rules.verification && !problems.is_empty())
+            .await;
+    }
+
+    /// Alerts when the disk holding the data directory is fuller than the lowest
+    /// `disk_usage` of any database
+    ///
+    /// All databases share the one data directory, so this is checked once and
+    /// sent once, to every channel one of their rules routes to.
+    pub async fn check_disk_usage(&self, config: &Config) {
+        let Some(rules) = disk_usage_rules(config) else {
+            return;
+        };
+        let (total, available) = match (
+            fs2::total_space(&self.base_mount_point),
+            fs2::available_space(&self.base_mount_point),
+        ) {
+            (Ok(total), Ok(available)) if total > 0 => (total, available),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Can't read disk usage of {}: {}", self.base_mount_point, e);
+                return;
+            }
+            _ => return,
+        };
+        let used = 100 - available * 100 / total;
+        let alert = Alert {
+            kind: AlertKind::DiskUsage,
+            database_name: self.base_mount_point.clone(),
+            target_name: None,
+            message: format!(
+                "disk holding {} is {}% full, {} MiB free",
+                self.base_mount_point,
+                used,
+                available / (1024 * 1024)
+            ),
+        };
+        let firing = rules.disk_usage > 0 && used >= rules.disk_usage as u64;
+        self.update(&rules, alert, firing).await;
+    }
+
+    /// Sends a test alert to one channel, or to all of them, failing on the first
+    /// channel that doesn't take it
+    pub async fn send_test(&self, channel_name: Option<&str>) -> Result<usize, PbusError> {
+        let alert = Alert {
+            kind: AlertKind::Failures,
+            database_name: "pbus".to_string(),
+            target_name: None,
+            message: "test alert, please ignore".to_string(),
+        };
+        let notification = Notification::new(&alert, "firing", SystemTime::now(), false);
+
+        let mut sent = 0;
+        for channel in &self.alerting.channels {
+            if channel_name.is_some_and(|name| name != channel.name) {
+                continue;
+            }
+            self.deliver(channel, &notification)
+                .await
+                .map_err(|e| e.context(format!("channel {}", channel.name)))?;
+            sent += 1;
+        }
+        if sent == 0 {
+            return Err(match channel_name {
+                Some(name) => PbusError::config(format!("no alert channel named {}", name)),
+                None => PbusError::config("no alert channels configured"),
+            });
+        }
+        Ok(sent)
+    }
+
+    async fn update(&self, rules: &AlertRules, alert: Alert, firing: bool) {
+        let key = alert.key();
+        let active = match self.state.get_alert(&key) {
+            Ok(active) => active,
+            Err(e) => {
+                warn!(alert = %key, "Can't read alert state: {}", e);
+                return;
+            }
+        };
+        let now = SystemTime::now();
+
+        let result = match (firing, active) {
+            (true, None) => {
+                warn!(alert = %key, "Alert firing: {}", alert.message);
+                let notification = Notification::new(&alert, "firing", now, false);
+                let delivered = self.notify(rules, &notification).await;
+                self.state.set_alert(&ActiveAlert {
+                    key: key.clone(),
+                    database_name: alert.database_name.clone(),
+                    target_name: alert.target_name.clone(),
+                    kind: alert.kind.as_str().to_string(),
+                    message: alert.message.clone(),
+                    since: now,
+                    last_notified: delivered.then_some(now),
+                })
+            }
+            (true, Some(mut active)) => {
+                let repeat_interval = Duration::from_secs(self.alerting.repeat_interval);
+                let due = active.last_notified.is_none_or(|last_notified| {
+                    now.duration_since(last_notified).unwrap_or_default() >= repeat_interval
+                });
+                if due {
+                    let repeat = active.last_notified.is_some();
+                    let notification = Notification::new(&alert, "firing", active.since, repeat);
+                    if self.notify(rules, &notification).await {
+                        active.last_notified = Some(now);
+                    }
+                }
+                active.message = alert.message;
+                self.state.set_alert(&active)
+            }
+            (false, Some(active)) => {
+                info!(alert = %key, "Alert resolved");
+                // Nobody heard about an alert that was never delivered
+                if active.last_notified.is_some() {
+                    let alert = Alert {
+                        message: active.message,
+                        ..alert
+                    };
+                    let notification = Notification::new(&alert, "resolved", active.since, false);
+                    self.notify(rules, &notification).await;
+                }
+                self.state.remove_alert(&key)
+            }
+            (false, None) => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!(alert = %key, "Can't store alert state: {}", e);
+        }
+    }
+
+    /// Sends a notification to every channel the rules route to, returning whether
+    /// any of them took it
+    async fn notify(&self, rules: &AlertRules, notification: &Notification) -> bool {
+        let mut delivered = false;
+        for channel in &self.alerting.channels {
+            if !rules.routes_to(channel) {
+                continue;
+            }
+            match self.deliver(channel, notification).await {
+                Ok(()) => delivered = true,
+                Err(e) => warn!(channel = %channel.name, "Can't deliver alert: {}", e),
+            }
+        }
+        delivered
+    }
+
+    async fn deliver(
+        &self,
+        channel: &AlertChannel,
+        notification: &Notification,
+    ) -> Result<(), PbusError> {
+        let request = match &channel.kind {
+            ChannelKind::Webhook { url } => self.http.post(url).json(notification),
+            ChannelKind::Slack { url } => self
+                .http
+                .post(url)
+                .json(&serde_json::json!({ "text": notification.text() })),
+            ChannelKind::Email(email) => return self.send_email(email, notification).await,
+        };
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(http_error)?;
+        Ok(())
+    }
+
+    async fn send_email(
+        &self,
+        email: &EmailChannel,
+        notification: &Notification,
+    ) -> Result<(), PbusError> {
+        let mut message = Message::builder()
+            .from(email.from.parse().map_err(PbusError::config)?)
+            .subject(notification.subject())
+            .header(ContentType::TEXT_PLAIN);
+        for to in &email.to {
+            message = message.to(to.parse().map_err(PbusError::config)?);
+        }
+        let message = message
+            .body(notification.text())
+            .map_err(PbusError::config)?;
+
+        let mut transport = match email.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&email.smtp_host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&email.smtp_host)
+                    .map_err(PbusError::config)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&email.smtp_host)
+                .map_err(PbusError::config)?,
+        }
+        .port(email.smtp_port)
+        .timeout(Some(DELIVERY_TIMEOUT));
+        if let Some(username) = &email.username {
+            let password = match &email.password {
+                Some(password) => password.resolve_secret(&self.base_mount_point)?,
+                None => String::new(),
+            };
+            transport = transport.credentials(Credentials::new(username.clone(), password));
+        }
+
+        transport
+            .build()
+            .send(message)
+            .await
+            .map_err(|e| PbusError::Connection {
+                // The server turned the mail down for good, such as an unknown recipient
+                retryable: !e.is_permanent(),
+                message: e.to_string(),
+            })?;
+        Ok(())
+    }
+}
+
+/// A webhook that can't be reached or answers with a server error may take the
+/// alert later, one that refuses the request won't
+fn http_error(e: reqwest::Error) -> PbusError {
+    let retryable = e
+        .status()
+        .is_none_or(|status| status.is_server_error() || status.as_u16() == 429);
+    PbusError::Connection {
+        message: e.to_string(),
+        retryable,
+    }
+}
+
+/// The disk usage rule of every database with alerts folded into one: the
+/// lowest threshold that isn't off, routed to the channels of all of them
+fn disk_usage_rules(config: &Config) -> Option<AlertRules> {
+    let mut merged: Option<AlertRules> = None;
+    for rules in config
+        .get_databases()
+        .iter()
+        .filter_map(|database| database.alerts.as_ref())
+        .filter(|rules| rules.disk_usage > 0)
+    {
+        merged = Some(match merged {
+            None => rules.clone(),
+            Some(mut merged) => {
+                merged.disk_usage = merged.disk_usage.min(rules.disk_usage);
+                // No channels means every channel
+                if merged.channels.is_empty() || rules.channels.is_empty() {
+                    merged.channels.clear();
+                } else {
+                    for channel in &rules.channels {
+                        if !merged.channels.contains(channel) {
+                            merged.channels.push(channel.clone());
+                        }
+                    }
+                }
+                merged
+            }
+        });
+    }
+    merged
+}
+
+fn format_secs(duration: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(duration.as_secs()))
+}
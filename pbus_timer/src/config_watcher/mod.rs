@@ -0,0 +1,133 @@
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use pbus_config_handler::config_file::{config_path, is_config_file};
+use pbus_config_handler::*;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::info;
+use utility::time_handler::HitTargets;
+use utility::PbusError;
+
+/// Watches the directory of the config file and reports every change to the file
+///
+/// The directory is watched rather than the file itself because config writes
+/// replace the file through a rename.
+pub struct ConfigWatcher {
+    // Dropping the watcher stops the notifications
+    _watcher: RecommendedWatcher,
+    receiver: UnboundedReceiver<()>,
+}
+
+impl ConfigWatcher {
+    pub fn new(base_mount_point: &str) -> Result<ConfigWatcher, PbusError> {
+        let (sender, receiver) = unbounded_channel();
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                if event.paths.iter().any(|path| is_config_file(path)) {
+                    let _ = sender.send(());
+                }
+            }
+        })
+        .map_err(PbusError::storage)?;
+        let path = config_path(base_mount_point);
+        let dir = match Path::new(&path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| PbusError::storage(format!("can't watch {}: {}", dir.display(), e)))?;
+
+        Ok(ConfigWatcher {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    /// Waits until the config file changed
+    ///
+    /// Bursts of events from a single save are collapsed into one.
+    pub async fn changed(&mut self) {
+        if self.receiver.recv().await.is_none() {
+            // The watcher is owned by `self`, so it never hangs up
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+/// Re-reads the config and applies what changed to the running schedule
+///
+/// Targets that did not change keep their last and next hit. If the new config
+/// can't be read or fails validation the current one stays in effect and the
+/// error is returned.
+pub fn reload_config(
+    base_mount_point: &str,
+    config: &mut Config,
+    times: &mut Vec<HitTargets>,
+) -> Result<(), PbusError> {
+    let new_config = validate_config(base_mount_point)?;
+
+    let diff = ConfigDiff::new(config, &new_config);
+    if diff.is_empty() {
+        return Ok(());
+    }
+    info!(?diff, "Config updated");
+
+    for database_name in &diff.removed_databases {
+        times.retain(|time| &time.get_database_name() != database_name);
+    }
+    for (database_name, target_name) in &diff.removed_targets {
+        times.retain(|time| {
+            &time.get_database_name() != database_name || &time.get_name() != target_name
+        });
+    }
+
+    for database in new_config.get_databases() {
+        let database_changed = diff.modified_databases.contains(&database.database_name);
+        for target in database.get_targets() {
+            let key = (database.database_name.clone(), target.get_name().clone());
+            if database_changed
+                || diff.added_targets.contains(&key)
+                || diff.modified_targets.contains(&key)
+            {
+                schedule_target(times, database, target.get_name(), target.get_enabled());
+            }
+        }
+    }
+
+    *config = new_config;
+    Ok(())
+}
+
+/// Brings one target's entry in the schedule in line with the config
+pub fn schedule_target(
+    times: &mut Vec<HitTargets>,
+    database: &Database,
+    target_name: &String,
+    enabled: bool,
+) {
+    let interval = Duration::from_secs(database.get_update_interval());
+    let position = times.iter().position(|time| {
+        time.get_database_name() == database.database_name && &time.get_name() == target_name
+    });
+
+    match (position, enabled) {
+        (Some(position), true) => {
+            if times[position].get_interval() != interval {
+                times[position].set_interval(interval);
+            }
+        }
+        (Some(position), false) => {
+            times.remove(position);
+        }
+        (None, true) => times.push(HitTargets::new(
+            target_name.to_string(),
+            database.database_name.to_string(),
+            interval,
+        )),
+        (None, false) => {}
+    }
+}
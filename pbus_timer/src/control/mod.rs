@@ -0,0 +1,368 @@
+//! The control socket of a running worker
+//!
+//! The worker listens on a Unix domain socket, `pbus.sock` in the data
+//! directory unless `PBUS_CONTROL_SOCKET` names another path. Clients write one
+//! `Request` as JSON per line and read one `Response` per line back. Requests
+//! are answered by the worker between cycles, so one sent while targets are
+//! backed up waits for them. `Shutdown` is the exception, it cancels the run in
+//! progress after its current batch, see `shutdown`.
+
+use pbus_db_manager::{BackupRun, Catalog, RunStatus};
+use serde::{Deserialize, Serialize};
+use std::fs::DirBuilder;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use utility::PbusError;
+
+use crate::shutdown::shutdown;
+
+/// Requests waiting for the worker before new ones are held back
+const QUEUE: usize = 16;
+
+/// How often `wait_for_run` checks whether the run finished
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// What the worker is doing and when each target runs next
+    Status,
+    /// Re-read the config file now, answered with an error if it is rejected
+    Reload,
+    /// Take a target, or every target of a database, off the schedule until it
+    /// is resumed or the worker restarts
+    Pause {
+        database: String,
+        target: Option<String>,
+    },
+    /// Put paused targets back on the schedule, they run one interval later
+    Resume {
+        database: String,
+        target: Option<String>,
+    },
+    /// Back a target up right now, whether it is enabled, paused or not;
+    /// answered with the id of the run as soon as it is recorded, see
+    /// `wait_for_run`
+    RunNow { database: String, target: String },
+    /// Stop the worker once it answered, cancelling a run in progress
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Done,
+    Status(WorkerStatus),
+    /// The run a `RunNow` request started
+    Started {
+        run_id: i64,
+    },
+    Error(ControlError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorkerStatus {
+    pub pid: u32,
+    pub started_at: String,
+    /// Cycles run since the worker started
+    pub cycles: u64,
+    pub targets: Vec<ScheduledTarget>,
+}
+
+/// Where a configured target stands in the schedule
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduledTarget {
+    pub database: String,
+    pub target: String,
+    pub schedule: Schedule,
+    /// When the target runs next, if it is scheduled
+    pub next_hit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    Scheduled,
+    Paused,
+    /// Disabled in the config
+    Disabled,
+    /// Taken off the schedule after an error that needs a config change
+    Suspended,
+}
+
+impl Schedule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Schedule::Scheduled => "scheduled",
+            Schedule::Paused => "paused",
+            Schedule::Disabled => "disabled",
+            Schedule::Suspended => "suspended",
+        }
+    }
+}
+
+/// A `PbusError` on the wire
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlError {
+    pub class: String,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl From<&PbusError> for ControlError {
+    fn from(e: &PbusError) -> ControlError {
+        let message = match e {
+            PbusError::Config(message)
+            | PbusError::Storage(message)
+            | PbusError::Schema(message)
+            | PbusError::Connection { message, .. }
+            | PbusError::Query { message, .. } => message.clone(),
+            PbusError::Cancelled => String::new(),
+        };
+        ControlError {
+            class: e.class().to_string(),
+            message,
+            retryable: e.is_retryable(),
+        }
+    }
+}
+
+impl From<ControlError> for PbusError {
+    fn from(e: ControlError) -> PbusError {
+        let ControlError {
+            class,
+            message,
+            retryable,
+        } = e;
+        match class.as_str() {
+            "config" => PbusError::Config(message),
+            "connection" => PbusError::Connection { message, retryable },
+            "query" => PbusError::Query { message, retryable },
+            "schema" => PbusError::Schema(message),
+            "cancelled" => PbusError::Cancelled,
+            _ => PbusError::Storage(message),
+        }
+    }
+}
+
+/// A paused database, or a single paused target of one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pause {
+    pub database: String,
+    pub target: Option<String>,
+}
+
+impl Pause {
+    pub fn covers(&self, database_name: &str, target_name: &str) -> bool {
+        self.database == database_name
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|target| target == target_name)
+    }
+}
+
+/// The socket of the worker in `base_mount_point`
+pub fn socket_path(base_mount_point: &str) -> PathBuf {
+    match std::env::var_os("PBUS_CONTROL_SOCKET") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("{}pbus.sock", base_mount_point)),
+    }
+}
+
+/// A request read from the socket and where its answer goes
+pub struct Call {
+    pub request: Request,
+    reply: oneshot::Sender<Response>,
+}
+
+impl Call {
+    pub fn reply(self, response: Response) {
+        // The client may have hung up already
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Accepts clients on the control socket and hands their requests to the worker
+///
+/// Dropping it stops listening and removes the socket.
+pub struct ControlServer {
+    path: PathBuf,
+    calls: mpsc::Receiver<Call>,
+    listener: JoinHandle<()>,
+}
+
+impl ControlServer {
+    /// Listens on the socket of `base_mount_point`
+    ///
+    /// A socket left behind by a worker that didn't stop cleanly is replaced, one
+    /// that another worker still listens on is an error. Only the owner of the
+    /// worker process can connect.
+    pub fn bind(base_mount_point: &str) -> Result<ControlServer, PbusError> {
+        let path = socket_path(base_mount_point);
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(PbusError::storage(format!(
+                    "another worker is listening on {}",
+                    path.display()
+                )));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = bind_private(&path).map_err(|e| {
+            PbusError::storage(format!("can't listen on {}: {}", path.display(), e))
+        })?;
+        info!(socket = %path.display(), "Listening for control requests");
+
+        let (sender, calls) = mpsc::channel(QUEUE);
+        let listener = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_client(stream, sender.clone()));
+                    }
+                    Err(e) => warn!("Can't accept a control connection: {}", e),
+                }
+            }
+        });
+        Ok(ControlServer {
+            path,
+            calls,
+            listener,
+        })
+    }
+
+    /// The next request, waits until one arrives
+    pub async fn next(&mut self) -> Option<Call> {
+        self.calls.recv().await
+    }
+}
+
+/// Binds a socket at `path` that only its owner can connect to
+///
+/// The socket is bound in a fresh directory only the owner can enter, made
+/// owner-only and then renamed into place, so there is no moment in which
+/// others could connect to it.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    let dir = path.with_extension(format!("{}.tmp", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = dir.join("pbus.sock");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+    listener
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.listener.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Answers the requests of one client until it hangs up
+async fn serve_client(stream: UnixStream, calls: mpsc::Sender<Call>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                debug!(?request, "Control request");
+                let (reply, answer) = oneshot::channel();
+                let stop = request == Request::Shutdown;
+                if calls.send(Call { request, reply }).await.is_err() {
+                    return;
+                }
+                // Queued first, so the worker answers it before it stops
+                if stop {
+                    shutdown().request();
+                }
+                match answer.await {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => Response::Error(ControlError::from(&PbusError::config(format!(
+                "not a control request: {}",
+                e
+            )))),
+        };
+        let mut line = match serde_json::to_string(&response) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Can't encode a control response: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends `request` to the worker of `base_mount_point` and waits for the answer
+///
+/// Returns `None` if no worker is running there. An error the worker answered
+/// with is returned as an error.
+pub async fn send(
+    base_mount_point: &str,
+    request: &Request,
+) -> Result<Option<Response>, PbusError> {
+    let path = socket_path(base_mount_point);
+    let stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None)
+        }
+        Err(e) => {
+            return Err(PbusError::storage(format!(
+                "can't connect to {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(request).map_err(PbusError::storage)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let answer = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| PbusError::storage("the worker hung up without answering"))?;
+    match serde_json::from_str(&answer).map_err(PbusError::storage)? {
+        Response::Error(e) => Err(e.into()),
+        response => Ok(Some(response)),
+    }
+}
+
+/// Waits until a run the worker started for a `RunNow` request finished, and
+/// returns it as the catalog recorded it
+pub async fn wait_for_run(catalog: &Catalog, run_id: i64) -> Result<BackupRun, PbusError> {
+    loop {
+        let run = catalog
+            .get_run(run_id)?
+            .ok_or_else(|| PbusError::storage(format!("there is no run {}", run_id)))?;
+        if run.status != RunStatus::Running {
+            return Ok(run);
+        }
+        tokio::time::sleep(RUN_POLL_INTERVAL).await;
+    }
+}
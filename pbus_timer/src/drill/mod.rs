@@ -0,0 +1,480 @@
+use pbus_config_handler::{validation, Config, Database, ScratchServer};
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus, Segment};
+use pbus_remotedb_manager::{DbHandler, TableField};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{info, instrument, warn, Span};
+use utility::{PbusError, Target};
+
+use crate::restore::{replay_segments, segment_rows, Replay};
+
+/// Most restored rows compared against the source
+const SAMPLE_ROWS: usize = 100;
+
+/// What a restore drill found
+#[derive(Debug, Clone, Default)]
+pub struct DrillReport {
+    pub run_id: i64,
+    /// Active segments replayed
+    pub segments: usize,
+    /// Rows read from the segments
+    pub rows: usize,
+    /// Rows the scratch table holds after the restore
+    pub restored: u64,
+    /// Highest cursor the replayed rows reach
+    pub cursor: Option<i64>,
+    /// Cursor ranges `(from, to]` no segment covers
+    pub gaps: Vec<(i64, i64)>,
+    /// The segment that doesn't match its checksum, nothing was restored then
+    pub corrupt_segment: Option<String>,
+    /// Restored rows compared against the source
+    pub sampled: usize,
+    /// Ids of sampled rows that differ from the source or are gone there
+    pub mismatched: Vec<i64>,
+    pub duration: Duration,
+}
+
+impl DrillReport {
+    pub fn passed(&self) -> bool {
+        self.corrupt_segment.is_none()
+            && self.gaps.is_empty()
+            && self.restored == self.rows as u64
+            && self.mismatched.is_empty()
+    }
+
+    /// What made the drill fail, empty if it passed
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(path) = &self.corrupt_segment {
+            problems.push(format!("segment {} is corrupt", path));
+        }
+        for (from, to) in &self.gaps {
+            problems.push(format!("no segment covers cursors ({}, {}]", from, to));
+        }
+        if self.corrupt_segment.is_none() && self.restored != self.rows as u64 {
+            problems.push(format!("restored {} of {} rows", self.restored, self.rows));
+        }
+        if !self.mismatched.is_empty() {
+            problems.push(format!(
+                "{} of {} sampled rows differ from the source, ids {:?}",
+                self.mismatched.len(),
+                self.sampled,
+                self.mismatched
+            ));
+        }
+        problems
+    }
+}
+
+/// Restores a target into a throwaway schema on the scratch server and compares
+/// a sample of the restored rows against the source
+///
+/// The schema is named after the drill's run and dropped when the drill ends,
+/// whether it passed or not. The drill is recorded as a run, failed if it found
+/// anything. Errors if no scratch server is configured, the target isn't, or a
+/// server can't be reached.
+#[instrument(
+    name = "drill",
+    skip_all,
+    fields(database = %database_name, target = %target_name, run_id)
+)]
+pub async fn run_drill(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    config: &Config,
+    database_name: &str,
+    target_name: &str,
+) -> Result<DrillReport, PbusError> {
+    let scratch = config.get_scratch().ok_or_else(|| {
+        PbusError::config("no scratch server is configured, restore drills need one")
+    })?;
+    let database = config
+        .get_databases()
+        .iter()
+        .find(|database| database.database_name == database_name)
+        .ok_or_else(|| {
+            PbusError::config(format!("database {} is not configured", database_name))
+        })?;
+    let target = database
+        .get_targets()
+        .iter()
+        .find(|target| target.get_name() == target_name)
+        .ok_or_else(|| PbusError::config(format!("Target {} not found", target_name)))?;
+
+    let run_id = catalog.start_run(database_name, target_name, RunKind::Drill, 0)?;
+    Span::current().record("run_id", run_id);
+    info!("Starting restore drill");
+    let started = Instant::now();
+
+    let mut report = DrillReport {
+        run_id,
+        ..DrillReport::default()
+    };
+    let result = restore_and_compare(
+        base_mount_point,
+        catalog,
+        scratch,
+        database,
+        target,
+        &mut report,
+    )
+    .await;
+    report.duration = started.elapsed();
+
+    let stats = RunStats {
+        cursor_after: report.cursor.unwrap_or_default(),
+        rows_captured: report.restored as i64,
+        ..RunStats::default()
+    };
+    let (status, error) = match &result {
+        Ok(()) if report.passed() => (RunStatus::Succeeded, None),
+        Ok(()) => (RunStatus::Failed, Some(report.problems().join("; "))),
+        Err(e) => (RunStatus::Failed, Some(e.to_string())),
+    };
+    catalog.finish_run(run_id, status, &stats, error.as_deref())?;
+    match &error {
+        None => info!(rows = report.rows, "Restore drill passed"),
+        Some(error) => warn!("Restore drill failed: {}", error),
+    }
+    result.map(|()| report)
+}
+
+async fn restore_and_compare(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    scratch: &ScratchServer,
+    database: &Database,
+    target: &Target,
+    report: &mut DrillReport,
+) -> Result<(), PbusError> {
+    let database_name = &database.database_name;
+    let replay = replay_segments(
+        base_mount_point,
+        catalog,
+        database_name,
+        target.get_name(),
+        None,
+    )?;
+    let segments = match replay {
+        Replay::Segments { segments, gaps } => {
+            report.gaps = gaps;
+            segments
+        }
+        Replay::Corrupt(segment) => {
+            report.segments = catalog
+                .get_segments(database_name, target.get_name())?
+                .len();
+            report.corrupt_segment = Some(segment.path);
+            return Ok(());
+        }
+    };
+    report.segments = segments.len();
+
+    let source = DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await?;
+    let columns = source.get_column_definitions(target).await?;
+    let mut restore = DbHandler::new(
+        &scratch.host,
+        scratch.port,
+        &scratch.user,
+        &scratch.database,
+        &scratch.resolve_password(base_mount_point)?,
+    )
+    .await?;
+    restore_segments(
+        base_mount_point,
+        target,
+        &columns,
+        &segments,
+        &source,
+        &mut restore,
+        report,
+    )
+    .await
+}
+
+/// What the drill needs of the source and the scratch server
+pub(crate) trait DrillServer {
+    async fn create_scratch_table(
+        &self,
+        schema: &str,
+        table: &Target,
+        columns: &[TableField],
+    ) -> Result<String, PbusError>;
+
+    async fn insert_rows_into(&mut self, table: &str, rows: &[Value]) -> Result<u64, PbusError>;
+
+    async fn get_rows_by_id(&self, table: &str, ids: &[i64]) -> Result<Vec<Value>, PbusError>;
+
+    async fn drop_schema(&self, schema: &str) -> Result<(), PbusError>;
+}
+
+impl DrillServer for DbHandler {
+    async fn create_scratch_table(
+        &self,
+        schema: &str,
+        table: &Target,
+        columns: &[TableField],
+    ) -> Result<String, PbusError> {
+        DbHandler::create_scratch_table(self, schema, table, columns).await
+    }
+
+    async fn insert_rows_into(&mut self, table: &str, rows: &[Value]) -> Result<u64, PbusError> {
+        DbHandler::insert_rows_into(self, table, rows).await
+    }
+
+    async fn get_rows_by_id(&self, table: &str, ids: &[i64]) -> Result<Vec<Value>, PbusError> {
+        DbHandler::get_rows_by_id(self, table, ids).await
+    }
+
+    async fn drop_schema(&self, schema: &str) -> Result<(), PbusError> {
+        DbHandler::drop_schema(self, schema).await
+    }
+}
+
+/// Restores the segments into a schema named after the drill's run on the
+/// scratch server and compares a sample of the rows against the source
+///
+/// The schema is dropped again however the restore went.
+async fn restore_segments(
+    base_mount_point: &str,
+    target: &Target,
+    columns: &[TableField],
+    segments: &[Segment],
+    source: &impl DrillServer,
+    scratch: &mut impl DrillServer,
+    report: &mut DrillReport,
+) -> Result<(), PbusError> {
+    let schema = format!("pbus_drill_{}", report.run_id);
+    let restored = async {
+        let table = scratch
+            .create_scratch_table(&schema, target, columns)
+            .await?;
+
+        // Segments are restored one at a time, every `step`th row is sampled
+        let total_rows: usize = segments
+            .iter()
+            .map(|segment| segment.row_count as usize)
+            .sum();
+        let step = total_rows.div_ceil(SAMPLE_ROWS).max(1);
+        let mut ids = Vec::new();
+        for segment in segments {
+            let rows = segment_rows(base_mount_point, segment, None)?;
+            report.restored += scratch.insert_rows_into(&table, &rows).await?;
+            for row in &rows {
+                if report.rows.is_multiple_of(step) {
+                    ids.extend(row_cursor(row));
+                }
+                report.rows += 1;
+            }
+            report.cursor = report.cursor.max(rows.iter().filter_map(row_cursor).max());
+        }
+
+        report.sampled = ids.len();
+        let restored = by_id(scratch.get_rows_by_id(&table, &ids).await?);
+        let current = by_id(source.get_rows_by_id(target.get_name(), &ids).await?);
+        report.mismatched = ids
+            .into_iter()
+            .filter(|id| {
+                restored
+                    .get(id)
+                    .is_none_or(|row| Some(row) != current.get(id))
+            })
+            .collect();
+        Ok::<(), PbusError>(())
+    }
+    .await;
+
+    let dropped = scratch.drop_schema(&schema).await;
+    if let Err(e) = &dropped {
+        warn!(schema = %schema, "Can't drop the drill's scratch schema: {}", e);
+    }
+    restored.and(dropped)
+}
+
+fn row_cursor(row: &Value) -> Option<i64> {
+    row[validation::CURSOR_COLUMN].as_i64()
+}
+
+fn by_id(rows: Vec<Value>) -> HashMap<i64, Value> {
+    rows.into_iter()
+        .filter_map(|row| Some((row_cursor(&row)?, row)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pbus_db_manager::segments::write_segment;
+    use serde_json::json;
+    use std::cell::RefCell;
+    use tempfile::TempDir;
+
+    /// Keeps tables in memory, the scratch server can be told to fail inserts
+    #[derive(Default)]
+    struct FakeServer {
+        tables: HashMap<String, Vec<Value>>,
+        schemas: RefCell<Vec<String>>,
+        dropped: RefCell<Vec<String>>,
+        fail_inserts: bool,
+    }
+
+    impl DrillServer for FakeServer {
+        async fn create_scratch_table(
+            &self,
+            schema: &str,
+            table: &Target,
+            _columns: &[TableField],
+        ) -> Result<String, PbusError> {
+            self.schemas.borrow_mut().push(schema.to_string());
+            Ok(format!("{}.{}", schema, table.get_name()))
+        }
+
+        async fn insert_rows_into(
+            &mut self,
+            table: &str,
+            rows: &[Value],
+        ) -> Result<u64, PbusError> {
+            if self.fail_inserts {
+                return Err(PbusError::Query {
+                    message: "disk full".to_string(),
+                    retryable: false,
+                });
+            }
+            self.tables
+                .entry(table.to_string())
+                .or_default()
+                .extend_from_slice(rows);
+            Ok(rows.len() as u64)
+        }
+
+        async fn get_rows_by_id(&self, table: &str, ids: &[i64]) -> Result<Vec<Value>, PbusError> {
+            Ok(self
+                .tables
+                .get(table)
+                .into_iter()
+                .flatten()
+                .filter(|row| row_cursor(row).is_some_and(|id| ids.contains(&id)))
+                .cloned()
+                .collect())
+        }
+
+        async fn drop_schema(&self, schema: &str) -> Result<(), PbusError> {
+            self.dropped.borrow_mut().push(schema.to_string());
+            Ok(())
+        }
+    }
+
+    fn rows(ids: std::ops::RangeInclusive<i64>) -> Vec<Value> {
+        ids.map(|id| json!({ "id": id, "total": id * 10 }))
+            .collect()
+    }
+
+    /// Two segments holding the ids 1 to 20 and the run of the drill
+    fn segments(dir: &TempDir) -> (String, Vec<Segment>, DrillReport) {
+        let base = format!("{}/", dir.path().display());
+        let catalog = Catalog::open(&base).unwrap();
+        let run_id = catalog
+            .start_run("shop", "orders", RunKind::Backup, 0)
+            .unwrap();
+        let run = catalog.get_run(run_id).unwrap().unwrap();
+        for (start, end) in [(0, 10), (10, 20)] {
+            let segment = write_segment(
+                &base,
+                &run,
+                start,
+                end,
+                &rows(start + 1..=end),
+                "v1".to_string(),
+            )
+            .unwrap();
+            catalog.add_segment(&segment).unwrap();
+        }
+        let segments = catalog.get_segments("shop", "orders").unwrap();
+        let report = DrillReport {
+            run_id: catalog
+                .start_run("shop", "orders", RunKind::Drill, 0)
+                .unwrap(),
+            segments: segments.len(),
+            ..DrillReport::default()
+        };
+        (base, segments, report)
+    }
+
+    #[tokio::test]
+    async fn restored_rows_are_compared_against_the_source() {
+        let dir = TempDir::new().unwrap();
+        let (base, segments, mut report) = segments(&dir);
+        let target = Target::new("orders".to_string());
+
+        // The source changed one row and lost another since they were captured
+        let mut source_rows = rows(1..=20);
+        source_rows[4]["total"] = json!(0);
+        source_rows.remove(11);
+        let source = FakeServer {
+            tables: HashMap::from([("orders".to_string(), source_rows)]),
+            ..FakeServer::default()
+        };
+        let mut scratch = FakeServer::default();
+
+        restore_segments(
+            &base,
+            &target,
+            &[],
+            &segments,
+            &source,
+            &mut scratch,
+            &mut report,
+        )
+        .await
+        .unwrap();
+
+        let schema = format!("pbus_drill_{}", report.run_id);
+        assert_eq!(*scratch.schemas.borrow(), [schema.as_str()]);
+        assert_eq!(*scratch.dropped.borrow(), [schema.as_str()]);
+        assert_eq!(scratch.tables[&format!("{}.orders", schema)], rows(1..=20));
+        assert_eq!((report.rows, report.restored), (20, 20));
+        assert_eq!(report.cursor, Some(20));
+        assert_eq!(report.sampled, 20);
+        assert_eq!(report.mismatched, [5, 12]);
+        assert!(!report.passed());
+    }
+
+    #[tokio::test]
+    async fn the_schema_is_dropped_when_the_restore_fails() {
+        let dir = TempDir::new().unwrap();
+        let (base, segments, mut report) = segments(&dir);
+        let target = Target::new("orders".to_string());
+        let source = FakeServer::default();
+        let mut scratch = FakeServer {
+            fail_inserts: true,
+            ..FakeServer::default()
+        };
+
+        let error = restore_segments(
+            &base,
+            &target,
+            &[],
+            &segments,
+            &source,
+            &mut scratch,
+            &mut report,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.to_string().contains("disk full"));
+        assert_eq!(
+            scratch.dropped.borrow().as_slice(),
+            [format!("pbus_drill_{}", report.run_id)]
+        );
+        assert_eq!(report.restored, 0);
+    }
+}
@@ -0,0 +1,48 @@
+use pbus_db_manager::EventLog;
+use std::sync::{Mutex, OnceLock};
+use tracing::warn;
+
+pub use pbus_models::Event;
+
+/// Hands the events of backup runs to whoever is listening
+///
+/// Every event is appended to the `EventLog`, which is how the CLI and the web
+/// server follow runs, in this process or another. The log is opened on the
+/// first event and kept open. There is one instance per process, see `events()`.
+pub struct EventBus {
+    /// The open log and the base mount point it belongs to
+    log: Mutex<Option<(String, EventLog)>>,
+}
+
+/// The process wide event bus
+pub fn events() -> &'static EventBus {
+    static EVENTS: OnceLock<EventBus> = OnceLock::new();
+    EVENTS.get_or_init(|| EventBus {
+        log: Mutex::new(None),
+    })
+}
+
+impl EventBus {
+    /// Appends the event to the log of the data directory
+    ///
+    /// Events are only progress reports, so failing to log one is a warning and
+    /// never fails the run.
+    pub fn publish(&self, base_mount_point: &str, event: Event) {
+        let mut open = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let logged = serde_json::to_string(&event)
+            .map_err(utility::PbusError::storage)
+            .and_then(|payload| {
+                let log = match open.take() {
+                    Some((base, log)) if base == base_mount_point => log,
+                    _ => EventLog::open(base_mount_point)?,
+                };
+                log.append(event.kind(), event.database(), event.target(), &payload)?;
+                // A log that failed is opened again for the next event
+                *open = Some((base_mount_point.to_string(), log));
+                Ok(())
+            });
+        if let Err(e) = logged {
+            warn!("Can't log {} event: {}", event.kind(), e);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use pbus_config_handler::*;
+use pbus_db_manager::{Catalog, StateStore};
+use pbus_remotedb_manager::DbHandler;
+use std::time::{Duration, SystemTime};
+use utility::PbusError;
+
+/// How far the backup of a target trails its source
+#[derive(Debug, Clone)]
+pub struct TargetFreshness {
+    pub database_name: String,
+    pub target_name: String,
+    /// End of the last successful run
+    pub last_success: Option<SystemTime>,
+    /// Time since the last successful run, `None` if there never was one
+    pub age: Option<Duration>,
+    /// Cursor the backup has reached
+    pub captured_cursor: i64,
+    /// Highest cursor in the source table
+    pub source_cursor: Option<i64>,
+    /// Rows by cursor value the backup is behind the source
+    pub cursor_lag: Option<i64>,
+    /// WAL bytes written since the last confirmed LSN, for segments that carry one
+    pub wal_lag_bytes: Option<i64>,
+    pub rpo: Option<Duration>,
+    pub rpo_breached: bool,
+    /// Why the source could not be queried, if it couldn't
+    pub source_error: Option<String>,
+}
+
+/// Freshness of a target from the local catalog and state store alone
+///
+/// Cheap enough to run after every backup. The source side fields are left
+/// empty, see `check_freshness` for those.
+pub fn local_freshness(
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+    now: SystemTime,
+) -> Result<TargetFreshness, PbusError> {
+    let last_success = catalog
+        .get_last_successful_run(&database.database_name, target_name)?
+        .and_then(|run| run.finished_at);
+    let age = last_success.map(|time| now.duration_since(time).unwrap_or_default());
+    let rpo = database.get_target_rpo(target_name);
+    let rpo_breached = match (rpo, age) {
+        (Some(rpo), Some(age)) => age > rpo,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    Ok(TargetFreshness {
+        database_name: database.database_name.clone(),
+        target_name: target_name.to_string(),
+        last_success,
+        age,
+        captured_cursor: state
+            .get_target_state(&database.database_name, target_name)?
+            .last_id,
+        source_cursor: None,
+        cursor_lag: None,
+        wal_lag_bytes: None,
+        rpo,
+        rpo_breached,
+        source_error: None,
+    })
+}
+
+/// Freshness of every target of a database, including how far behind the source
+/// each one is
+///
+/// A source that can't be reached is reported in `source_error` rather than
+/// failing the whole check.
+pub async fn check_freshness(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+) -> Result<Vec<TargetFreshness>, PbusError> {
+    let now = SystemTime::now();
+    let mut report = Vec::new();
+    for target in database.get_targets() {
+        report.push(local_freshness(
+            catalog,
+            state,
+            database,
+            target.get_name(),
+            now,
+        )?);
+    }
+
+    let handler = match connect(base_mount_point, database).await {
+        Ok(handler) => handler,
+        Err(e) => {
+            for freshness in &mut report {
+                freshness.source_error = Some(e.to_string());
+            }
+            return Ok(report);
+        }
+    };
+
+    for (freshness, target) in report.iter_mut().zip(database.get_targets()) {
+        match handler.get_max_cursor(target).await {
+            Ok(source_cursor) => {
+                freshness.source_cursor = source_cursor;
+                freshness.cursor_lag = source_cursor
+                    .map(|source_cursor| (source_cursor - freshness.captured_cursor).max(0));
+            }
+            Err(e) => freshness.source_error = Some(e.to_string()),
+        }
+
+        let confirmed_lsn = catalog
+            .get_latest_segment(&database.database_name, target.get_name())?
+            .and_then(|segment| segment.lsn_end);
+        if let Some(lsn) = confirmed_lsn {
+            match handler.get_wal_lag_bytes(&lsn).await {
+                Ok(bytes) => freshness.wal_lag_bytes = Some(bytes),
+                Err(e) => freshness.source_error = Some(e.to_string()),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn connect(base_mount_point: &str, database: &Database) -> Result<DbHandler, PbusError> {
+    DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        &database.database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await
+}
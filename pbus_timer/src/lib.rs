@@ -1,61 +1,889 @@
 use pbus_config_handler::*;
-use std::thread;
-use std::time::Duration;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use pbus_db_manager::segments::{schema_version, write_segment};
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus, StateStore};
+use pbus_remotedb_manager::DbHandler;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, debug_span, error, info, instrument, warn, Instrument, Span};
 use utility::*;
 
+pub mod alerting;
+pub mod config_watcher;
+pub mod control;
+pub mod drill;
+pub mod events;
+pub mod freshness;
+pub mod metrics;
+pub mod onboarding;
+pub mod restore;
+pub mod retry;
+pub mod scrub;
+pub mod shutdown;
+
+use crate::alerting::Alerter;
+use crate::config_watcher::{reload_config, schedule_target, ConfigWatcher};
+use crate::control::{
+    ControlError, ControlServer, Pause, Request, Response, Schedule, ScheduledTarget, WorkerStatus,
+};
+use crate::events::{events, Event};
+use crate::freshness::local_freshness;
+use crate::metrics::metrics;
+use crate::retry::{backoff, circuit_open_until, record_outcome, should_retry};
+use crate::scrub::scrub_target;
+use crate::shutdown::{listen_for_signals, shutdown};
+
+/// Longest the worker sleeps before re-checking the schedule
+const MAX_IDLE: Duration = Duration::from_secs(10);
+
+/// Most rows written to one segment, a large table is captured in batches of
+/// this size that each show up as progress
+const BATCH_ROWS: u32 = 10_000;
+
 /// Main thread function for the timer
 ///
+/// Returns when the worker is told to stop or can't start, see `worker`.
+///
 /// # Arguments
-/// * `times` - A vector of time_handler::HitTargets
 /// * `base_mount_point` - The base mount point for the config file
+pub async fn worker_manager(base_mount_point: &str) -> Result<(), PbusError> {
+    let config = Config::read_config(base_mount_point)?;
+
+    let mut times: Vec<time_handler::HitTargets> = Vec::new();
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            schedule_target(
+                &mut times,
+                database,
+                target.get_name(),
+                target.get_enabled(),
+            );
+        }
+    }
+
+    worker(base_mount_point, config, &mut times).await
+}
+
+/// Hits due targets until the process exits or is told to stop
 ///
+/// Changes to the config file are picked up as they happen and applied to `times`
+/// without restarting the worker. Requests on the control socket are answered
+/// while the worker waits for the next target, see `control`. A `Shutdown`
+/// request, SIGTERM or SIGINT stop a run in progress after its current batch,
+/// see `shutdown`. Returns an error
+/// only if the catalog, state store, config watcher or control socket can't be
+/// opened, or the cursors can't be reconciled with the catalog, see
+/// `reconcile_cursors`; failed runs are handled as described at `run_cycle`.
+pub async fn worker(
+    base_mount_point: &str,
+    mut config: Config,
+    times: &mut Vec<time_handler::HitTargets>,
+) -> Result<(), PbusError> {
+    info!("Starting worker");
+    let catalog = Catalog::open(base_mount_point)?;
+    let state = StateStore::open(base_mount_point)?;
+    let mut watcher = ConfigWatcher::new(base_mount_point)?;
+    let mut control = ControlServer::bind(base_mount_point)?;
+    let mut alerter = Alerter::new(base_mount_point, &config)?;
+    reconcile_cursors(&catalog, &state, &config)?;
+    listen_for_signals()?;
+    let mut pauses: Vec<Pause> = Vec::new();
+    let mut queued: Vec<QueuedRun> = Vec::new();
+    let started_at = SystemTime::now();
 
-pub async fn worker_manager(base_mount_point: &str) {
+    let mut cycle = 0u64;
     loop {
-        let mut times: Vec<time_handler::HitTargets> = Vec::new();
-        let config = Config::read_config(base_mount_point).unwrap();
-        for Database in config.get_databases() {
-            for target in Database.get_targets() {
-                if &target.get_enabled() == &false {
-                    continue;
+        cycle += 1;
+        for run in queued.drain(..) {
+            run_queued(base_mount_point, &catalog, &state, &config, run).await;
+        }
+        let idle = run_cycle(base_mount_point, &catalog, &state, &alerter, &config, times)
+            .instrument(debug_span!("cycle", cycle))
+            .await;
+
+        // Anything that changes the schedule ends the wait, so the next cycle
+        // sees it
+        let wake_up = tokio::time::Instant::now() + idle;
+        loop {
+            tokio::select! {
+                // A `Shutdown` request also requests a shutdown, it is answered
+                // before the worker stops
+                biased;
+                Some(call) = control.next() => {
+                    let response = match &call.request {
+                        Request::Status => Response::Status(worker_status(
+                            &config, times, &pauses, started_at, cycle,
+                        )),
+                        Request::Reload => {
+                            let reloaded = reload_config(base_mount_point, &mut config, times);
+                            alerter.set_config(&config);
+                            times.retain(|time| !is_paused(&pauses, time));
+                            respond(reloaded.map(|_| Response::Done))
+                        }
+                        Request::Pause { database, target } => respond(
+                            pause(&config, times, &mut pauses, database, target.as_deref()),
+                        ),
+                        Request::Resume { database, target } => respond(
+                            resume(&config, times, &mut pauses, database, target.as_deref()),
+                        ),
+                        Request::RunNow { database, target } => respond(queue_run(
+                            base_mount_point,
+                            &catalog,
+                            &state,
+                            &config,
+                            &mut queued,
+                            database,
+                            target,
+                        )),
+                        Request::Shutdown => {
+                            info!("Stopping worker on request");
+                            call.reply(Response::Done);
+                            return Ok(());
+                        }
+                    };
+                    let changed = !matches!(call.request, Request::Status);
+                    call.reply(response);
+                    if changed {
+                        break;
+                    }
+                }
+                _ = watcher.changed() => {
+                    if let Err(e) = reload_config(base_mount_point, &mut config, times) {
+                        warn!("Rejected config change, keeping current config: {}", e);
+                    }
+                    alerter.set_config(&config);
+                    times.retain(|time| !is_paused(&pauses, time));
+                    break;
+                }
+                _ = shutdown().requested() => {
+                    info!("Stopping worker");
+                    return Ok(());
                 }
-                times.push(time_handler::HitTargets::new(
-                    target.get_name().to_string(),
-                    Database.database_name.to_string(),
-                    Duration::from_secs(Database.get_update_interval() * 1),
-                ));
+                _ = tokio::time::sleep_until(wake_up) => break,
             }
         }
-        worker(base_mount_point, &mut times).await;
     }
 }
 
-pub async fn worker(base_mount_point: &str, times: &mut Vec<time_handler::HitTargets>) {
-    println!("Starting Worker!");
-    let mut config = Config::read_config(base_mount_point).unwrap();
-    loop {
-        if check_config_update(base_mount_point).unwrap() {
-            println!("Config updated, restarting");
-            config.set_update(0);
-            config.write_config(base_mount_point).unwrap();
+/// Moves the cursor of every target up to the end of its latest segment
+///
+/// The catalog and the state store are separate databases, so a run that stops
+/// between recording a segment and saving the cursor leaves the cursor behind,
+/// and the next run would capture those rows again. A cursor ahead of the
+/// latest segment is only reported, the rows in between have no segment but
+/// moving the cursor back could capture rows a retention policy already
+/// expired.
+pub fn reconcile_cursors(
+    catalog: &Catalog,
+    state: &StateStore,
+    config: &Config,
+) -> Result<(), PbusError> {
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            let (database_name, target_name) = (&database.database_name, target.get_name());
+            let Some(segment) = catalog.get_latest_segment(database_name, target_name)? else {
+                continue;
+            };
+            let mut target_state = state.get_target_state(database_name, target_name)?;
+            if segment.cursor_end > target_state.last_id {
+                warn!(
+                    database = %database_name,
+                    target = %target_name,
+                    cursor = target_state.last_id,
+                    segment_end = segment.cursor_end,
+                    "Cursor is behind the latest segment, moving it up"
+                );
+                target_state.last_id = segment.cursor_end;
+                target_state.last_updated = segment.created_at;
+                state.set_target_state(database_name, target_name, &target_state)?;
+            } else if segment.cursor_end < target_state.last_id {
+                warn!(
+                    database = %database_name,
+                    target = %target_name,
+                    cursor = target_state.last_id,
+                    segment_end = segment.cursor_end,
+                    "Cursor is ahead of the latest segment, rows in between are not backed up"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A control request's outcome as its response
+fn respond(result: Result<Response, PbusError>) -> Response {
+    result.unwrap_or_else(|e| Response::Error(ControlError::from(&e)))
+}
+
+fn is_paused(pauses: &[Pause], time: &time_handler::HitTargets) -> bool {
+    pauses
+        .iter()
+        .any(|pause| pause.covers(&time.get_database_name(), &time.get_name()))
+}
+
+/// The configured database of a target, an error if either isn't configured
+fn find_target<'a>(
+    config: &'a Config,
+    database_name: &str,
+    target_name: &str,
+) -> Result<&'a Database, PbusError> {
+    config
+        .get_databases()
+        .iter()
+        .find(|database| {
+            database.database_name == database_name
+                && database
+                    .get_targets()
+                    .iter()
+                    .any(|target| target.get_name() == target_name)
+        })
+        .ok_or_else(|| {
+            PbusError::config(format!(
+                "target {}.{} is not configured",
+                database_name, target_name
+            ))
+        })
+}
+
+/// A backup a `RunNow` request started, run before the next cycle
+struct QueuedRun {
+    run_id: i64,
+    database_name: String,
+    target_name: String,
+}
+
+/// Records the run a `RunNow` request asks for and answers with its id, the
+/// worker runs it as soon as it stops waiting, which answering the request makes
+/// it do
+fn queue_run(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    config: &Config,
+    queued: &mut Vec<QueuedRun>,
+    database_name: &str,
+    target_name: &str,
+) -> Result<Response, PbusError> {
+    let database = find_target(config, database_name, target_name)?;
+    let run_id = start_backup(base_mount_point, catalog, state, database, target_name)?;
+    queued.push(QueuedRun {
+        run_id,
+        database_name: database_name.to_string(),
+        target_name: target_name.to_string(),
+    });
+    Ok(Response::Started { run_id })
+}
+
+async fn run_queued(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    config: &Config,
+    run: QueuedRun,
+) {
+    let result = match find_target(config, &run.database_name, &run.target_name) {
+        Ok(database) => {
+            run_backup(
+                base_mount_point,
+                catalog,
+                state,
+                database,
+                &run.target_name,
+                run.run_id,
+            )
+            .await
+        }
+        // Recorded, so the run doesn't stay running forever
+        Err(e) => catalog
+            .finish_run(
+                run.run_id,
+                RunStatus::Failed,
+                &RunStats::default(),
+                Some(&e.to_string()),
+            )
+            .and(Err(e)),
+    };
+    if let Err(e) = result {
+        warn!(
+            database = %run.database_name,
+            target = %run.target_name,
+            run_id = run.run_id,
+            "Requested run failed: {}",
+            e
+        );
+    }
+}
+
+fn find_database<'a>(config: &'a Config, database_name: &str) -> Result<&'a Database, PbusError> {
+    config
+        .get_databases()
+        .iter()
+        .find(|database| database.database_name == database_name)
+        .ok_or_else(|| PbusError::config(format!("database {} is not configured", database_name)))
+}
+
+fn pause(
+    config: &Config,
+    times: &mut Vec<time_handler::HitTargets>,
+    pauses: &mut Vec<Pause>,
+    database_name: &str,
+    target_name: Option<&str>,
+) -> Result<Response, PbusError> {
+    match target_name {
+        Some(target_name) => find_target(config, database_name, target_name)?,
+        None => find_database(config, database_name)?,
+    };
+    let pause = Pause {
+        database: database_name.to_string(),
+        target: target_name.map(str::to_string),
+    };
+    if !pauses.contains(&pause) {
+        info!(database = %database_name, target = target_name.unwrap_or("*"), "Paused");
+        pauses.push(pause);
+    }
+    times.retain(|time| !is_paused(pauses, time));
+    Ok(Response::Done)
+}
+
+/// Lifts the pauses the request covers, resuming a database also resumes its
+/// targets that were paused one by one
+fn resume(
+    config: &Config,
+    times: &mut Vec<time_handler::HitTargets>,
+    pauses: &mut Vec<Pause>,
+    database_name: &str,
+    target_name: Option<&str>,
+) -> Result<Response, PbusError> {
+    let database = find_database(config, database_name)?;
+    let lifted = |pause: &Pause| {
+        pause.database == database_name
+            && (target_name.is_none() || pause.target.as_deref() == target_name)
+    };
+    if !pauses.iter().any(lifted) {
+        let name = match target_name {
+            Some(target_name) => format!("{}.{}", database_name, target_name),
+            None => database_name.to_string(),
+        };
+        return Err(PbusError::config(format!("{} is not paused", name)));
+    }
+    pauses.retain(|pause| !lifted(pause));
+    info!(database = %database_name, target = target_name.unwrap_or("*"), "Resumed");
+
+    for target in database.get_targets() {
+        if target_name.is_some_and(|name| name != target.get_name())
+            || pauses
+                .iter()
+                .any(|pause| pause.covers(database_name, target.get_name()))
+        {
+            continue;
+        }
+        let listed = times.iter().any(|time| {
+            time.get_database_name() == database_name && time.get_name() == *target.get_name()
+        });
+        if !listed {
+            schedule_target(times, database, target.get_name(), target.get_enabled());
+        }
+    }
+    Ok(Response::Done)
+}
+
+fn worker_status(
+    config: &Config,
+    times: &[time_handler::HitTargets],
+    pauses: &[Pause],
+    started_at: SystemTime,
+    cycles: u64,
+) -> WorkerStatus {
+    let mut targets = Vec::new();
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            let time = times.iter().find(|time| {
+                time.get_database_name() == database.database_name
+                    && time.get_name() == *target.get_name()
+            });
+            let schedule = if !target.get_enabled() {
+                Schedule::Disabled
+            } else if pauses
+                .iter()
+                .any(|pause| pause.covers(&database.database_name, target.get_name()))
+            {
+                Schedule::Paused
+            } else if time.is_some() {
+                Schedule::Scheduled
+            } else {
+                Schedule::Suspended
+            };
+            targets.push(ScheduledTarget {
+                database: database.database_name.clone(),
+                target: target.get_name().clone(),
+                schedule,
+                next_hit: time
+                    .map(|time| humantime::format_rfc3339_seconds(time.get_next_hit()).to_string()),
+            });
+        }
+    }
+    WorkerStatus {
+        pid: std::process::id(),
+        started_at: humantime::format_rfc3339_seconds(started_at).to_string(),
+        cycles,
+        targets,
+    }
+}
+
+/// Backs up every due target once and returns how long to sleep until the next
+/// one is due
+///
+/// Databases whose circuit is open are skipped until their next probe, see
+/// `retry::record_outcome`. A failed run is handled by the class of its error:
+/// * connection - the other targets of the database are skipped this cycle, all
+///   of them are tried again at their next hit
+/// * retryable query - tried again at the next hit
+/// * config, schema or other query errors - the target is taken off the
+///   schedule until its config changes, as running it again would fail the same
+///   way
+/// * storage - the cycle stops and the worker backs off for `MAX_IDLE`, the
+///   remaining targets stay due
+/// * cancelled - the cycle stops the same way, the worker is shutting down
+///
+/// Scheduled targets of reachable databases are then scrubbed once their last
+/// scrub is older than the database's `scrub_interval`, see `scrub_due_targets`.
+async fn run_cycle(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    alerter: &Alerter,
+    config: &Config,
+    times: &mut Vec<time_handler::HitTargets>,
+) -> Duration {
+    let due = times
+        .iter()
+        .filter(|time| time.get_next_hit() < SystemTime::now())
+        .count();
+    metrics().record_schedule(times.len(), due);
+
+    let mut unreachable: Vec<String> = Vec::new();
+    let mut suspended: Vec<(String, String)> = Vec::new();
+    let mut back_off = false;
+    for time in times.iter_mut() {
+        if time.get_next_hit() >= SystemTime::now() {
+            continue;
+        }
+        let database = match config
+            .get_databases()
+            .iter()
+            .find(|database| database.database_name == time.get_database_name())
+        {
+            Some(database) => database,
+            None => continue,
+        };
+
+        if unreachable.contains(&database.database_name) {
+            debug!(target = %time.get_name(), "Skipped, database is unreachable");
+        } else if let Some(next_probe) = circuit_open_until(state, database, SystemTime::now()) {
+            debug!(
+                target = %time.get_name(),
+                next_probe = %humantime::format_rfc3339_seconds(next_probe),
+                "Skipped, circuit of the database is open"
+            );
+        } else if let Err(e) =
+            backup_target(base_mount_point, catalog, state, database, &time.get_name()).await
+        {
+            let (database_name, target_name) = (&database.database_name, time.get_name());
+            match &e {
+                PbusError::Connection { .. } => {
+                    if e.is_retryable() {
+                        warn!(database = %database_name, "Backup failed, retrying at the next hit: {}", e);
+                    } else {
+                        error!(database = %database_name, "Backup failed: {}", e);
+                    }
+                    unreachable.push(database_name.clone());
+                }
+                PbusError::Query { .. } if e.is_retryable() => {
+                    warn!(database = %database_name, target = %target_name, "Backup failed, retrying at the next hit: {}", e);
+                }
+                PbusError::Config(_) | PbusError::Query { .. } | PbusError::Schema(_) => {
+                    error!(database = %database_name, target = %target_name, "Backup failed, suspending target until its config changes: {}", e);
+                    suspended.push((database_name.clone(), target_name.clone()));
+                }
+                PbusError::Storage(_) => {
+                    error!(database = %database_name, target = %target_name, "Backup failed, backing off: {}", e);
+                    back_off = true;
+                }
+                PbusError::Cancelled => {
+                    info!(database = %database_name, target = %target_name, "Backup cancelled, the worker is stopping");
+                    back_off = true;
+                }
+            }
+        }
+        alerter
+            .check_failures(catalog, database, &time.get_name())
+            .await;
+
+        let now = SystemTime::now();
+        match local_freshness(catalog, state, database, &time.get_name(), now) {
+            Ok(freshness) if freshness.rpo_breached => warn!(
+                database = %freshness.database_name,
+                target = %freshness.target_name,
+                rpo = %humantime::format_duration(freshness.rpo.unwrap_or_default()),
+                age = ?freshness.age.map(|age| age.as_secs()),
+                "Target is past its RPO"
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(target = %time.get_name(), "Freshness check failed: {}", e),
+        }
+        time.set_last_hit(now);
+        time.set_next_hit(now + time.get_interval());
+        debug!(target = %time.get_name(), next_hit = ?time.get_next_hit(), "Scheduled next hit");
+
+        if back_off {
             break;
         }
-        for time in times.iter_mut() {
-            if time.get_next_hit() < SystemTime::now() {
-                println!("Hitting target: {}", time.get_name());
-                println!("Next hit: {:?}", time.get_next_hit());
-                // update config
+    }
+    times.retain(|time| !suspended.contains(&(time.get_database_name(), time.get_name())));
+
+    if !back_off {
+        for database in config.get_databases() {
+            if !unreachable.contains(&database.database_name) {
+                scrub_due_targets(base_mount_point, catalog, state, alerter, database, times).await;
+            }
+        }
+    }
+
+    // Suspended targets are still checked, so they raise freshness alerts
+    let now = SystemTime::now();
+    for database in config.get_databases() {
+        for target in database.get_targets() {
+            if !target.get_enabled() {
+                continue;
+            }
+            if let Ok(freshness) = local_freshness(catalog, state, database, target.get_name(), now)
+            {
+                metrics().record_freshness(&freshness);
+                alerter.check_freshness(database, &freshness).await;
+            }
+        }
+    }
+    for database in config.get_databases() {
+        if let Ok(circuit) = state.get_circuit(&database.database_name) {
+            metrics().record_circuit(&database.database_name, circuit.is_open());
+        }
+    }
+    alerter.check_disk_usage(config).await;
 
-                config
-                    .get_database(&time.get_database_name())
-                    .unwrap()
-                    .set_target_last_checked(time.get_name().to_string(), SystemTime::now());
+    if back_off {
+        return MAX_IDLE;
+    }
+    times
+        .iter()
+        .map(|time| {
+            time.get_next_hit()
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        })
+        .min()
+        .unwrap_or(MAX_IDLE)
+        .min(MAX_IDLE)
+}
 
-                config.write_config(base_mount_point).unwrap();
+/// Scrubs the scheduled targets of a database that weren't scrubbed for its
+/// `scrub_interval`, and raises or resolves their verification alerts
+///
+/// Stops at the first target whose scrub can't reach the database, the others
+/// are tried again next cycle.
+async fn scrub_due_targets(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    alerter: &Alerter,
+    database: &Database,
+    times: &[time_handler::HitTargets],
+) {
+    let Some(interval) = database.scrub_interval.map(Duration::from_secs) else {
+        return;
+    };
+    if circuit_open_until(state, database, SystemTime::now()).is_some() {
+        return;
+    }
+    for target in database.get_targets() {
+        let scheduled = times.iter().any(|time| {
+            time.get_database_name() == database.database_name
+                && time.get_name() == *target.get_name()
+        });
+        if !scheduled {
+            continue;
+        }
+        let due = match catalog.get_last_run(
+            &database.database_name,
+            target.get_name(),
+            RunKind::Scrub,
+        ) {
+            Ok(last) => last.is_none_or(|run| run.started_at + interval <= SystemTime::now()),
+            Err(e) => {
+                warn!(target = %target.get_name(), "Can't read the last scrub: {}", e);
+                continue;
             }
+        };
+        if !due {
+            continue;
+        }
+        match scrub_target(base_mount_point, catalog, database, target.get_name()).await {
+            Ok(report) => {
+                alerter
+                    .check_verification(database, target.get_name(), &report.problems())
+                    .await
+            }
+            Err(e @ PbusError::Connection { .. }) => {
+                warn!(database = %database.database_name, "Scrubs skipped, database is unreachable: {}", e);
+                return;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Captures the rows of a target added since its last id into new segments
+///
+/// Retryable errors are tried again within the run as the database's `retry`
+/// policy allows, carrying on after the last batch that was written. Every call
+/// is recorded as a run in the catalog, failed or not, updates the circuit of the
+/// database and stamps the target's `last_checked` in the state store. Batches
+/// written before a run fails are kept and move the target's cursor. Progress is
+/// published on the `events()` bus. Returns what the run captured, or
+/// `PbusError::Cancelled` if a shutdown was requested between batches or
+/// retries.
+pub async fn backup_target(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+) -> Result<RunStats, PbusError> {
+    let run_id = start_backup(base_mount_point, catalog, state, database, target_name)?;
+    run_backup(
+        base_mount_point,
+        catalog,
+        state,
+        database,
+        target_name,
+        run_id,
+    )
+    .await
+}
+
+/// Records a backup run of a target at its current cursor and announces it,
+/// returning the run's id; `run_backup` does the actual work
+pub fn start_backup(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+) -> Result<i64, PbusError> {
+    let cursor_before = state
+        .get_target_state(&database.database_name, target_name)?
+        .last_id;
+    let run_id = catalog.start_run(
+        &database.database_name,
+        target_name,
+        RunKind::Backup,
+        cursor_before,
+    )?;
+    events().publish(
+        base_mount_point,
+        Event::RunStarted {
+            database: database.database_name.clone(),
+            target: target_name.to_string(),
+            run_id,
+            cursor: cursor_before,
+        },
+    );
+    Ok(run_id)
+}
+
+/// Runs a backup recorded with `start_backup`, see `backup_target`
+#[instrument(
+    name = "backup",
+    skip_all,
+    fields(database = %database.database_name, target = %target_name, run_id)
+)]
+pub async fn run_backup(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+    run_id: i64,
+) -> Result<RunStats, PbusError> {
+    let mut target_state = state.get_target_state(&database.database_name, target_name)?;
+    let cursor_before = target_state.last_id;
+    Span::current().record("run_id", run_id);
+    info!(cursor = cursor_before, "Starting backup run");
+    let started = Instant::now();
+
+    let mut stats = RunStats {
+        cursor_after: cursor_before,
+        ..RunStats::default()
+    };
+    let result = loop {
+        let result = capture_target(
+            base_mount_point,
+            catalog,
+            state,
+            run_id,
+            database,
+            target_name,
+            &mut stats,
+        )
+        .await;
+        let e = match result {
+            Ok(()) => break Ok(stats.clone()),
+            Err(e) => e,
+        };
+        let retrying = should_retry(&database.retry, &e, stats.retries);
+        events().publish(
+            base_mount_point,
+            Event::Error {
+                database: database.database_name.clone(),
+                target: target_name.to_string(),
+                run_id,
+                class: e.class().to_string(),
+                message: e.to_string(),
+                retrying,
+            },
+        );
+        if !retrying {
+            break Err(e);
+        }
+        let delay = backoff(&database.retry, stats.retries);
+        stats.retries += 1;
+        warn!(
+            attempt = stats.retries,
+            delay = %humantime::format_duration(delay),
+            "Attempt failed, retrying: {}",
+            e
+        );
+        if let Err(cancelled) = shutdown().sleep(delay).await {
+            break Err(cancelled);
+        }
+    };
+
+    metrics().record_run(
+        &database.database_name,
+        target_name,
+        result.as_ref().ok(),
+        started.elapsed(),
+    );
+
+    let status = match &result {
+        Ok(_) => RunStatus::Succeeded,
+        Err(_) => RunStatus::Failed,
+    };
+    let error = result.as_ref().err().map(|e| e.to_string());
+    catalog.finish_run(run_id, status, &stats, error.as_deref())?;
+    events().publish(
+        base_mount_point,
+        Event::RunFinished {
+            database: database.database_name.clone(),
+            target: target_name.to_string(),
+            run_id,
+            status: status.as_str().to_string(),
+            rows_captured: stats.rows_captured,
+            bytes_written: stats.bytes_written,
+            cursor: stats.cursor_after,
+            retries: stats.retries,
+        },
+    );
+
+    let now = SystemTime::now();
+    record_outcome(state, database, &result, now);
+    if stats.cursor_after != cursor_before {
+        target_state.last_id = stats.cursor_after;
+        target_state.last_updated = now;
+    }
+    target_state.last_checked = now;
+    state.set_target_state(&database.database_name, target_name, &target_state)?;
+
+    result
+}
+
+/// Captures the rows past `stats.cursor_after` in batches of `BATCH_ROWS`, one
+/// segment each, until the table has no more
+///
+/// `stats` and the cursor in `target_state` are advanced and saved after every
+/// batch that made it into the catalog, so a failed attempt or a crash keeps
+/// what it wrote.
+async fn capture_target(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    state: &StateStore,
+    run_id: i64,
+    database: &Database,
+    target_name: &str,
+    stats: &mut RunStats,
+) -> Result<(), PbusError> {
+    let target = database
+        .get_targets()
+        .iter()
+        .find(|target| target.get_name() == target_name)
+        .ok_or_else(|| PbusError::config(format!("Target {} not found", target_name)))?;
+
+    let _connection = metrics().connection(&database.database_name);
+    let handler = DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        &database.database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await?;
+    let run = catalog
+        .get_run(run_id)?
+        .ok_or_else(|| PbusError::storage("Run vanished from catalog"))?;
+
+    loop {
+        shutdown().check()?;
+        let last_id = stats.cursor_after;
+        let lsn_start = handler.get_current_lsn().await?;
+        let (rows, new_last_id) = handler.get_rows(target, last_id, BATCH_ROWS).await?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut segment = write_segment(
+            base_mount_point,
+            &run,
+            last_id,
+            new_last_id,
+            &rows,
+            schema_version(target),
+        )?;
+        segment.lsn_start = Some(lsn_start);
+        segment.lsn_end = Some(handler.get_current_lsn().await?);
+        catalog.add_segment(&segment)?;
+        let mut target_state = state.get_target_state(&database.database_name, target_name)?;
+        target_state.last_id = new_last_id;
+        target_state.last_updated = SystemTime::now();
+        state.set_target_state(&database.database_name, target_name, &target_state)?;
+        stats.cursor_after = new_last_id;
+        stats.rows_captured += segment.row_count;
+        stats.bytes_written += segment.size_bytes;
+        info!(
+            rows = rows.len(),
+            bytes = segment.size_bytes,
+            cursor = new_last_id,
+            "Captured rows"
+        );
+        events().publish(
+            base_mount_point,
+            Event::BatchWritten {
+                database: database.database_name.clone(),
+                target: target_name.to_string(),
+                run_id,
+                rows: segment.row_count,
+                bytes: segment.size_bytes,
+                cursor: stats.cursor_after,
+            },
+        );
+
+        if rows.len() < BATCH_ROWS as usize {
+            return Ok(());
         }
-        thread::sleep(Duration::from_secs(10));
     }
 }
@@ -0,0 +1,248 @@
+use pbus_db_manager::RunStats;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::freshness::TargetFreshness;
+
+const TARGET_LABELS: [&str; 2] = ["database", "target"];
+
+/// Prometheus metrics of the backup worker
+///
+/// There is one instance per process, see `metrics()`.
+pub struct Metrics {
+    registry: Registry,
+    rows_captured: IntCounterVec,
+    bytes_written: IntCounterVec,
+    runs: IntCounterVec,
+    failures: IntCounterVec,
+    run_duration: HistogramVec,
+    scheduled_targets: IntGauge,
+    due_targets: IntGauge,
+    connections: IntGaugeVec,
+    cursor: IntGaugeVec,
+    backup_age: IntGaugeVec,
+    rpo: IntGaugeVec,
+    rpo_breached: IntGaugeVec,
+    circuit_open: IntGaugeVec,
+}
+
+/// The process wide metrics
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("pbus".to_string()), None).unwrap();
+
+        let rows_captured = IntCounterVec::new(
+            Opts::new("rows_captured_total", "Rows captured from the source"),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let bytes_written = IntCounterVec::new(
+            Opts::new("bytes_written_total", "Bytes written to segment files"),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let runs = IntCounterVec::new(
+            Opts::new("runs_total", "Finished backup runs by outcome"),
+            &["database", "target", "status"],
+        )
+        .unwrap();
+        let failures = IntCounterVec::new(
+            Opts::new("run_failures_total", "Failed backup runs"),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let run_duration = HistogramVec::new(
+            HistogramOpts::new("run_duration_seconds", "Duration of backup runs").buckets(vec![
+                0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+            ]),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let scheduled_targets =
+            IntGauge::new("scheduled_targets", "Targets on the schedule").unwrap();
+        let due_targets = IntGauge::new(
+            "scheduler_queue_depth",
+            "Targets that were due on the last pass of the scheduler",
+        )
+        .unwrap();
+        let connections = IntGaugeVec::new(
+            Opts::new("source_connections", "Open connections to source databases"),
+            &["database"],
+        )
+        .unwrap();
+        let cursor = IntGaugeVec::new(
+            Opts::new(
+                "captured_cursor",
+                "Cursor the backup of a target has reached",
+            ),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let backup_age = IntGaugeVec::new(
+            Opts::new(
+                "backup_age_seconds",
+                "Seconds since the last successful run, -1 if there never was one",
+            ),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let rpo = IntGaugeVec::new(
+            Opts::new("rpo_seconds", "Recovery point objective of a target"),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let rpo_breached = IntGaugeVec::new(
+            Opts::new("rpo_breached", "1 if the target is past its RPO"),
+            &TARGET_LABELS,
+        )
+        .unwrap();
+        let circuit_open = IntGaugeVec::new(
+            Opts::new(
+                "circuit_open",
+                "1 if the database is paused by its circuit breaker",
+            ),
+            &["database"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(rows_captured.clone())).unwrap();
+        registry.register(Box::new(bytes_written.clone())).unwrap();
+        registry.register(Box::new(runs.clone())).unwrap();
+        registry.register(Box::new(failures.clone())).unwrap();
+        registry.register(Box::new(run_duration.clone())).unwrap();
+        registry
+            .register(Box::new(scheduled_targets.clone()))
+            .unwrap();
+        registry.register(Box::new(due_targets.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(cursor.clone())).unwrap();
+        registry.register(Box::new(backup_age.clone())).unwrap();
+        registry.register(Box::new(rpo.clone())).unwrap();
+        registry.register(Box::new(rpo_breached.clone())).unwrap();
+        registry.register(Box::new(circuit_open.clone())).unwrap();
+
+        Metrics {
+            registry,
+            rows_captured,
+            bytes_written,
+            runs,
+            failures,
+            run_duration,
+            scheduled_targets,
+            due_targets,
+            connections,
+            cursor,
+            backup_age,
+            rpo,
+            rpo_breached,
+            circuit_open,
+        }
+    }
+
+    pub fn record_run(
+        &self,
+        database_name: &str,
+        target_name: &str,
+        stats: Option<&RunStats>,
+        duration: Duration,
+    ) {
+        let labels = [database_name, target_name];
+        self.run_duration
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+
+        match stats {
+            Some(stats) => {
+                self.runs
+                    .with_label_values(&[database_name, target_name, "succeeded"])
+                    .inc();
+                self.rows_captured
+                    .with_label_values(&labels)
+                    .inc_by(stats.rows_captured as u64);
+                self.bytes_written
+                    .with_label_values(&labels)
+                    .inc_by(stats.bytes_written as u64);
+                self.cursor
+                    .with_label_values(&labels)
+                    .set(stats.cursor_after);
+            }
+            None => {
+                self.runs
+                    .with_label_values(&[database_name, target_name, "failed"])
+                    .inc();
+                self.failures.with_label_values(&labels).inc();
+            }
+        }
+    }
+
+    pub fn record_freshness(&self, freshness: &TargetFreshness) {
+        let labels = [
+            freshness.database_name.as_str(),
+            freshness.target_name.as_str(),
+        ];
+        self.backup_age
+            .with_label_values(&labels)
+            .set(freshness.age.map(|age| age.as_secs() as i64).unwrap_or(-1));
+        match freshness.rpo {
+            Some(rpo) => self
+                .rpo
+                .with_label_values(&labels)
+                .set(rpo.as_secs() as i64),
+            None => {
+                let _ = self.rpo.remove_label_values(&labels);
+            }
+        }
+        self.rpo_breached
+            .with_label_values(&labels)
+            .set(freshness.rpo_breached as i64);
+        self.cursor
+            .with_label_values(&labels)
+            .set(freshness.captured_cursor);
+    }
+
+    pub fn record_circuit(&self, database_name: &str, open: bool) {
+        self.circuit_open
+            .with_label_values(&[database_name])
+            .set(open as i64);
+    }
+
+    pub fn record_schedule(&self, scheduled: usize, due: usize) {
+        self.scheduled_targets.set(scheduled as i64);
+        self.due_targets.set(due as i64);
+    }
+
+    /// Counts a connection to `database_name` as open until the guard is dropped
+    pub fn connection(&self, database_name: &str) -> ConnectionGuard {
+        let gauge = self.connections.with_label_values(&[database_name]);
+        gauge.inc();
+        ConnectionGuard { gauge }
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub struct ConnectionGuard {
+    gauge: prometheus::IntGauge,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
@@ -0,0 +1,191 @@
+use pbus_config_handler::validation::{is_cursor_type, CURSOR_COLUMN};
+use pbus_config_handler::*;
+use pbus_remotedb_manager::DbHandler;
+use std::collections::HashMap;
+use utility::{PbusError, Target};
+
+/// A table found in a database being added, and whether it can be backed up
+#[derive(Debug, Clone)]
+pub struct TableCandidate {
+    pub name: String,
+    /// Column name to data type
+    pub fields: HashMap<String, String>,
+    pub primary_key: Vec<String>,
+    /// Column the capture would page through, `None` if the table has no usable one
+    pub cursor_column: Option<String>,
+    /// Why the table can't be backed up, if it can't
+    pub problem: Option<String>,
+}
+
+impl TableCandidate {
+    pub fn is_selectable(&self) -> bool {
+        self.cursor_column.is_some()
+    }
+
+    pub fn to_target(&self) -> Target {
+        Target::construct(self.name.clone(), self.fields.clone(), true)
+    }
+}
+
+/// Connects to a database that is not in the config yet and lists its tables
+///
+/// Fails if the connection can't be made, so this doubles as the connection test.
+pub async fn discover_tables(
+    base_mount_point: &str,
+    database: &Database,
+) -> Result<Vec<TableCandidate>, PbusError> {
+    let handler = DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        &database.database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await
+    .map_err(|e| e.context(format!("can't connect to {}", database.database_name)))?;
+
+    let mut tables = handler.get_tables().await?;
+    tables.sort();
+
+    let mut candidates = Vec::new();
+    for name in tables {
+        let fields: HashMap<String, String> = handler
+            .get_table_fields(name.clone())
+            .await?
+            .into_iter()
+            .map(|field| (field.name, field.data_type))
+            .collect();
+        let primary_key = handler.get_primary_key(name.clone()).await?;
+        let (cursor_column, problem) = propose_cursor(&fields, &primary_key);
+
+        candidates.push(TableCandidate {
+            name,
+            fields,
+            primary_key,
+            cursor_column,
+            problem,
+        });
+    }
+
+    Ok(candidates)
+}
+
+/// Picks the cursor column of a table, or explains why there is none
+///
+/// The capture query only pages through `CURSOR_COLUMN`, no other column can be
+/// used. It should be unique as well, a primary key that doesn't cover it gets a
+/// warning.
+fn propose_cursor(
+    fields: &HashMap<String, String>,
+    primary_key: &[String],
+) -> (Option<String>, Option<String>) {
+    match fields.get(CURSOR_COLUMN) {
+        None => (
+            None,
+            Some(format!(
+                "no `{}` column, backups only page through `{}`",
+                CURSOR_COLUMN, CURSOR_COLUMN
+            )),
+        ),
+        Some(data_type) if !is_cursor_type(data_type) => (
+            None,
+            Some(format!(
+                "`{}` is `{}`, backups only page through an integer `{}`",
+                CURSOR_COLUMN, data_type, CURSOR_COLUMN
+            )),
+        ),
+        Some(_) if primary_key != [CURSOR_COLUMN] => (
+            Some(CURSOR_COLUMN.to_string()),
+            Some(format!(
+                "`{}` is not the primary key, rows sharing an id may be skipped",
+                CURSOR_COLUMN
+            )),
+        ),
+        Some(_) => (Some(CURSOR_COLUMN.to_string()), None),
+    }
+}
+
+/// Fills `database` with the selected tables as targets and adds it to the config
+///
+/// Every name in `selected` must be a selectable candidate. The config is edited
+/// under the config lock and checked before it is written.
+pub fn add_database(
+    base_mount_point: &str,
+    mut database: Database,
+    candidates: &[TableCandidate],
+    selected: &[String],
+) -> Result<Config, PbusError> {
+    database.targets.clear();
+    for name in selected {
+        let candidate = candidates
+            .iter()
+            .find(|candidate| &candidate.name == name)
+            .ok_or_else(|| PbusError::config(format!("table {} was not found", name)))?;
+        if !candidate.is_selectable() {
+            return Err(PbusError::schema(format!(
+                "table {} can't be backed up: {}",
+                name,
+                candidate.problem.clone().unwrap_or_default()
+            )));
+        }
+        database.add_target(candidate.to_target());
+    }
+
+    Config::edit_config(base_mount_point, |config| {
+        if config
+            .get_database_names()
+            .contains(&database.database_name)
+        {
+            return Err(PbusError::config(format!(
+                "database {} is already configured",
+                database.database_name
+            )));
+        }
+        config.add_database(database);
+
+        let document = serde_json::to_string(config).map_err(PbusError::config)?;
+        validation::validate_config_str(&document)?;
+        Ok(())
+    })
+}
+
+/// Adds a table found by `discover_tables` as a target of a configured database
+///
+/// Like `add_database`, the config is edited under the config lock and checked
+/// before it is written.
+pub fn add_target(
+    base_mount_point: &str,
+    database_name: &str,
+    candidate: &TableCandidate,
+) -> Result<Config, PbusError> {
+    if !candidate.is_selectable() {
+        return Err(PbusError::schema(format!(
+            "table {} can't be backed up: {}",
+            candidate.name,
+            candidate.problem.clone().unwrap_or_default()
+        )));
+    }
+
+    Config::edit_config(base_mount_point, |config| {
+        let database = config
+            .get_database(&database_name.to_string())
+            .ok_or_else(|| {
+                PbusError::config(format!("database {} is not configured", database_name))
+            })?;
+        if database
+            .get_targets()
+            .iter()
+            .any(|target| target.get_name() == &candidate.name)
+        {
+            return Err(PbusError::config(format!(
+                "target {} is already configured",
+                candidate.name
+            )));
+        }
+        database.add_target(candidate.to_target());
+
+        let document = serde_json::to_string(config).map_err(PbusError::config)?;
+        validation::validate_config_str(&document)?;
+        Ok(())
+    })
+}
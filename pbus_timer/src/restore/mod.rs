@@ -0,0 +1,156 @@
+use pbus_config_handler::{validation, Config};
+use pbus_db_manager::segments::{read_segment, verify_segment};
+use pbus_db_manager::{Catalog, Segment};
+use pbus_remotedb_manager::DbHandler;
+use serde_json::Value;
+use utility::PbusError;
+
+/// What replaying the segments of a target will read
+pub enum Replay {
+    /// The segments to read in cursor order, see `segment_rows`, and the cursor
+    /// ranges `(from, to]` no segment covers
+    Segments {
+        segments: Vec<Segment>,
+        gaps: Vec<(i64, i64)>,
+    },
+    /// A segment doesn't match its checksum, nothing should be restored
+    Corrupt(Segment),
+}
+
+impl Replay {
+    /// Rows the segments hold, some may be past the cursor the replay stops at
+    /// or already read from an overlapping segment
+    pub fn row_count(&self) -> usize {
+        match self {
+            Replay::Segments { segments, .. } => segments
+                .iter()
+                .map(|segment| segment.row_count as usize)
+                .sum(),
+            Replay::Corrupt(_) => 0,
+        }
+    }
+}
+
+/// Picks the active segments of a target that hold cursors up to and including
+/// `to_cursor`
+///
+/// Every segment is checked against its checksum first, so a corrupt one stops
+/// the replay before any rows are used. The rows are read one segment at a time
+/// with `segment_rows`, so a large target never has to fit into memory.
+///
+/// Segments that overlap the ones before them, as left behind by a run that
+/// crashed before saving its cursor, are trimmed to the cursors not read yet,
+/// and dropped if they hold none.
+pub fn replay_segments(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    database_name: &str,
+    target_name: &str,
+    to_cursor: Option<i64>,
+) -> Result<Replay, PbusError> {
+    let to_cursor = to_cursor.unwrap_or(i64::MAX);
+    let segments: Vec<_> = catalog
+        .get_segments(database_name, target_name)?
+        .into_iter()
+        .filter(|segment| segment.cursor_start < to_cursor)
+        .collect();
+    if segments.is_empty() {
+        return Err(PbusError::storage(format!(
+            "no segments for {}.{}",
+            database_name, target_name
+        )));
+    }
+
+    let mut replayed = Vec::with_capacity(segments.len());
+    let mut gaps = Vec::new();
+    let mut cursor = segments[0].cursor_start;
+    for mut segment in segments {
+        if !verify_segment(base_mount_point, &segment)? {
+            return Ok(Replay::Corrupt(segment));
+        }
+        if segment.cursor_end <= cursor {
+            continue;
+        }
+        if segment.cursor_start > cursor {
+            gaps.push((cursor, segment.cursor_start));
+        }
+        // Rows up to `cursor` were read from the segments before
+        segment.cursor_start = segment.cursor_start.max(cursor);
+        cursor = segment.cursor_end;
+        replayed.push(segment);
+    }
+
+    Ok(Replay::Segments {
+        segments: replayed,
+        gaps,
+    })
+}
+
+/// Rows of a replayed segment past its `cursor_start`, with a cursor up to and
+/// including `to_cursor`
+pub fn segment_rows(
+    base_mount_point: &str,
+    segment: &Segment,
+    to_cursor: Option<i64>,
+) -> Result<Vec<Value>, PbusError> {
+    let to_cursor = to_cursor.unwrap_or(i64::MAX);
+    let mut rows = read_segment(base_mount_point, segment)?;
+    rows.retain(|row| {
+        let cursor = row[validation::CURSOR_COLUMN].as_i64().unwrap_or(i64::MIN);
+        cursor > segment.cursor_start && cursor <= to_cursor
+    });
+    Ok(rows)
+}
+
+/// Rows read from the segments and inserted by `restore_segments`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Restored {
+    pub rows: usize,
+    /// The others were already present
+    pub inserted: u64,
+}
+
+/// Inserts the replayed rows of `target_name` into the configured database
+/// `into`, one transaction per segment
+///
+/// The table of the target must exist there. Rows that are already present are
+/// skipped. If a segment fails, the segments before it stay restored.
+pub async fn restore_segments(
+    base_mount_point: &str,
+    config: &Config,
+    into: &str,
+    database_name: &str,
+    target_name: &str,
+    segments: &[Segment],
+    to_cursor: Option<i64>,
+) -> Result<Restored, PbusError> {
+    let database = config
+        .get_databases()
+        .iter()
+        .find(|database| database.database_name == into)
+        .ok_or_else(|| PbusError::config(format!("database {} is not configured", into)))?;
+    let target = config
+        .get_database_targets(database_name.to_string())
+        .and_then(|targets| {
+            targets
+                .iter()
+                .find(|target| target.get_name() == target_name)
+        })
+        .ok_or_else(|| PbusError::config(format!("target {} is not configured", target_name)))?;
+
+    let mut handler = DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        &database.database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await?;
+    let mut restored = Restored::default();
+    for segment in segments {
+        let rows = segment_rows(base_mount_point, segment, to_cursor)?;
+        restored.inserted += handler.insert_rows(target, &rows).await?;
+        restored.rows += rows.len();
+    }
+    Ok(restored)
+}
@@ -0,0 +1,257 @@
+use pbus_config_handler::{Database, RetryPolicy};
+use pbus_db_manager::StateStore;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use utility::PbusError;
+
+/// Wait before retry number `retry` of a run, counted from 0
+///
+/// Up to the policy's `jitter` share of the wait is taken off at random.
+pub fn backoff(policy: &RetryPolicy, retry: u32) -> Duration {
+    let jitter = policy.jitter.clamp(0.0, 1.0) * fastrand::f64();
+    policy.backoff(retry).mul_f64(1.0 - jitter)
+}
+
+/// Whether a run that failed with `e` after `retries` retries is tried again
+///
+/// Only retryable errors are, and only while the policy has attempts left.
+pub fn should_retry(policy: &RetryPolicy, e: &PbusError, retries: u32) -> bool {
+    e.is_retryable() && retries + 1 < policy.max_attempts
+}
+
+/// Whether an error counts against the circuit of its database
+///
+/// Only trouble with the database itself does; a broken target or a full disk
+/// says nothing about whether the database is up.
+pub fn trips_circuit(e: &PbusError) -> bool {
+    match e {
+        PbusError::Connection { .. } => true,
+        PbusError::Query { retryable, .. } => *retryable,
+        _ => false,
+    }
+}
+
+/// When the next probe of an open circuit is due, `None` if runs of the database
+/// may go ahead
+///
+/// Once the probe is due this returns `None` and the next run is the probe.
+pub fn circuit_open_until(
+    state: &StateStore,
+    database: &Database,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    let circuit = match state.get_circuit(&database.database_name) {
+        Ok(circuit) => circuit,
+        Err(e) => {
+            warn!(database = %database.database_name, "Can't read circuit state: {}", e);
+            return None;
+        }
+    };
+    match circuit.next_probe {
+        Some(next_probe) if circuit.is_open() && next_probe > now => Some(next_probe),
+        Some(_) if circuit.is_open() => {
+            info!(database = %database.database_name, "Probing database with an open circuit");
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Updates the circuit of a database with the outcome of a run
+///
+/// A success closes it, and failures that trip it count up until the
+/// threshold of the database opens it. A failed probe keeps it open for another
+/// `probe_interval`.
+pub fn record_outcome<T>(
+    state: &StateStore,
+    database: &Database,
+    result: &Result<T, PbusError>,
+    now: SystemTime,
+) {
+    let database_name = &database.database_name;
+    let updated = state
+        .get_circuit(database_name)
+        .and_then(|mut circuit| match result {
+            Ok(_) => {
+                if circuit.is_open() {
+                    info!(database = %database_name, "Circuit closed, database recovered");
+                }
+                state.remove_circuit(database_name)
+            }
+            Err(e) if trips_circuit(e) => {
+                let policy = &database.circuit_breaker;
+                circuit.failures += 1;
+                circuit.last_error = Some(e.to_string());
+                if policy.failure_threshold > 0 && circuit.failures >= policy.failure_threshold {
+                    let next_probe = now + Duration::from_secs(policy.probe_interval);
+                    if !circuit.is_open() {
+                        warn!(
+                            database = %database_name,
+                            failures = circuit.failures,
+                            next_probe = %humantime::format_rfc3339_seconds(next_probe),
+                            "Circuit opened, pausing database"
+                        );
+                        circuit.opened_at = Some(now);
+                    }
+                    circuit.next_probe = Some(next_probe);
+                }
+                state.set_circuit(database_name, &circuit)
+            }
+            Err(_) => Ok(()),
+        });
+    if let Err(e) = updated {
+        warn!(database = %database_name, "Can't store circuit state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pbus_config_handler::{CircuitBreakerPolicy, SecretRef};
+    use tempfile::TempDir;
+
+    fn database() -> Database {
+        let mut database = Database::new(
+            "localhost".to_string(),
+            5432,
+            "postgres".to_string(),
+            "shop".to_string(),
+            SecretRef::Plain("password".to_string()),
+            Vec::new(),
+            60,
+            SystemTime::UNIX_EPOCH,
+        );
+        database.circuit_breaker = CircuitBreakerPolicy {
+            failure_threshold: 3,
+            probe_interval: 60,
+        };
+        database
+    }
+
+    fn unreachable() -> Result<(), PbusError> {
+        Err(PbusError::Connection {
+            message: "connection refused".to_string(),
+            retryable: true,
+        })
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: 1,
+            max_backoff: 10,
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+        let waits: Vec<u64> = (0..6)
+            .map(|retry| backoff(&policy, retry).as_secs())
+            .collect();
+        assert_eq!(waits, [1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_wait() {
+        let policy = RetryPolicy {
+            jitter: 0.25,
+            ..RetryPolicy::default()
+        };
+        let full = policy.backoff(3);
+        for _ in 0..1000 {
+            let wait = backoff(&policy, 3);
+            assert!(wait <= full, "{:?} is longer than {:?}", wait, full);
+            assert!(wait >= full.mul_f64(0.75), "{:?} is too short", wait);
+        }
+
+        // Out of range jitter is clamped instead of making waits negative
+        let policy = RetryPolicy {
+            jitter: 5.0,
+            ..RetryPolicy::default()
+        };
+        assert!(backoff(&policy, 0) <= policy.backoff(0));
+    }
+
+    #[test]
+    fn only_retryable_errors_are_retried() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            ..RetryPolicy::default()
+        };
+        let dropped = PbusError::Connection {
+            message: "reset".to_string(),
+            retryable: true,
+        };
+        let deadlock = PbusError::Query {
+            message: "deadlock".to_string(),
+            retryable: true,
+        };
+        assert!(should_retry(&policy, &dropped, 0));
+        assert!(should_retry(&policy, &deadlock, 1));
+        // The third attempt was the last one
+        assert!(!should_retry(&policy, &dropped, 2));
+
+        for fatal in [
+            PbusError::Connection {
+                message: "password authentication failed".to_string(),
+                retryable: false,
+            },
+            PbusError::Query {
+                message: "division by zero".to_string(),
+                retryable: false,
+            },
+            PbusError::config("no such target"),
+            PbusError::storage("disk full"),
+            PbusError::schema("column id is missing"),
+            PbusError::Cancelled,
+        ] {
+            assert!(!should_retry(&policy, &fatal, 0), "{} was retried", fatal);
+        }
+    }
+
+    #[test]
+    fn circuit_opens_probes_and_resets() {
+        let dir = TempDir::new().unwrap();
+        let state = StateStore::open(&format!("{}/", dir.path().display())).unwrap();
+        let database = database();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        // Below the threshold the database keeps running
+        for i in 0..2 {
+            let now = start + Duration::from_secs(i);
+            record_outcome(&state, &database, &unreachable(), now);
+            assert_eq!(circuit_open_until(&state, &database, now), None);
+        }
+        // Errors that say nothing about the database don't count
+        record_outcome::<()>(
+            &state,
+            &database,
+            &Err(PbusError::storage("disk full")),
+            start,
+        );
+        assert_eq!(state.get_circuit("shop").unwrap().failures, 2);
+
+        record_outcome(&state, &database, &unreachable(), start);
+        let circuit = state.get_circuit("shop").unwrap();
+        assert!(circuit.is_open());
+        assert_eq!(circuit.opened_at, Some(start));
+        let probe = start + Duration::from_secs(60);
+        assert_eq!(circuit_open_until(&state, &database, start), Some(probe));
+
+        // Once the probe is due one run goes ahead, and failing keeps it open
+        assert_eq!(circuit_open_until(&state, &database, probe), None);
+        record_outcome(&state, &database, &unreachable(), probe);
+        let circuit = state.get_circuit("shop").unwrap();
+        assert_eq!(circuit.opened_at, Some(start));
+        assert_eq!(
+            circuit_open_until(&state, &database, probe),
+            Some(probe + Duration::from_secs(60))
+        );
+
+        // A success closes it and forgets the failures
+        record_outcome(&state, &database, &Ok(()), probe);
+        let circuit = state.get_circuit("shop").unwrap();
+        assert!(!circuit.is_open());
+        assert_eq!(circuit.failures, 0);
+        assert_eq!(circuit_open_until(&state, &database, probe), None);
+    }
+}
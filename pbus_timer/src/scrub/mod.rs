@@ -0,0 +1,187 @@
+use pbus_config_handler::Database;
+use pbus_db_manager::segments::{checksum, segment_contents, verify_segment};
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus, Segment, SegmentStatus};
+use pbus_remotedb_manager::DbHandler;
+use std::fmt;
+use tracing::{info, instrument, warn, Span};
+use utility::PbusError;
+
+use crate::metrics::metrics;
+
+/// A segment whose rows no longer match what the source holds for its range
+#[derive(Debug, Clone)]
+pub struct SourceMismatch {
+    pub segment: Segment,
+    pub source_rows: i64,
+    pub source_checksum: String,
+}
+
+/// What scrubbing the segments of a target found
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    /// Active segments when the scrub started
+    pub segments: usize,
+    /// Rows compared against the source
+    pub rows: i64,
+    /// Segments that don't match their checksum, now marked corrupt
+    pub corrupt: Vec<Segment>,
+    /// Cursor ranges `(from, to]` no segment covers
+    pub gaps: Vec<(i64, i64)>,
+    /// Segments whose row count or hash differs from the source
+    pub mismatched: Vec<SourceMismatch>,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.gaps.is_empty() && self.mismatched.is_empty()
+    }
+
+    /// One line per problem, for alerts and the CLI
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for segment in &self.corrupt {
+            problems.push(format!("{} doesn't match its checksum", segment.path));
+        }
+        for (from, to) in &self.gaps {
+            problems.push(format!("no segment covers cursors ({}, {}]", from, to));
+        }
+        for mismatch in &self.mismatched {
+            problems.push(format!(
+                "{} holds {} rows, the source {} with a different hash",
+                mismatch.segment.path, mismatch.segment.row_count, mismatch.source_rows
+            ));
+        }
+        problems
+    }
+}
+
+impl fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "{} segments and {} rows match", self.segments, self.rows);
+        }
+        write!(f, "{}", self.problems().join("; "))
+    }
+}
+
+/// Checks every active segment of a target against its checksum and the source
+///
+/// Segments that don't match their checksum are marked corrupt. The others are
+/// compared against the rows the source holds for their cursor range, which
+/// have to hash the same as long as the source didn't change them since they
+/// were captured. The scrub is recorded as a run, failed if it found anything,
+/// and errors only if the catalog can't be read or the source can't be reached.
+#[instrument(
+    name = "scrub",
+    skip_all,
+    fields(database = %database.database_name, target = %target_name, run_id)
+)]
+pub async fn scrub_target(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    database: &Database,
+    target_name: &str,
+) -> Result<ScrubReport, PbusError> {
+    let segments = catalog.get_segments(&database.database_name, target_name)?;
+    let cursor_before = segments.first().map_or(0, |segment| segment.cursor_start);
+    let run_id = catalog.start_run(
+        &database.database_name,
+        target_name,
+        RunKind::Scrub,
+        cursor_before,
+    )?;
+    Span::current().record("run_id", run_id);
+    info!(segments = segments.len(), "Starting scrub");
+
+    let mut stats = RunStats {
+        cursor_after: cursor_before,
+        ..RunStats::default()
+    };
+    let result = compare_segments(
+        base_mount_point,
+        catalog,
+        database,
+        target_name,
+        segments,
+        &mut stats,
+    )
+    .await;
+
+    let (status, error) = match &result {
+        Ok(report) if report.is_clean() => (RunStatus::Succeeded, None),
+        Ok(report) => (RunStatus::Failed, Some(report.to_string())),
+        Err(e) => (RunStatus::Failed, Some(e.to_string())),
+    };
+    catalog.finish_run(run_id, status, &stats, error.as_deref())?;
+    match &result {
+        Ok(report) if report.is_clean() => info!("Scrub passed: {}", report),
+        Ok(report) => warn!("Scrub found problems: {}", report),
+        Err(e) => warn!("Scrub failed: {}", e),
+    }
+    result
+}
+
+async fn compare_segments(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    database: &Database,
+    target_name: &str,
+    segments: Vec<Segment>,
+    stats: &mut RunStats,
+) -> Result<ScrubReport, PbusError> {
+    let target = database
+        .get_targets()
+        .iter()
+        .find(|target| target.get_name() == target_name)
+        .ok_or_else(|| PbusError::config(format!("Target {} not found", target_name)))?;
+
+    let mut report = ScrubReport {
+        segments: segments.len(),
+        ..ScrubReport::default()
+    };
+    let mut intact = Vec::new();
+    let mut cursor = segments.first().map(|segment| segment.cursor_start);
+    for segment in segments {
+        if let Some(cursor) = cursor.filter(|cursor| segment.cursor_start > *cursor) {
+            report.gaps.push((cursor, segment.cursor_start));
+        }
+        cursor = cursor.max(Some(segment.cursor_end));
+        if verify_segment(base_mount_point, &segment)? {
+            intact.push(segment);
+        } else {
+            catalog.set_segment_status(segment.id, SegmentStatus::Corrupt)?;
+            report.corrupt.push(segment);
+        }
+    }
+    if intact.is_empty() {
+        return Ok(report);
+    }
+
+    let _connection = metrics().connection(&database.database_name);
+    let handler = DbHandler::new(
+        &database.database_host,
+        database.server_port,
+        &database.database_user,
+        &database.database_name,
+        &database.resolve_password(base_mount_point)?,
+    )
+    .await?;
+    for segment in intact {
+        let rows = handler
+            .get_rows_between(target, segment.cursor_start, segment.cursor_end)
+            .await?;
+        let source_checksum = checksum(segment_contents(&rows)?.as_bytes());
+        let source_rows = rows.len() as i64;
+        stats.rows_captured += source_rows;
+        stats.cursor_after = segment.cursor_end;
+        if source_rows != segment.row_count || source_checksum != segment.checksum {
+            report.mismatched.push(SourceMismatch {
+                segment,
+                source_rows,
+                source_checksum,
+            });
+        }
+        report.rows += source_rows;
+    }
+    Ok(report)
+}
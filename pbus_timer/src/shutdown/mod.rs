@@ -0,0 +1,75 @@
+//! Stopping the worker in the middle of a run
+//!
+//! A `Shutdown` control request, SIGTERM or SIGINT set the process wide flag of
+//! `shutdown()`. Runs check it between batches and while waiting to retry and
+//! fail with `PbusError::Cancelled`, keeping the batches they wrote.
+
+use std::sync::OnceLock;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::info;
+use utility::PbusError;
+
+/// Whether the worker was asked to stop, one instance per process, see
+/// `shutdown()`
+pub struct Shutdown {
+    requested: watch::Sender<bool>,
+}
+
+/// The process wide shutdown flag
+pub fn shutdown() -> &'static Shutdown {
+    static SHUTDOWN: OnceLock<Shutdown> = OnceLock::new();
+    SHUTDOWN.get_or_init(|| Shutdown {
+        requested: watch::channel(false).0,
+    })
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// `PbusError::Cancelled` once a shutdown was requested
+    pub fn check(&self) -> Result<(), PbusError> {
+        if self.is_requested() {
+            Err(PbusError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits until a shutdown is requested, returns at once if it already was
+    pub async fn requested(&self) {
+        let mut requested = self.requested.subscribe();
+        // The sender lives as long as the process, so this can't fail
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+
+    /// Sleeps for `duration`, `PbusError::Cancelled` if a shutdown is requested
+    /// before it is over
+    pub async fn sleep(&self, duration: std::time::Duration) -> Result<(), PbusError> {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => Ok(()),
+            _ = self.requested() => Err(PbusError::Cancelled),
+        }
+    }
+}
+
+/// Requests a shutdown when the process receives SIGTERM or SIGINT
+pub fn listen_for_signals() -> Result<JoinHandle<()>, PbusError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::spawn(async move {
+        let name = tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        };
+        info!("Received {}, stopping the worker", name);
+        shutdown().request();
+    }))
+}
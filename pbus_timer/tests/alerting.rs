@@ -0,0 +1,218 @@
+use pbus_config_handler::alerting::{EmailChannel, SmtpSecurity};
+use pbus_config_handler::{
+    AlertChannel, AlertRules, AlertingConfig, ChannelKind, Config, Database, SecretRef,
+};
+use pbus_timer::alerting::Alerter;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use utility::targets::Target;
+
+fn config(base_mount_point: &str, kind: ChannelKind, repeat_interval: u64) -> Config {
+    let mut database = Database::new(
+        "localhost".to_string(),
+        5432,
+        "postgres".to_string(),
+        "shop".to_string(),
+        SecretRef::Plain("password".to_string()),
+        vec![Target::new("orders".to_string())],
+        60,
+        SystemTime::UNIX_EPOCH,
+    );
+    database.alerts = Some(AlertRules::default());
+    let mut config = Config::new(vec![database], base_mount_point);
+    config.set_alerting(AlertingConfig {
+        channels: vec![AlertChannel {
+            name: "ops".to_string(),
+            kind,
+        }],
+        repeat_interval,
+    });
+    config
+}
+
+/// Answers every POST with the next of `statuses`, 200 once they ran out, and
+/// passes on the JSON bodies it got
+async fn webhook(statuses: &[u16]) -> (String, mpsc::UnboundedReceiver<Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+    let statuses = Arc::new(Mutex::new(
+        statuses.iter().copied().collect::<VecDeque<_>>(),
+    ));
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let line = line.trim_end().to_ascii_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+            sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+
+            let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+            let response = format!(
+                "HTTP/1.1 {} Stub\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (url, receiver)
+}
+
+/// Takes one mail like a plain SMTP relay would and passes on what it got
+async fn smtp_relay() -> (u16, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut transcript = String::new();
+        stream.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            transcript.push_str(&line);
+            let command = line.trim_end().to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") {
+                b"250-localhost\r\n250 8BITMIME\r\n"
+            } else if command == "DATA" {
+                stream.write_all(b"354 go ahead\r\n").await.unwrap();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if line == ".\r\n" {
+                        break;
+                    }
+                    transcript.push_str(&line);
+                }
+                b"250 queued\r\n"
+            } else if command == "QUIT" {
+                stream.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            stream.write_all(reply).await.unwrap();
+        }
+        sender.send(transcript).unwrap();
+    });
+    (port, receiver)
+}
+
+#[tokio::test]
+async fn webhook_alerts_are_retried_and_repeated_once_due() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let (url, mut posts) = webhook(&[500]).await;
+    let mut config = config(&base, ChannelKind::Webhook { url }, 3600);
+    let mut alerter = Alerter::new(&base, &config).unwrap();
+    let database = config.get_databases()[0].clone();
+    let problems = ["segment 3 is corrupt".to_string()];
+
+    // The webhook fails, so the alert counts as not sent yet
+    alerter
+        .check_verification(&database, "orders", &problems)
+        .await;
+    let first = posts.try_recv().unwrap();
+    assert_eq!(first["status"], "firing");
+    assert_eq!(first["kind"], "verification");
+    assert_eq!(first["database"], "shop");
+    assert_eq!(first["target"], "orders");
+    assert!(first["message"]
+        .as_str()
+        .unwrap()
+        .contains("segment 3 is corrupt"));
+    assert_eq!(first["repeat"], false);
+
+    alerter
+        .check_verification(&database, "orders", &problems)
+        .await;
+    let retried = posts.try_recv().unwrap();
+    assert_eq!(retried["repeat"], false);
+    assert_eq!(retried["since"], first["since"]);
+
+    // Delivered, so nothing more until the repeat interval passed
+    alerter
+        .check_verification(&database, "orders", &problems)
+        .await;
+    assert!(posts.try_recv().is_err());
+
+    config.set_alerting(AlertingConfig {
+        repeat_interval: 0,
+        ..config.get_alerting().clone()
+    });
+    alerter.set_config(&config);
+    alerter
+        .check_verification(&database, "orders", &problems)
+        .await;
+    assert_eq!(posts.try_recv().unwrap()["repeat"], true);
+
+    alerter.check_verification(&database, "orders", &[]).await;
+    let resolved = posts.try_recv().unwrap();
+    assert_eq!(resolved["status"], "resolved");
+    assert_eq!(resolved["since"], first["since"]);
+    alerter.check_verification(&database, "orders", &[]).await;
+    assert!(posts.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn refused_webhooks_are_not_retryable() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let (url, _posts) = webhook(&[404, 503]).await;
+    let config = config(&base, ChannelKind::Webhook { url }, 3600);
+    let alerter = Alerter::new(&base, &config).unwrap();
+
+    let refused = alerter.send_test(Some("ops")).await.unwrap_err();
+    assert!(!refused.is_retryable());
+    assert!(refused.to_string().contains("channel ops"));
+    assert!(alerter.send_test(None).await.unwrap_err().is_retryable());
+    assert_eq!(alerter.send_test(None).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn email_alerts_go_through_the_relay() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let (port, mut mails) = smtp_relay().await;
+    let email = EmailChannel {
+        smtp_host: "127.0.0.1".to_string(),
+        smtp_port: port,
+        security: SmtpSecurity::None,
+        username: None,
+        password: None,
+        from: "pbus@example.com".to_string(),
+        to: vec!["ops@example.com".to_string()],
+    };
+    let config = config(&base, ChannelKind::Email(email), 3600);
+    let alerter = Alerter::new(&base, &config).unwrap();
+
+    assert_eq!(alerter.send_test(None).await.unwrap(), 1);
+    let transcript = mails.recv().await.unwrap();
+    assert!(transcript.contains("MAIL FROM:<pbus@example.com>"));
+    assert!(transcript.contains("RCPT TO:<ops@example.com>"));
+    assert!(transcript.contains("Subject: [pbus] FIRING failures pbus"));
+    assert!(transcript.contains("test alert, please ignore"));
+}
@@ -0,0 +1,26 @@
+use pbus_config_handler::config_file::{config_path, write_atomic};
+use pbus_timer::config_watcher::ConfigWatcher;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// A single thread, so a watcher that blocked it would keep the write from
+// ever happening
+#[tokio::test(flavor = "current_thread")]
+async fn changes_are_awaited_without_blocking() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let mut watcher = ConfigWatcher::new(&base).unwrap();
+
+    let writer = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        write_atomic(&config_path(&base), b"{}").unwrap();
+    });
+    tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+        .await
+        .expect("no change was reported");
+    writer.await.unwrap();
+
+    // The events of that one save were collapsed into one change
+    let again = tokio::time::timeout(Duration::from_millis(300), watcher.changed()).await;
+    assert!(again.is_err());
+}
@@ -0,0 +1,70 @@
+use pbus_db_manager::{Catalog, RunKind, RunStats, RunStatus};
+use pbus_timer::control::{self, socket_path, ControlServer, Request, Response};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn socket_is_private_and_answers() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    assert!(control::send(&base, &Request::Status)
+        .await
+        .unwrap()
+        .is_none());
+
+    let mut server = ControlServer::bind(&base).unwrap();
+    let path = socket_path(&base);
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // Nothing is left of the directory it was bound in
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert!(ControlServer::bind(&base).is_err());
+
+    let worker = tokio::spawn(async move {
+        let call = server.next().await.unwrap();
+        assert_eq!(call.request, Request::Status);
+        call.reply(Response::Done);
+        server
+    });
+    assert_eq!(
+        control::send(&base, &Request::Status).await.unwrap(),
+        Some(Response::Done)
+    );
+
+    drop(worker.await.unwrap());
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn waiting_for_a_run_returns_it_once_finished() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let catalog = Catalog::open(&base).unwrap();
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, 10)
+        .unwrap();
+
+    let worker = tokio::spawn({
+        let base = base.clone();
+        async move {
+            let catalog = Catalog::open(&base).unwrap();
+            let stats = RunStats {
+                cursor_after: 20,
+                rows_captured: 10,
+                bytes_written: 100,
+                retries: 0,
+            };
+            catalog
+                .finish_run(run_id, RunStatus::Succeeded, &stats, None)
+                .unwrap();
+        }
+    });
+    let run = control::wait_for_run(&catalog, run_id).await.unwrap();
+    worker.await.unwrap();
+    assert_eq!(run.status, RunStatus::Succeeded);
+    assert_eq!(run.stats().cursor_after, 20);
+    assert_eq!(run.stats().rows_captured, 10);
+
+    assert!(control::wait_for_run(&catalog, run_id + 1).await.is_err());
+}
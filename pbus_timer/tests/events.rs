@@ -0,0 +1,43 @@
+use pbus_db_manager::EventLog;
+use pbus_timer::events::{events, Event};
+use tempfile::TempDir;
+
+fn started(run_id: i64) -> Event {
+    Event::RunStarted {
+        database: "shop".to_string(),
+        target: "orders".to_string(),
+        run_id,
+        cursor: 0,
+    }
+}
+
+fn logged_runs(base_mount_point: &str) -> Vec<i64> {
+    EventLog::open(base_mount_point)
+        .unwrap()
+        .get_events_after(0, 10)
+        .unwrap()
+        .iter()
+        .map(
+            |logged| match serde_json::from_str::<Event>(&logged.payload).unwrap() {
+                Event::RunStarted { run_id, .. } => run_id,
+                event => panic!("unexpected {} event", event.kind()),
+            },
+        )
+        .collect()
+}
+
+#[test]
+fn events_land_in_the_log_of_their_data_directory() {
+    let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+    let [first, second] = dirs
+        .each_ref()
+        .map(|dir| format!("{}/", dir.path().display()));
+
+    events().publish(&first, started(1));
+    events().publish(&first, started(2));
+    events().publish(&second, started(3));
+    events().publish(&first, started(4));
+
+    assert_eq!(logged_runs(&first), [1, 2, 4]);
+    assert_eq!(logged_runs(&second), [3]);
+}
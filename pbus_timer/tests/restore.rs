@@ -0,0 +1,60 @@
+use pbus_db_manager::segments::write_segment;
+use pbus_db_manager::{Catalog, RunKind};
+use pbus_timer::restore::{replay_segments, segment_rows, Replay};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+fn rows(ids: std::ops::RangeInclusive<i64>) -> Vec<Value> {
+    ids.map(|id| json!({ "id": id })).collect()
+}
+
+/// Writes a segment holding the ids `(cursor_start, cursor_end]`
+fn add_segment(base_mount_point: &str, catalog: &Catalog, cursor_start: i64, cursor_end: i64) {
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, cursor_start)
+        .unwrap();
+    let run = catalog.get_run(run_id).unwrap().unwrap();
+    let segment = write_segment(
+        base_mount_point,
+        &run,
+        cursor_start,
+        cursor_end,
+        &rows(cursor_start + 1..=cursor_end),
+        "v1".to_string(),
+    )
+    .unwrap();
+    catalog.add_segment(&segment).unwrap();
+}
+
+fn replayed_ids(base_mount_point: &str, catalog: &Catalog) -> (Vec<i64>, Vec<(i64, i64)>) {
+    match replay_segments(base_mount_point, catalog, "shop", "orders", None).unwrap() {
+        Replay::Segments { segments, gaps } => {
+            let ids = segments
+                .iter()
+                .flat_map(|segment| segment_rows(base_mount_point, segment, None).unwrap())
+                .map(|row| row["id"].as_i64().unwrap())
+                .collect();
+            (ids, gaps)
+        }
+        Replay::Corrupt(segment) => panic!("{} is corrupt", segment.path),
+    }
+}
+
+#[test]
+fn overlapping_segments_are_read_once() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let catalog = Catalog::open(&base).unwrap();
+
+    // A run that crashed before saving its cursor is captured again
+    add_segment(&base, &catalog, 0, 10);
+    add_segment(&base, &catalog, 10, 20);
+    add_segment(&base, &catalog, 0, 15);
+    add_segment(&base, &catalog, 15, 25);
+    add_segment(&base, &catalog, 30, 35);
+
+    let (ids, gaps) = replayed_ids(&base, &catalog);
+    let expected: Vec<i64> = (1..=25).chain(31..=35).collect();
+    assert_eq!(ids, expected);
+    assert_eq!(gaps, [(25, 30)]);
+}
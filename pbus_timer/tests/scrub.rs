@@ -0,0 +1,137 @@
+use pbus_config_handler::{Database, SecretRef};
+use pbus_db_manager::segments::write_segment;
+use pbus_db_manager::{Catalog, RunKind, RunStatus, Segment, SegmentStatus};
+use pbus_timer::scrub::scrub_target;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::SystemTime;
+use tempfile::TempDir;
+use utility::targets::Target;
+
+/// A source nothing listens on, so a scrub that reaches out to it fails
+fn database() -> Database {
+    Database::new(
+        "127.0.0.1".to_string(),
+        1,
+        "postgres".to_string(),
+        "shop".to_string(),
+        SecretRef::Plain("password".to_string()),
+        vec![Target::new("orders".to_string())],
+        60,
+        SystemTime::UNIX_EPOCH,
+    )
+}
+
+fn rows(ids: std::ops::RangeInclusive<i64>) -> Vec<Value> {
+    ids.map(|id| json!({ "id": id })).collect()
+}
+
+/// Writes and records a segment holding the ids `(cursor_start, cursor_end]`
+fn add_segment(
+    base_mount_point: &str,
+    catalog: &Catalog,
+    cursor_start: i64,
+    cursor_end: i64,
+) -> Segment {
+    let run_id = catalog
+        .start_run("shop", "orders", RunKind::Backup, cursor_start)
+        .unwrap();
+    let run = catalog.get_run(run_id).unwrap().unwrap();
+    let mut segment = write_segment(
+        base_mount_point,
+        &run,
+        cursor_start,
+        cursor_end,
+        &rows(cursor_start + 1..=cursor_end),
+        "v1".to_string(),
+    )
+    .unwrap();
+    segment.id = catalog.add_segment(&segment).unwrap();
+    segment
+}
+
+fn path(base_mount_point: &str, segment: &Segment) -> String {
+    format!("{}{}", base_mount_point, segment.path)
+}
+
+/// A tampered, a truncated and a missing segment, with a gap before the last
+fn damage(base_mount_point: &str, catalog: &Catalog) -> Vec<Segment> {
+    let tampered = add_segment(base_mount_point, catalog, 0, 10);
+    let contents = fs::read_to_string(path(base_mount_point, &tampered)).unwrap();
+    fs::write(
+        path(base_mount_point, &tampered),
+        contents.replace("{\"id\":3}", "{\"id\":4}"),
+    )
+    .unwrap();
+
+    let truncated = add_segment(base_mount_point, catalog, 10, 20);
+    let contents = fs::read(path(base_mount_point, &truncated)).unwrap();
+    fs::write(
+        path(base_mount_point, &truncated),
+        &contents[..contents.len() / 2],
+    )
+    .unwrap();
+
+    let missing = add_segment(base_mount_point, catalog, 30, 40);
+    fs::remove_file(path(base_mount_point, &missing)).unwrap();
+
+    vec![tampered, truncated, missing]
+}
+
+#[tokio::test]
+async fn damaged_segments_are_marked_corrupt() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let catalog = Catalog::open(&base).unwrap();
+    let damaged = damage(&base, &catalog);
+
+    // Nothing is left to compare, so the source isn't needed
+    let report = scrub_target(&base, &catalog, &database(), "orders")
+        .await
+        .unwrap();
+    assert_eq!(report.segments, 3);
+    let corrupt: Vec<i64> = report.corrupt.iter().map(|segment| segment.id).collect();
+    let expected: Vec<i64> = damaged.iter().map(|segment| segment.id).collect();
+    assert_eq!(corrupt, expected);
+    assert_eq!(report.gaps, [(20, 30)]);
+    assert!(!report.is_clean());
+    assert_eq!(report.problems().len(), 4);
+
+    assert!(catalog.get_segments("shop", "orders").unwrap().is_empty());
+    assert!(catalog
+        .get_corrupt_segments("shop")
+        .unwrap()
+        .iter()
+        .all(|segment| segment.status == SegmentStatus::Corrupt));
+    let run = catalog
+        .get_last_run("shop", "orders", RunKind::Scrub)
+        .unwrap()
+        .unwrap();
+    assert_eq!(run.status, RunStatus::Failed);
+    assert!(run.error.unwrap().contains("doesn't match its checksum"));
+}
+
+#[tokio::test]
+async fn intact_segments_stay_active() {
+    let dir = TempDir::new().unwrap();
+    let base = format!("{}/", dir.path().display());
+    let catalog = Catalog::open(&base).unwrap();
+    damage(&base, &catalog);
+    let intact = add_segment(&base, &catalog, 40, 50);
+
+    // The intact segment is compared against the source, which can't be reached
+    let error = scrub_target(&base, &catalog, &database(), "orders")
+        .await
+        .unwrap_err();
+    assert!(error.is_retryable());
+
+    let active = catalog.get_segments("shop", "orders").unwrap();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].id, intact.id);
+    assert_eq!(catalog.get_corrupt_segments("shop").unwrap().len(), 3);
+    let run = catalog
+        .get_last_run("shop", "orders", RunKind::Scrub)
+        .unwrap()
+        .unwrap();
+    assert_eq!(run.status, RunStatus::Failed);
+}
@@ -0,0 +1,230 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/// Command-line client for the pbus web API
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// Stored profile to use instead of the current one
+    #[arg(long, env = "PBUS_PROFILE", global = true)]
+    pub profile: Option<String>,
+
+    /// Server to talk to, e.g. `http://127.0.0.1:8080`, instead of the profile's
+    #[arg(long, env = "PBUS_SERVER", global = true)]
+    pub server: Option<String>,
+
+    /// API token to use instead of the profile's
+    #[arg(long, env = "PBUS_TOKEN", global = true, hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// How to print results
+    #[arg(long, env = "PBUS_FORMAT", value_enum, default_value_t = Format::Table, global = true)]
+    pub format: Format,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns for reading
+    Table,
+    /// The API's JSON, for scripts
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create an API token with a name and password and store it as a profile
+    Login(LoginArgs),
+    /// Revoke the token of a profile and forget the profile
+    Logout,
+    /// List, switch or remove stored profiles
+    #[command(subcommand)]
+    Profiles(ProfilesCommand),
+    /// Show the user and role the token belongs to
+    Whoami,
+    /// List, show, add, change or remove databases
+    #[command(subcommand)]
+    Databases(DatabasesCommand),
+    /// List, show, add, change or remove targets
+    #[command(subcommand)]
+    Targets(TargetsCommand),
+    /// Show how far each backup trails its source and flag RPO breaches
+    Freshness(TargetFilter),
+    /// Back a target up right now and show its progress
+    Backup(TargetArgs),
+    /// Follow the progress of backup runs as they happen
+    Watch(WatchArgs),
+    /// Show the backup runs of a target, newest first
+    Runs(RunsArgs),
+    /// Show the active segments of a target
+    Segments(TargetArgs),
+    /// Replay a target's backed up rows into a file, stdout or a database
+    Restore(RestoreArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct LoginArgs {
+    /// Server to log in to, e.g. `http://127.0.0.1:8080`
+    pub server: String,
+    /// Name of the user
+    pub user: String,
+    /// Name to store the profile under
+    #[arg(long, default_value = "default")]
+    pub name: String,
+    /// Label of the token the server shows in its token list
+    #[arg(long, default_value = "pbus_webclient")]
+    pub label: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfilesCommand {
+    /// List stored profiles, the current one is marked
+    List,
+    /// Make a profile the current one
+    Use { name: String },
+    /// Forget a profile without revoking its token
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DatabasesCommand {
+    /// List databases with their circuit and targets
+    List,
+    /// Show a database
+    Show { database: String },
+    /// Connect a new database and add it with the chosen tables
+    Add(AddDatabaseArgs),
+    /// Change connection settings, the update interval or the RPO
+    Update(UpdateDatabaseArgs),
+    /// Take a database out of the config, its backups are kept
+    Remove { database: String },
+}
+
+#[derive(Args, Debug)]
+pub struct AddDatabaseArgs {
+    pub name: String,
+    #[arg(long, default_value = "localhost")]
+    pub host: String,
+    #[arg(long, default_value_t = 5432)]
+    pub port: u16,
+    #[arg(long)]
+    pub user: String,
+    /// A reference such as `env:PGPASSWORD`, or the password itself
+    #[arg(long)]
+    pub password: String,
+    /// Seconds between runs
+    #[arg(long, default_value_t = 3600)]
+    pub update_interval: u64,
+    /// Seconds a backup may trail its source
+    #[arg(long)]
+    pub rpo: Option<u64>,
+    /// Tables to back up
+    #[arg(long = "target")]
+    pub targets: Vec<String>,
+    /// Back up every table that can be
+    #[arg(long, conflicts_with = "targets")]
+    pub all: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct UpdateDatabaseArgs {
+    pub database: String,
+    #[arg(long)]
+    pub host: Option<String>,
+    #[arg(long)]
+    pub port: Option<u16>,
+    #[arg(long)]
+    pub user: Option<String>,
+    /// A reference such as `env:PGPASSWORD`, or the password itself
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Seconds between runs
+    #[arg(long)]
+    pub update_interval: Option<u64>,
+    /// Seconds a backup may trail its source, 0 removes the RPO
+    #[arg(long)]
+    pub rpo: Option<u64>,
+    /// Seconds backups should reach back, only shown; 0 removes the retention
+    #[arg(long)]
+    pub retention: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TargetsCommand {
+    /// List the targets of a database with their cursor
+    List { database: String },
+    /// Show a target
+    Show(TargetArgs),
+    /// Add a table of a database as a target
+    Add {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Seconds the backup may trail its source
+        #[arg(long)]
+        rpo: Option<u64>,
+    },
+    /// Enable or disable a target, or change its RPO
+    Update {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        #[arg(long)]
+        disable: bool,
+        /// Seconds the backup may trail its source, 0 removes the target's own RPO
+        #[arg(long)]
+        rpo: Option<u64>,
+    },
+    /// Take a target out of the config, its backups are kept
+    Remove(TargetArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TargetArgs {
+    pub database: String,
+    pub target: String,
+}
+
+#[derive(Args, Debug)]
+pub struct TargetFilter {
+    /// Only this database
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Only this target
+    #[arg(long)]
+    pub target: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub filter: TargetFilter,
+    /// Start after this event id instead of with new events
+    #[arg(long)]
+    pub after: Option<i64>,
+}
+
+#[derive(Args, Debug)]
+pub struct RunsArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Most runs to show
+    #[arg(long, default_value_t = 20)]
+    pub limit: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+    /// Only restore rows with a cursor up to and including this value
+    #[arg(long)]
+    pub to_cursor: Option<i64>,
+    /// Insert the rows into this configured database
+    #[arg(long, conflicts_with = "output")]
+    pub into: Option<String>,
+    /// Write the rows as JSON lines to this file instead of stdout
+    #[arg(long)]
+    pub output: Option<String>,
+}
@@ -0,0 +1,182 @@
+use pbus_models::api::ErrorBody;
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+
+/// How a request proves who sends it
+pub enum Auth {
+    Token(String),
+    Password { user: String, password: String },
+}
+
+/// Talks to the API of one server
+///
+/// Paths are given as segments below `/api`, so names are escaped as needed.
+pub struct ApiClient {
+    http: reqwest::Client,
+    server: Url,
+    auth: Auth,
+}
+
+/// One server-sent event
+#[derive(Debug, Default)]
+pub struct SseMessage {
+    pub id: Option<i64>,
+    pub event: String,
+    pub data: String,
+}
+
+/// Server-sent events as they arrive
+pub struct EventStream {
+    response: Response,
+    /// Bytes received that don't make up a whole event yet
+    buffer: Vec<u8>,
+}
+
+impl ApiClient {
+    pub fn new(server: &str, auth: Auth) -> Result<ApiClient, Box<dyn Error>> {
+        let server = Url::parse(server).map_err(|e| format!("{} is not a URL: {}", server, e))?;
+        if server.cannot_be_a_base() {
+            return Err(format!("{} is not a server URL", server).into());
+        }
+        Ok(ApiClient {
+            http: reqwest::Client::new(),
+            server,
+            auth,
+        })
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &[&str]) -> Result<T, Box<dyn Error>> {
+        self.get_with_query(path, &[]).await
+    }
+
+    pub async fn get_with_query<T: DeserializeOwned>(
+        &self,
+        path: &[&str],
+        query: &[(&str, String)],
+    ) -> Result<T, Box<dyn Error>> {
+        let response = send(self.request(Method::GET, path).query(query)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Sends `body` as JSON and reads the answer as `T`
+    pub async fn send<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &[&str],
+        body: &B,
+    ) -> Result<T, Box<dyn Error>> {
+        let response = send(self.request(method, path).json(body)).await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn delete(&self, path: &[&str]) -> Result<(), Box<dyn Error>> {
+        send(self.request(Method::DELETE, path)).await?;
+        Ok(())
+    }
+
+    /// Sends `body` as JSON and hands back the answer unread, for streaming it
+    pub async fn open<B: Serialize>(
+        &self,
+        method: Method,
+        path: &[&str],
+        body: &B,
+    ) -> Result<Response, Box<dyn Error>> {
+        send(self.request(method, path).json(body)).await
+    }
+
+    /// Subscribes to run events, starting after `after` or with new ones
+    ///
+    /// Returns once the server accepted the subscription, so no event that
+    /// happens afterwards is missed.
+    pub async fn events(
+        &self,
+        database: Option<&str>,
+        target: Option<&str>,
+        after: Option<i64>,
+    ) -> Result<EventStream, Box<dyn Error>> {
+        let mut query = Vec::new();
+        if let Some(database) = database {
+            query.push(("database", database.to_string()));
+        }
+        if let Some(target) = target {
+            query.push(("target", target.to_string()));
+        }
+        if let Some(after) = after {
+            query.push(("after", after.to_string()));
+        }
+        let response = send(self.request(Method::GET, &["events"]).query(&query)).await?;
+        Ok(EventStream {
+            response,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &[&str]) -> RequestBuilder {
+        let mut url = self.server.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().push("api").extend(path);
+        }
+        let request = self.http.request(method, url);
+        match &self.auth {
+            Auth::Token(token) => request.bearer_auth(token),
+            Auth::Password { user, password } => request.basic_auth(user, Some(password)),
+        }
+    }
+}
+
+/// Sends a request, turning an error status into an error with the server's
+/// message
+async fn send(request: RequestBuilder) -> Result<Response, Box<dyn Error>> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = match response.json::<ErrorBody>().await {
+        Ok(body) => body.error,
+        Err(_) => status
+            .canonical_reason()
+            .unwrap_or("unknown error")
+            .to_string(),
+    };
+    Err(format!("{}: {}", status.as_u16(), message).into())
+}
+
+impl EventStream {
+    /// The next event, `None` once the server closed the stream
+    pub async fn next(&mut self) -> Result<Option<SseMessage>, Box<dyn Error>> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|bytes| bytes == b"\n\n") {
+                let bytes: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let block = String::from_utf8_lossy(&bytes);
+                let mut message = SseMessage::default();
+                for line in block.lines() {
+                    let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                    let value = value.strip_prefix(' ').unwrap_or(value);
+                    match field {
+                        "id" => message.id = value.parse().ok(),
+                        "event" => message.event = value.to_string(),
+                        "data" => {
+                            if !message.data.is_empty() {
+                                message.data.push('\n');
+                            }
+                            message.data.push_str(value);
+                        }
+                        // Comments, such as keep-alives
+                        _ => {}
+                    }
+                }
+                if !message.data.is_empty() {
+                    return Ok(Some(message));
+                }
+                continue;
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
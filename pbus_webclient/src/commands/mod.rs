@@ -0,0 +1,745 @@
+use pbus_models::api::*;
+use pbus_models::Event;
+use reqwest::Method;
+use std::error::Error;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::{
+    AddDatabaseArgs, Cli, DatabasesCommand, Format, LoginArgs, ProfilesCommand, RestoreArgs,
+    RunsArgs, TargetArgs, TargetFilter, TargetsCommand, UpdateDatabaseArgs, WatchArgs,
+};
+use crate::client::{ApiClient, Auth};
+use crate::output::{optional, seconds, show};
+use crate::profiles::{Profile, Profiles};
+use crate::EXIT_STALE;
+
+/// How long `watch` waits before reconnecting to a server that went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// A client for the server and token picked on the command line, or by the
+/// profile
+pub fn connect(cli: &Cli) -> Result<ApiClient, Box<dyn Error>> {
+    let profiles = Profiles::load()?;
+    let profile = profiles
+        .get(cli.profile.as_deref())
+        .map(|(_, profile)| profile);
+    if let (Some(name), None) = (&cli.profile, profile) {
+        return Err(format!("There is no profile {}", name).into());
+    }
+    let server = cli
+        .server
+        .clone()
+        .or_else(|| profile.map(|profile| profile.server.clone()))
+        .ok_or("No server, log in or pass --server")?;
+    let token = cli
+        .token
+        .clone()
+        .or_else(|| profile.map(|profile| profile.token.clone()))
+        .ok_or("No token, log in or pass --token")?;
+    ApiClient::new(&server, Auth::Token(token))
+}
+
+pub async fn login(args: &LoginArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let password = rpassword::prompt_password(format!("Password of {}: ", args.user))?;
+    let client = ApiClient::new(
+        &args.server,
+        Auth::Password {
+            user: args.user.clone(),
+            password,
+        },
+    )?;
+    let created: CreatedToken = client
+        .send(
+            Method::POST,
+            &["tokens"],
+            &NewToken {
+                label: args.label.clone(),
+            },
+        )
+        .await?;
+
+    let mut profiles = Profiles::load()?;
+    profiles.profiles.insert(
+        args.name.clone(),
+        Profile {
+            server: args.server.clone(),
+            user: args.user.clone(),
+            token: created.token,
+            token_id: created.id,
+        },
+    );
+    profiles.current = Some(args.name.clone());
+    profiles.save()?;
+    println!(
+        "Logged in to {} as {}, stored as profile {}",
+        args.server, args.user, args.name
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Revokes the token of the profile before forgetting it, a token the server
+/// no longer knows is forgotten all the same
+pub async fn logout(cli: &Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let mut profiles = Profiles::load()?;
+    let (name, profile) = match profiles.get(cli.profile.as_deref()) {
+        Some((name, profile)) => (name.to_string(), profile.clone()),
+        None => return Err("Not logged in".into()),
+    };
+    let client = ApiClient::new(&profile.server, Auth::Token(profile.token.clone()))?;
+    if let Err(e) = client
+        .delete(&["tokens", &profile.token_id.to_string()])
+        .await
+    {
+        eprintln!("Can't revoke the token: {}", e);
+    }
+
+    profiles.profiles.remove(&name);
+    if profiles.current.as_deref() == Some(name.as_str()) {
+        profiles.current = None;
+    }
+    profiles.save()?;
+    println!("Logged out of {}", profile.server);
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn profiles(command: &ProfilesCommand, format: Format) -> Result<ExitCode, Box<dyn Error>> {
+    let mut profiles = Profiles::load()?;
+    match command {
+        ProfilesCommand::List => {
+            let rows = profiles
+                .profiles
+                .iter()
+                .map(|(name, profile)| {
+                    let current = profiles.current.as_deref() == Some(name.as_str());
+                    vec![
+                        if current { "*" } else { "" }.to_string(),
+                        name.clone(),
+                        profile.server.clone(),
+                        profile.user.clone(),
+                    ]
+                })
+                .collect();
+            // Never print the tokens
+            let listed: Vec<_> = profiles
+                .profiles
+                .iter()
+                .map(|(name, profile)| {
+                    serde_json::json!({
+                        "name": name,
+                        "server": profile.server,
+                        "user": profile.user,
+                        "current": profiles.current.as_deref() == Some(name.as_str()),
+                    })
+                })
+                .collect();
+            show(format, &listed, &["", "NAME", "SERVER", "USER"], rows)?;
+        }
+        ProfilesCommand::Use { name } => {
+            if !profiles.profiles.contains_key(name) {
+                return Err(format!("There is no profile {}", name).into());
+            }
+            profiles.current = Some(name.clone());
+            profiles.save()?;
+        }
+        ProfilesCommand::Remove { name } => {
+            if profiles.profiles.remove(name).is_none() {
+                return Err(format!("There is no profile {}", name).into());
+            }
+            if profiles.current.as_deref() == Some(name.as_str()) {
+                profiles.current = None;
+            }
+            profiles.save()?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn whoami(client: &ApiClient, format: Format) -> Result<ExitCode, Box<dyn Error>> {
+    let session: SessionView = client.get(&["session"]).await?;
+    let rows = vec![vec![
+        session.user.clone(),
+        session.role.as_str().to_string(),
+    ]];
+    show(format, &session, &["USER", "ROLE"], rows)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn databases(
+    client: &ApiClient,
+    format: Format,
+    command: &DatabasesCommand,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        DatabasesCommand::List => {
+            let databases: Vec<DatabaseView> = client.get(&["databases"]).await?;
+            show_databases(format, &databases)?;
+        }
+        DatabasesCommand::Show { database } => {
+            let database: DatabaseView = client.get(&["databases", database]).await?;
+            show_database(format, &database)?;
+        }
+        DatabasesCommand::Add(args) => {
+            let database: DatabaseView = client
+                .send(Method::POST, &["databases"], &new_database(args))
+                .await?;
+            show_database(format, &database)?;
+        }
+        DatabasesCommand::Update(args) => {
+            let database: DatabaseView = client
+                .send(
+                    Method::PATCH,
+                    &["databases", &args.database],
+                    &database_update(args),
+                )
+                .await?;
+            show_database(format, &database)?;
+        }
+        DatabasesCommand::Remove { database } => {
+            client.delete(&["databases", database]).await?;
+            println!("Removed {}, its backups are kept", database);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn new_database(args: &AddDatabaseArgs) -> NewDatabase {
+    NewDatabase {
+        name: args.name.clone(),
+        host: args.host.clone(),
+        port: args.port,
+        user: args.user.clone(),
+        password: args.password.clone(),
+        update_interval: args.update_interval,
+        rpo: args.rpo,
+        targets: args.targets.clone(),
+        all: args.all,
+    }
+}
+
+fn database_update(args: &UpdateDatabaseArgs) -> DatabaseUpdate {
+    DatabaseUpdate {
+        host: args.host.clone(),
+        port: args.port,
+        user: args.user.clone(),
+        password: args.password.clone(),
+        update_interval: args.update_interval,
+        rpo: args.rpo,
+        retention: args.retention,
+    }
+}
+
+fn show_databases(format: Format, databases: &[DatabaseView]) -> Result<(), Box<dyn Error>> {
+    let rows = databases
+        .iter()
+        .map(|database| {
+            vec![
+                database.name.clone(),
+                format!("{}:{}", database.host, database.port),
+                database.user.clone(),
+                seconds(database.update_interval),
+                optional(database.rpo.map(seconds)),
+                optional(database.retention.map(seconds)),
+                database.targets.len().to_string(),
+                circuit(&database.circuit),
+            ]
+        })
+        .collect();
+    show(
+        format,
+        &databases,
+        &[
+            "NAME",
+            "SERVER",
+            "USER",
+            "INTERVAL",
+            "RPO",
+            "RETENTION",
+            "TARGETS",
+            "CIRCUIT",
+        ],
+        rows,
+    )
+}
+
+fn show_database(format: Format, database: &DatabaseView) -> Result<(), Box<dyn Error>> {
+    if format == Format::Json {
+        return show(format, database, &[], Vec::new());
+    }
+    show_databases(format, std::slice::from_ref(database))?;
+    if let Some(error) = &database.circuit.last_error {
+        println!("\nLast error: {}", error);
+    }
+    println!();
+    show_targets(format, &database.targets, &[])
+}
+
+fn circuit(circuit: &CircuitView) -> String {
+    match (&circuit.next_probe, circuit.open) {
+        (Some(next_probe), true) => format!("open until {}", next_probe),
+        _ if circuit.failures > 0 => format!("closed, {} failures", circuit.failures),
+        _ => "closed".to_string(),
+    }
+}
+
+pub async fn targets(
+    client: &ApiClient,
+    format: Format,
+    command: &TargetsCommand,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        TargetsCommand::List { database } => {
+            let targets: Vec<TargetView> = client.get(&["databases", database, "targets"]).await?;
+            let freshness: Vec<FreshnessView> = client
+                .get_with_query(&["freshness"], &[("database", database.clone())])
+                .await?;
+            show_targets(format, &targets, &freshness)?;
+        }
+        TargetsCommand::Show(args) => {
+            let target: TargetView = client.get(&target_path(args, &[])).await?;
+            show_targets(format, std::slice::from_ref(&target), &[])?;
+        }
+        TargetsCommand::Add { target, rpo } => {
+            let created: TargetView = client
+                .send(
+                    Method::POST,
+                    &["databases", &target.database, "targets"],
+                    &NewTarget {
+                        name: target.target.clone(),
+                        rpo: *rpo,
+                    },
+                )
+                .await?;
+            show_targets(format, std::slice::from_ref(&created), &[])?;
+        }
+        TargetsCommand::Update {
+            target,
+            enable,
+            disable,
+            rpo,
+        } => {
+            let update = TargetUpdate {
+                enabled: match (enable, disable) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                rpo: *rpo,
+            };
+            let updated: TargetView = client
+                .send(Method::PATCH, &target_path(target, &[]), &update)
+                .await?;
+            show_targets(format, std::slice::from_ref(&updated), &[])?;
+        }
+        TargetsCommand::Remove(target) => {
+            client.delete(&target_path(target, &[])).await?;
+            println!(
+                "Removed {}.{}, its backups are kept",
+                target.database, target.target
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Path of the target, followed by `rest`
+fn target_path<'a>(target: &'a TargetArgs, rest: &[&'a str]) -> Vec<&'a str> {
+    let mut path = vec!["databases", &target.database, "targets", &target.target];
+    path.extend(rest);
+    path
+}
+
+/// Targets with the status and age of their backup, if `freshness` has them
+fn show_targets(
+    format: Format,
+    targets: &[TargetView],
+    freshness: &[FreshnessView],
+) -> Result<(), Box<dyn Error>> {
+    let rows = targets
+        .iter()
+        .map(|target| {
+            let fresh = freshness.iter().find(|fresh| fresh.target == target.name);
+            vec![
+                target.name.clone(),
+                if target.enabled { "yes" } else { "no" }.to_string(),
+                target.last_id.to_string(),
+                optional(target.last_updated.clone()),
+                optional(fresh.and_then(|fresh| fresh.age).map(seconds)),
+                optional(fresh.map(status)),
+                optional(target.rpo.map(seconds)),
+            ]
+        })
+        .collect();
+    show(
+        format,
+        &targets,
+        &[
+            "TARGET", "ENABLED", "CURSOR", "UPDATED", "AGE", "STATUS", "RPO",
+        ],
+        rows,
+    )
+}
+
+fn status(freshness: &FreshnessView) -> String {
+    if freshness.rpo_breached {
+        "STALE".to_string()
+    } else {
+        "ok".to_string()
+    }
+}
+
+/// Exits with `EXIT_STALE` if any target is past its RPO
+pub async fn freshness(
+    client: &ApiClient,
+    format: Format,
+    filter: &TargetFilter,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut query = Vec::new();
+    if let Some(database) = &filter.database {
+        query.push(("database", database.clone()));
+    }
+    if let Some(target) = &filter.target {
+        query.push(("target", target.clone()));
+    }
+    let report: Vec<FreshnessView> = client.get_with_query(&["freshness"], &query).await?;
+
+    let rows = report
+        .iter()
+        .map(|freshness| {
+            vec![
+                format!("{}.{}", freshness.database, freshness.target),
+                status(freshness),
+                freshness
+                    .age
+                    .map(seconds)
+                    .unwrap_or_else(|| "never".to_string()),
+                optional(freshness.rpo.map(seconds)),
+                format!(
+                    "{}/{}",
+                    freshness.captured_cursor,
+                    optional(freshness.source_cursor)
+                ),
+                optional(freshness.cursor_lag),
+                optional(freshness.wal_lag_bytes),
+                optional(freshness.source_error.clone()),
+            ]
+        })
+        .collect();
+    show(
+        format,
+        &report,
+        &[
+            "TARGET",
+            "STATUS",
+            "AGE",
+            "RPO",
+            "CURSOR",
+            "CURSOR_LAG",
+            "WAL_LAG",
+            "SOURCE_ERROR",
+        ],
+        rows,
+    )?;
+
+    Ok(if report.iter().any(|freshness| freshness.rpo_breached) {
+        ExitCode::from(EXIT_STALE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Starts a backup and shows its events while it runs
+pub async fn backup(
+    client: &ApiClient,
+    format: Format,
+    args: &TargetArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let progress = if format == Format::Table && std::io::stderr().is_terminal() {
+        let mut events = client
+            .events(Some(&args.database), Some(&args.target), None)
+            .await?;
+        Some(tokio::spawn(async move {
+            while let Ok(Some(message)) = events.next().await {
+                if let Ok(event) = serde_json::from_str::<Event>(&message.data) {
+                    eprintln!("{}", describe(&event));
+                }
+            }
+        }))
+    } else {
+        None
+    };
+
+    let result = client
+        .send::<_, BackupResult>(Method::POST, &target_path(args, &["backups"]), &())
+        .await;
+    if let Some(progress) = progress {
+        // Let the last events of the run arrive
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        progress.abort();
+    }
+    let result = result?;
+
+    let rows = vec![vec![
+        result.rows_captured.to_string(),
+        result.bytes_written.to_string(),
+        result.cursor_after.to_string(),
+        result.retries.to_string(),
+    ]];
+    show(
+        format,
+        &result,
+        &["ROWS", "BYTES", "CURSOR", "RETRIES"],
+        rows,
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints run events until interrupted, reconnecting where it left off if the
+/// connection drops
+pub async fn watch(
+    client: &ApiClient,
+    format: Format,
+    args: &WatchArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut after = args.after;
+    let mut connected_once = false;
+    loop {
+        let mut events = match client
+            .events(
+                args.filter.database.as_deref(),
+                args.filter.target.as_deref(),
+                after,
+            )
+            .await
+        {
+            Ok(events) => events,
+            // A server that can't be reached at all is an error, one that
+            // restarts is waited for
+            Err(e) if !connected_once => return Err(e),
+            Err(e) => {
+                eprintln!("Reconnecting: {}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        connected_once = true;
+
+        loop {
+            let message = match events.next().await {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Reconnecting: {}", e);
+                    break;
+                }
+            };
+            after = message.id.or(after);
+            match format {
+                Format::Json => println!("{}", message.data),
+                Format::Table => match serde_json::from_str::<Event>(&message.data) {
+                    Ok(event) => println!(
+                        "{}  {}",
+                        humantime::format_rfc3339_seconds(SystemTime::now()),
+                        describe(&event)
+                    ),
+                    Err(_) => println!("{}: {}", message.event, message.data),
+                },
+            }
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+fn describe(event: &Event) -> String {
+    match event {
+        Event::RunStarted {
+            database,
+            target,
+            run_id,
+            cursor,
+        } => format!(
+            "{}.{} run {}: started at cursor {}",
+            database, target, run_id, cursor
+        ),
+        Event::BatchWritten {
+            database,
+            target,
+            run_id,
+            rows,
+            bytes,
+            cursor,
+        } => format!(
+            "{}.{} run {}: wrote {} rows ({} bytes), at cursor {}",
+            database, target, run_id, rows, bytes, cursor
+        ),
+        Event::Error {
+            database,
+            target,
+            run_id,
+            class,
+            message,
+            retrying,
+        } => format!(
+            "{}.{} run {}: {} error{}: {}",
+            database,
+            target,
+            run_id,
+            class,
+            if *retrying { ", retrying" } else { "" },
+            message
+        ),
+        Event::RunFinished {
+            database,
+            target,
+            run_id,
+            status,
+            rows_captured,
+            bytes_written,
+            cursor,
+            retries,
+        } => format!(
+            "{}.{} run {}: {}, {} rows ({} bytes), cursor {}, {} retries",
+            database, target, run_id, status, rows_captured, bytes_written, cursor, retries
+        ),
+    }
+}
+
+pub async fn runs(
+    client: &ApiClient,
+    format: Format,
+    args: &RunsArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let runs: Vec<RunView> = client
+        .get_with_query(
+            &target_path(&args.target, &["runs"]),
+            &[("limit", args.limit.to_string())],
+        )
+        .await?;
+    let rows = runs
+        .iter()
+        .map(|run| {
+            vec![
+                run.id.to_string(),
+                run.kind.clone(),
+                run.status.clone(),
+                run.started_at.clone(),
+                optional(run.finished_at.clone()),
+                format!("{}..{}", run.cursor_before, optional(run.cursor_after)),
+                run.rows_captured.to_string(),
+                run.bytes_written.to_string(),
+                run.retries.to_string(),
+                optional(run.error.clone()),
+            ]
+        })
+        .collect();
+    show(
+        format,
+        &runs,
+        &[
+            "RUN", "KIND", "STATUS", "STARTED", "FINISHED", "CURSOR", "ROWS", "BYTES", "RETRIES",
+            "ERROR",
+        ],
+        rows,
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+pub async fn segments(
+    client: &ApiClient,
+    format: Format,
+    args: &TargetArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let segments: Vec<SegmentView> = client.get(&target_path(args, &["segments"])).await?;
+    let rows = segments
+        .iter()
+        .map(|segment| {
+            vec![
+                segment.id.to_string(),
+                segment.run_id.to_string(),
+                format!("{}..{}", segment.cursor_start, segment.cursor_end),
+                segment.row_count.to_string(),
+                segment.size_bytes.to_string(),
+                segment.status.clone(),
+                segment.created_at.clone(),
+                segment.path.clone(),
+            ]
+        })
+        .collect();
+    show(
+        format,
+        &segments,
+        &[
+            "SEGMENT", "RUN", "CURSOR", "ROWS", "BYTES", "STATUS", "CREATED", "PATH",
+        ],
+        rows,
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Restores into a database, or streams the rows to a file or stdout
+///
+/// Rows written to a file are counted on stderr as they arrive.
+pub async fn restore(
+    client: &ApiClient,
+    format: Format,
+    args: &RestoreArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let path = target_path(&args.target, &["restores"]);
+    let request = RestoreRequest {
+        to_cursor: args.to_cursor,
+        into: args.into.clone(),
+    };
+
+    if args.into.is_some() {
+        eprintln!(
+            "Restoring {}.{} into {}...",
+            args.target.database,
+            args.target.target,
+            args.into.as_deref().unwrap_or_default()
+        );
+        let result: RestoreResult = client.send(Method::POST, &path, &request).await?;
+        let rows = vec![vec![
+            result.database.clone(),
+            result.rows.to_string(),
+            result.inserted.to_string(),
+            result
+                .gaps
+                .iter()
+                .map(|gap| format!("({}, {}]", gap.from, gap.to))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ]];
+        show(
+            format,
+            &result,
+            &["DATABASE", "ROWS", "INSERTED", "GAPS"],
+            rows,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut response = client.open(Method::POST, &path, &request).await?;
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(file) => Box::new(File::create(file)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let show_progress = args.output.is_some() && std::io::stderr().is_terminal();
+    let mut rows = 0usize;
+    while let Some(chunk) = response.chunk().await? {
+        out.write_all(&chunk)?;
+        rows += chunk.iter().filter(|byte| **byte == b'\n').count();
+        if show_progress {
+            eprint!("\r{} rows", rows);
+        }
+    }
+    out.flush()?;
+    if let Some(file) = &args.output {
+        if show_progress {
+            eprintln!();
+        }
+        eprintln!("Restored {} rows to {}", rows, file);
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,3 +1,57 @@
-fn main() {
-    println!("Hello, world!");
+use clap::Parser;
+use std::process::ExitCode;
+
+mod cli;
+mod client;
+mod commands;
+mod output;
+mod profiles;
+
+use crate::cli::{Cli, Command};
+
+/// The command ran into an error, or the server refused it
+pub const EXIT_FAILURE: u8 = 1;
+/// At least one target is past its RPO
+pub const EXIT_STALE: u8 = 4;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // clap exits with 2 on usage errors
+    let cli = Cli::parse();
+    let format = cli.format;
+
+    let result = match &cli.command {
+        Command::Login(args) => commands::login(args).await,
+        Command::Logout => commands::logout(&cli).await,
+        Command::Profiles(command) => commands::profiles(command, format),
+        command => {
+            let client = match commands::connect(&cli) {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::from(EXIT_FAILURE);
+                }
+            };
+            match command {
+                Command::Whoami => commands::whoami(&client, format).await,
+                Command::Databases(command) => commands::databases(&client, format, command).await,
+                Command::Targets(command) => commands::targets(&client, format, command).await,
+                Command::Freshness(filter) => commands::freshness(&client, format, filter).await,
+                Command::Backup(args) => commands::backup(&client, format, args).await,
+                Command::Watch(args) => commands::watch(&client, format, args).await,
+                Command::Runs(args) => commands::runs(&client, format, args).await,
+                Command::Segments(args) => commands::segments(&client, format, args).await,
+                Command::Restore(args) => commands::restore(&client, format, args).await,
+                Command::Login(_) | Command::Logout | Command::Profiles(_) => unreachable!(),
+            }
+        }
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
 }
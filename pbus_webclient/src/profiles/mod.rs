@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A server and the token to use with it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub server: String,
+    pub user: String,
+    pub token: String,
+    /// Id of the token on the server, to revoke it on logout
+    pub token_id: i64,
+}
+
+/// Stored profiles and which one is used by default
+///
+/// Kept as JSON at `$PBUS_PROFILES`, or `pbus/profiles.json` in the user's
+/// config directory. The file holds tokens, so it is only readable by its owner.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Profiles {
+    pub current: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub fn path() -> Result<PathBuf, Box<dyn Error>> {
+        if let Some(path) = std::env::var_os("PBUS_PROFILES") {
+            return Ok(PathBuf::from(path));
+        }
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".config"),
+                None => return Err("Can't find a config directory, set PBUS_PROFILES".into()),
+            },
+        };
+        Ok(config_dir.join("pbus").join("profiles.json"))
+    }
+
+    /// The stored profiles, none if nothing was stored yet
+    pub fn load() -> Result<Profiles, Box<dyn Error>> {
+        let path = Profiles::path()?;
+        if !path.exists() {
+            return Ok(Profiles::default());
+        }
+        let contents = fs::read_to_string(&path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("{} is not a profiles file: {}", path.display(), e).into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Profiles::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Created owner-only and renamed into place, so the tokens are never
+        // readable by others or left half written
+        let temp_path = path.with_extension("json.tmp");
+        if temp_path.exists() {
+            fs::remove_file(&temp_path)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&temp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// The named profile, or the current one
+    pub fn get(&self, name: Option<&str>) -> Option<(&str, &Profile)> {
+        let name = name.or(self.current.as_deref())?;
+        self.profiles
+            .get_key_value(name)
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+}
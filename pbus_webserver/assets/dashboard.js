@@ -0,0 +1,679 @@
+"use strict";
+
+// The dashboard signs in by creating an API token with the user's password and
+// keeps it for the browser tab only. Everything it shows comes from the API.
+
+const TOKEN_KEY = "pbus.token";
+const ROLES = ["viewer", "operator", "admin"];
+const RUNS_SHOWN = 10;
+const FAILURES_SHOWN = 10;
+const ACTIVITY_SHOWN = 100;
+const REFRESH_INTERVAL_MS = 60000;
+
+let session = null;
+let refreshTimer = null;
+let events = null;
+// Progress of running backups by `database.target`, from the event stream
+const progress = new Map();
+
+function $(id) {
+  return document.getElementById(id);
+}
+
+// Builds an element, strings become text so nothing is parsed as HTML
+function el(tag, attrs, ...children) {
+  const node = document.createElement(tag);
+  for (const [name, value] of Object.entries(attrs || {})) {
+    if (name.startsWith("on")) {
+      node.addEventListener(name.slice(2), value);
+    } else if (value === true) {
+      node.setAttribute(name, "");
+    } else if (value !== false && value != null) {
+      node.setAttribute(name, value);
+    }
+  }
+  for (const child of children.flat()) {
+    if (child != null) {
+      node.append(child instanceof Node ? child : String(child));
+    }
+  }
+  return node;
+}
+
+function storedToken() {
+  const stored = sessionStorage.getItem(TOKEN_KEY);
+  return stored ? JSON.parse(stored) : null;
+}
+
+class ApiError extends Error {
+  constructor(status, message) {
+    super(`${status}: ${message}`);
+    this.status = status;
+  }
+}
+
+// Calls the API below `/api`, `path` is a list of segments
+async function api(method, path, { body, query, authorization } = {}) {
+  const token = storedToken();
+  const headers = { Accept: "application/json" };
+  if (authorization) {
+    headers.Authorization = authorization;
+  } else if (token) {
+    headers.Authorization = `Bearer ${token.token}`;
+  }
+  if (body !== undefined) {
+    headers["Content-Type"] = "application/json";
+  }
+  let url = "/api/" + path.map(encodeURIComponent).join("/");
+  if (query) {
+    url += "?" + new URLSearchParams(query);
+  }
+  const response = await fetch(url, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+  if (!response.ok) {
+    let message = response.statusText;
+    try {
+      message = (await response.json()).error;
+    } catch (_) {
+      // Not an error body
+    }
+    if (response.status === 401 && !authorization) {
+      signedOut();
+    }
+    throw new ApiError(response.status, message);
+  }
+  const type = response.headers.get("Content-Type") || "";
+  return type.includes("application/json") ? response.json() : null;
+}
+
+function can(role) {
+  return session !== null && ROLES.indexOf(session.role) >= ROLES.indexOf(role);
+}
+
+function bytes(count) {
+  if (count == null) {
+    return "-";
+  }
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = count;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${unit === 0 ? value : value.toFixed(1)} ${units[unit]}`;
+}
+
+function duration(secs) {
+  if (secs == null) {
+    return "-";
+  }
+  const parts = [];
+  for (const [size, unit] of [[86400, "d"], [3600, "h"], [60, "m"], [1, "s"]]) {
+    if (secs >= size || (unit === "s" && parts.length === 0)) {
+      parts.push(`${Math.floor(secs / size)}${unit}`);
+      secs %= size;
+    }
+  }
+  return parts.slice(0, 2).join(" ");
+}
+
+function time(timestamp) {
+  return timestamp ? new Date(timestamp).toLocaleString() : "never";
+}
+
+function ago(timestamp) {
+  if (!timestamp) {
+    return "never";
+  }
+  return `${duration(Math.max(0, Math.round((Date.now() - Date.parse(timestamp)) / 1000)))} ago`;
+}
+
+function until(timestamp) {
+  const secs = Math.round((Date.parse(timestamp) - Date.now()) / 1000);
+  return secs > 0 ? `in ${duration(secs)}` : "now";
+}
+
+function notify(message, kind) {
+  const notice = $("notice");
+  notice.textContent = message;
+  notice.className = `notice ${kind || ""}`;
+  notice.hidden = false;
+}
+
+// Signing in and out
+
+async function signIn(event) {
+  event.preventDefault();
+  const form = event.target;
+  const user = form.user.value;
+  const basic = "Basic " + btoa(unescape(encodeURIComponent(`${user}:${form.password.value}`)));
+  $("login-error").textContent = "";
+  try {
+    const created = await api("POST", ["tokens"], {
+      body: { label: "dashboard" },
+      authorization: basic,
+    });
+    sessionStorage.setItem(TOKEN_KEY, JSON.stringify({ id: created.id, token: created.token }));
+    form.reset();
+    await start();
+  } catch (e) {
+    $("login-error").textContent = e.status === 401 ? "Wrong user or password" : e.message;
+  }
+}
+
+async function signOut() {
+  const token = storedToken();
+  if (token) {
+    try {
+      await api("DELETE", ["tokens", String(token.id)]);
+    } catch (_) {
+      // The token is forgotten either way
+    }
+  }
+  signedOut();
+}
+
+function signedOut() {
+  sessionStorage.removeItem(TOKEN_KEY);
+  session = null;
+  clearInterval(refreshTimer);
+  if (events) {
+    events.abort();
+    events = null;
+  }
+  $("app").hidden = true;
+  $("session").hidden = true;
+  $("login").hidden = false;
+}
+
+async function start() {
+  session = await api("GET", ["session"]);
+  $("session-user").textContent = `${session.user} (${session.role})`;
+  $("login").hidden = true;
+  $("session").hidden = false;
+  $("app").hidden = false;
+  await refresh();
+  clearInterval(refreshTimer);
+  refreshTimer = setInterval(refresh, REFRESH_INTERVAL_MS);
+  watchEvents();
+}
+
+// Loading and showing everything
+
+async function refresh() {
+  try {
+    const [databases, freshness, storage, worker] = await Promise.all([
+      api("GET", ["databases"]),
+      api("GET", ["freshness"]),
+      api("GET", ["storage"]),
+      // Without a worker nothing runs on schedule, the rest still works
+      api("GET", ["worker"]).catch((e) => (e.status === 503 ? null : Promise.reject(e))),
+    ]);
+    const runs = new Map();
+    await Promise.all(
+      databases.flatMap((database) =>
+        database.targets.map(async (target) => {
+          const found = await api("GET", targetPath(database, target, "runs"), {
+            query: { limit: RUNS_SHOWN },
+          });
+          runs.set(`${database.name}.${target.name}`, found);
+        })
+      )
+    );
+    showWorker(worker);
+    showStorage(storage);
+    showFailures([...runs.values()].flat());
+    showDatabases(databases, freshness, storage, worker, runs);
+  } catch (e) {
+    if (e.status !== 401) {
+      notify(`Can't load the dashboard: ${e.message}`, "bad");
+    }
+  }
+}
+
+function showWorker(worker) {
+  const shown = $("worker");
+  if (!worker) {
+    shown.replaceChildren(
+      el("span", { class: "bad" }, "No worker is running."),
+      " Nothing is backed up on schedule, backups started here still run."
+    );
+    return;
+  }
+  shown.replaceChildren(
+    el("span", { class: "ok" }, "The worker is running"),
+    ` since ${time(worker.started_at)} as process ${worker.pid}.`
+  );
+}
+
+function showStorage(storage) {
+  const used = storage.targets.reduce((sum, target) => sum + target.bytes, 0);
+  const disk = $("disk");
+  disk.replaceChildren(`Backups take ${bytes(used)}.`);
+  if (storage.total_bytes && storage.available_bytes != null) {
+    const percent = Math.round(100 - (storage.available_bytes * 100) / storage.total_bytes);
+    disk.append(
+      " The data directory's disk is ",
+      el("span", { class: percent >= 90 ? "bad" : percent >= 75 ? "warn" : "ok" }, `${percent}% full`),
+      `, ${bytes(storage.available_bytes)} of ${bytes(storage.total_bytes)} left.`
+    );
+  }
+}
+
+function showFailures(runs) {
+  const failed = runs
+    .filter((run) => run.status === "failed" || run.retries > 0)
+    .sort((a, b) => Date.parse(b.started_at) - Date.parse(a.started_at))
+    .slice(0, FAILURES_SHOWN);
+  const container = $("failures");
+  if (failed.length === 0) {
+    container.replaceChildren(el("p", { class: "muted" }, "No failed or retried runs recently."));
+    return;
+  }
+  container.replaceChildren(
+    el(
+      "table",
+      {},
+      el("tr", {}, ["Started", "Target", "Kind", "Status", "Retries", "Error"].map((title) => el("th", {}, title))),
+      failed.map((run) =>
+        el(
+          "tr",
+          { class: run.status === "failed" ? "failed" : null },
+          el("td", {}, time(run.started_at)),
+          el("td", {}, `${run.database}.${run.target}`),
+          el("td", {}, run.kind),
+          el("td", {}, run.status),
+          el("td", {}, run.retries),
+          el("td", {}, run.error || "")
+        )
+      )
+    )
+  );
+}
+
+function showDatabases(databases, freshness, storage, worker, runs) {
+  const container = $("databases");
+  if (databases.length === 0) {
+    container.replaceChildren(el("section", {}, el("p", { class: "muted" }, "No databases are configured.")));
+    return;
+  }
+  container.replaceChildren(
+    ...databases.map((database) => {
+      const find = (list, target) =>
+        list.find((item) => item.database === database.name && item.target === target.name);
+      return el(
+        "section",
+        {},
+        el("h2", {}, database.name, " ", el("span", { class: "muted" }, `${database.host}:${database.port}`)),
+        circuit(database),
+        el(
+          "table",
+          {},
+          el(
+            "tr",
+            {},
+            ["Target", "Schedule", "Last backup", "Freshness", "Cursor", "Stored", "Restorable since", ""].map(
+              (title) => el("th", {}, title)
+            )
+          ),
+          database.targets.map((target) =>
+            targetRow(
+              database,
+              target,
+              find(freshness, target),
+              find(storage.targets, target),
+              worker && find(worker.targets, target)
+            )
+          )
+        ),
+        database.targets.map((target) => recentRuns(target, runs.get(`${database.name}.${target.name}`) || []))
+      );
+    })
+  );
+}
+
+function circuit(database) {
+  if (!database.circuit.open) {
+    return null;
+  }
+  return el(
+    "p",
+    { class: "notice bad" },
+    `Backups are paused after ${database.circuit.failures} failed runs in a row, ` +
+      `next try ${time(database.circuit.next_probe)}: ${database.circuit.last_error || "unknown error"}`
+  );
+}
+
+function targetRow(database, target, freshness, stored, scheduled) {
+  const key = `${database.name}.${target.name}`;
+  const rpo = freshness ? freshness.rpo : target.rpo || database.rpo;
+
+  let fresh = el("span", { class: "muted" }, "unknown");
+  if (freshness) {
+    fresh = el(
+      "span",
+      { class: freshness.rpo_breached ? "bad" : "ok", title: freshness.source_error || "" },
+      freshness.rpo_breached ? "Past RPO" : "Fresh",
+      ` (${duration(freshness.age)} of ${rpo ? duration(rpo) : "no RPO"})`
+    );
+  }
+
+  let cursor = String(target.last_id);
+  if (freshness && freshness.source_cursor != null) {
+    cursor += ` of ${freshness.source_cursor}`;
+  } else if (freshness && freshness.source_error) {
+    cursor += " (source unreachable)";
+  }
+
+  const enabled = el("input", {
+    type: "checkbox",
+    checked: target.enabled,
+    disabled: !can("operator"),
+    title: can("operator") ? "Back up on schedule" : "Needs the operator role",
+    onchange: (event) => toggleTarget(database, target, event.target),
+  });
+
+  let schedule = null;
+  if (scheduled && scheduled.schedule === "scheduled") {
+    schedule = el("div", { class: "muted", title: time(scheduled.next_hit) }, `next ${until(scheduled.next_hit)}`);
+  } else if (scheduled && scheduled.schedule === "paused") {
+    schedule = el("div", { class: "warn" }, "paused");
+  } else if (scheduled && scheduled.schedule === "suspended") {
+    schedule = el("div", { class: "bad", title: "Fix the error of its last run in the config" }, "suspended");
+  }
+
+  const actions = el("td", { class: "actions" });
+  if (can("operator")) {
+    actions.append(
+      el("button", { type: "button", onclick: (event) => backUp(database, target, event.target) }, "Back up"),
+      scheduled && ["scheduled", "paused"].includes(scheduled.schedule)
+        ? el(
+            "button",
+            {
+              type: "button",
+              onclick: (event) => setPaused(database, target, scheduled.schedule !== "paused", event.target),
+            },
+            scheduled.schedule === "paused" ? "Resume" : "Pause"
+          )
+        : null,
+      el(
+        "button",
+        { type: "button", disabled: !stored || stored.segments === 0, onclick: (event) => drill(database, target, event.target) },
+        "Restore drill"
+      )
+    );
+  }
+
+  return el(
+    "tr",
+    {},
+    el("td", {}, target.name, el("div", { class: "muted", id: `progress-${key}` }, progress.get(key) || "")),
+    el("td", {}, enabled, schedule),
+    el("td", { title: time(target.last_updated) }, ago(target.last_updated)),
+    el("td", {}, fresh),
+    el("td", {}, cursor),
+    el(
+      "td",
+      {},
+      stored ? `${bytes(stored.bytes)} in ${stored.segments} segments` : "-",
+      stored && stored.corrupt_segments > 0 ? el("div", { class: "bad" }, `${stored.corrupt_segments} corrupt`) : null
+    ),
+    el(
+      "td",
+      {},
+      stored && stored.oldest_segment ? time(stored.oldest_segment) : "-",
+      el("div", { class: "muted" }, database.retention ? `retention ${duration(database.retention)}` : "no retention"),
+      stored && stored.past_retention > 0
+        ? el("div", { class: "muted" }, `${stored.past_retention} segments older than that`)
+        : null
+    ),
+    actions
+  );
+}
+
+function recentRuns(target, runs) {
+  const failed = runs.filter((run) => run.status === "failed").length;
+  return el(
+    "details",
+    {},
+    el(
+      "summary",
+      {},
+      `Recent runs of ${target.name}`,
+      failed > 0 ? el("span", { class: "bad" }, ` (${failed} failed)`) : null
+    ),
+    runs.length === 0
+      ? el("p", { class: "muted" }, "No runs yet.")
+      : el(
+          "table",
+          {},
+          el(
+            "tr",
+            {},
+            ["Started", "Took", "Kind", "Status", "Rows", "Written", "Retries", "Error"].map((title) => el("th", {}, title))
+          ),
+          runs.map((run) =>
+            el(
+              "tr",
+              { class: run.status === "failed" ? "failed" : null },
+              el("td", {}, time(run.started_at)),
+              el(
+                "td",
+                {},
+                run.finished_at
+                  ? duration(Math.round((Date.parse(run.finished_at) - Date.parse(run.started_at)) / 1000))
+                  : "-"
+              ),
+              el("td", {}, run.kind),
+              el("td", {}, run.status),
+              el("td", {}, run.rows_captured),
+              el("td", {}, bytes(run.bytes_written)),
+              el("td", {}, run.retries),
+              el("td", {}, run.error || "")
+            )
+          )
+        )
+  );
+}
+
+// Actions
+
+function targetPath(database, target, ...rest) {
+  return ["databases", database.name, "targets", target.name, ...rest];
+}
+
+async function backUp(database, target, button) {
+  button.disabled = true;
+  notify(`Backing up ${database.name}.${target.name}...`);
+  try {
+    const result = await api("POST", targetPath(database, target, "backups"));
+    notify(
+      `${database.name}.${target.name}: captured ${result.rows_captured} rows ` +
+        `(${bytes(result.bytes_written)}), now at id ${result.cursor_after}`,
+      "ok"
+    );
+  } catch (e) {
+    notify(`Backup of ${database.name}.${target.name} failed: ${e.message}`, "bad");
+  }
+  button.disabled = false;
+  await refresh();
+}
+
+async function toggleTarget(database, target, checkbox) {
+  checkbox.disabled = true;
+  try {
+    await api("PATCH", targetPath(database, target), { body: { enabled: checkbox.checked } });
+    notify(`${database.name}.${target.name} is ${checkbox.checked ? "enabled" : "disabled"}`, "ok");
+  } catch (e) {
+    checkbox.checked = !checkbox.checked;
+    notify(`Can't change ${database.name}.${target.name}: ${e.message}`, "bad");
+  }
+  checkbox.disabled = false;
+}
+
+async function setPaused(database, target, paused, button) {
+  button.disabled = true;
+  try {
+    await api("POST", targetPath(database, target, paused ? "pause" : "resume"));
+    notify(`${database.name}.${target.name} is ${paused ? "paused" : "scheduled again"}`, "ok");
+  } catch (e) {
+    notify(`Can't ${paused ? "pause" : "resume"} ${database.name}.${target.name}: ${e.message}`, "bad");
+  }
+  button.disabled = false;
+  await refresh();
+}
+
+async function drill(database, target, button) {
+  button.disabled = true;
+  notify(`Restoring the backup of ${database.name}.${target.name} into the scratch server...`);
+  try {
+    const result = await api("POST", targetPath(database, target, "drills"));
+    let message = `Restore drill of ${database.name}.${target.name} `;
+    if (result.passed) {
+      message += `passed: restored ${result.restored} rows from ${result.segments} segments up to id ${result.cursor}, ${result.sampled} sampled rows match the source`;
+    } else if (result.corrupt_segment) {
+      message += `failed: segment ${result.corrupt_segment} is corrupt`;
+    } else if (result.gaps.length > 0) {
+      const gaps = result.gaps.map((gap) => `(${gap.from}, ${gap.to}]`).join(", ");
+      message += `failed: ${result.rows} rows, but no segment covers ids ${gaps}`;
+    } else if (result.restored !== result.rows) {
+      message += `failed: restored ${result.restored} of ${result.rows} rows`;
+    } else {
+      message += `failed: ${result.mismatched.length} of ${result.sampled} sampled rows differ from the source, ids ${result.mismatched.join(", ")}`;
+    }
+    notify(`${message}, took ${result.duration_ms} ms`, result.passed ? "ok" : "bad");
+  } catch (e) {
+    notify(`Restore drill of ${database.name}.${target.name} failed: ${e.message}`, "bad");
+  }
+  button.disabled = false;
+}
+
+// Live activity
+
+// Follows `/api/events`, EventSource can't send a token so the stream is read
+// with fetch
+async function watchEvents() {
+  if (events) {
+    events.abort();
+  }
+  const controller = new AbortController();
+  events = controller;
+  let after = null;
+  while (!controller.signal.aborted) {
+    try {
+      const token = storedToken();
+      const response = await fetch(after === null ? "/api/events" : `/api/events?after=${after}`, {
+        headers: { Authorization: `Bearer ${token.token}`, Accept: "text/event-stream" },
+        signal: controller.signal,
+      });
+      if (response.status === 401) {
+        signedOut();
+        return;
+      }
+      const reader = response.body.getReader();
+      const decoder = new TextDecoder();
+      let buffer = "";
+      for (;;) {
+        const { value, done } = await reader.read();
+        if (done) {
+          break;
+        }
+        buffer += decoder.decode(value, { stream: true });
+        let end;
+        while ((end = buffer.indexOf("\n\n")) >= 0) {
+          const message = parseEvent(buffer.slice(0, end));
+          buffer = buffer.slice(end + 2);
+          if (message) {
+            after = message.id;
+            showEvent(JSON.parse(message.data));
+          }
+        }
+      }
+    } catch (e) {
+      if (controller.signal.aborted) {
+        return;
+      }
+    }
+    // Reconnect after the stream broke off, with what was missed meanwhile
+    await new Promise((resolve) => setTimeout(resolve, 3000));
+  }
+}
+
+function parseEvent(block) {
+  const message = { id: null, data: "" };
+  for (const line of block.split("\n")) {
+    const colon = line.indexOf(":");
+    const field = colon < 0 ? line : line.slice(0, colon);
+    const value = colon < 0 ? "" : line.slice(colon + 1).replace(/^ /, "");
+    if (field === "id") {
+      message.id = Number(value);
+    } else if (field === "data") {
+      message.data += (message.data ? "\n" : "") + value;
+    }
+  }
+  return message.data ? message : null;
+}
+
+function showEvent(event) {
+  const key = `${event.database}.${event.target}`;
+  let text;
+  switch (event.type) {
+    case "run-started":
+      text = `run ${event.run_id} started at id ${event.cursor}`;
+      progress.set(key, "backing up...");
+      break;
+    case "batch-written":
+      text = `${event.rows} rows so far, at id ${event.cursor}`;
+      progress.set(key, text);
+      break;
+    case "error":
+      text = `${event.class} error: ${event.message}${event.retrying ? ", retrying" : ""}`;
+      break;
+    case "run-finished":
+      text = `run ${event.run_id} ${event.status}, ${event.rows_captured} rows, at id ${event.cursor}`;
+      progress.delete(key);
+      scheduleRefresh();
+      break;
+    default:
+      text = event.type;
+  }
+  const shown = $(`progress-${key}`);
+  if (shown) {
+    shown.textContent = progress.get(key) || "";
+  }
+
+  const activity = $("activity");
+  activity.prepend(
+    el(
+      "li",
+      { class: event.type === "error" ? "bad" : null },
+      `${new Date().toLocaleTimeString()} ${key}: ${text}`
+    )
+  );
+  while (activity.children.length > ACTIVITY_SHOWN) {
+    activity.lastChild.remove();
+  }
+}
+
+let refreshPending = null;
+
+// Runs often finish together, so refreshing waits a moment for the others
+function scheduleRefresh() {
+  clearTimeout(refreshPending);
+  refreshPending = setTimeout(refresh, 1000);
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  $("login").addEventListener("submit", signIn);
+  $("logout").addEventListener("click", signOut);
+  $("refresh").addEventListener("click", refresh);
+  if (storedToken()) {
+    start().catch(signedOut);
+  } else {
+    signedOut();
+  }
+});
@@ -0,0 +1,47 @@
+use axum::extract::{Query, State};
+use axum::Json;
+use pbus_db_manager::{AuthStore, Role};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::api::{run_blocking, ApiError, AppState};
+use crate::auth::Caller;
+use crate::models::*;
+
+#[derive(Deserialize, IntoParams, Debug)]
+pub struct AuditQuery {
+    /// Most entries to return, newest first
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+}
+
+fn default_limit() -> u32 {
+    100
+}
+
+/// Every request that tried to change something, allowed or not
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "access",
+    params(AuditQuery),
+    responses((status = 200, description = "Audit entries, newest first", body = [AuditView]))
+)]
+pub async fn list_audit(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditView>>, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let auth = AuthStore::open(&app.base_mount_point)?;
+        Ok(Json(
+            auth.get_audit(query.limit)?
+                .into_iter()
+                .map(audit_view)
+                .collect(),
+        ))
+    })
+    .await
+}
@@ -0,0 +1,233 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use pbus_config_handler::{Database, SecretRef};
+use pbus_db_manager::{Role, StateStore};
+use pbus_timer::onboarding;
+use std::sync::Arc;
+use std::time::SystemTime;
+use utility::PbusError;
+
+use crate::api::{
+    database_view, edit_config, find_database, read_config, reload_worker, run_blocking,
+};
+use crate::api::{ApiError, AppState};
+use crate::auth::Caller;
+use crate::models::*;
+
+#[utoipa::path(
+    get,
+    path = "/api/databases",
+    tag = "databases",
+    responses((status = 200, description = "Every configured database", body = [DatabaseView]))
+)]
+pub async fn list_databases(
+    State(app): State<Arc<AppState>>,
+) -> Result<Json<Vec<DatabaseView>>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        let views = config
+            .get_databases()
+            .iter()
+            .map(|database| database_view(&state, database))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Json(views))
+    })
+    .await
+}
+
+/// Connects to the database, then adds it with the chosen tables as targets
+#[utoipa::path(
+    post,
+    path = "/api/databases",
+    tag = "databases",
+    request_body = NewDatabase,
+    responses(
+        (status = 201, description = "The database was added", body = DatabaseView),
+        (status = 400, description = "Invalid or already configured", body = ErrorBody),
+        (status = 422, description = "A chosen table can't be backed up", body = ErrorBody),
+        (status = 502, description = "The database can't be reached", body = ErrorBody),
+    )
+)]
+pub async fn create_database(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Json(request): Json<NewDatabase>,
+) -> Result<(StatusCode, Json<DatabaseView>), ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let password: SecretRef = request.password.parse().map_err(ApiError::bad_request)?;
+        let mut database = Database::new(
+            request.host,
+            request.port,
+            request.user,
+            request.name.clone(),
+            password,
+            Vec::new(),
+            request.update_interval,
+            SystemTime::now(),
+        );
+        database.rpo = request.rpo;
+
+        let candidates = onboarding::discover_tables(&app.base_mount_point, &database).await?;
+        let selected: Vec<String> = if request.all {
+            candidates
+                .iter()
+                .filter(|candidate| candidate.is_selectable())
+                .map(|candidate| candidate.name.clone())
+                .collect()
+        } else {
+            request.targets
+        };
+        if selected.is_empty() {
+            return Err(ApiError::bad_request("no targets selected"));
+        }
+
+        let config =
+            onboarding::add_database(&app.base_mount_point, database, &candidates, &selected)?;
+        reload_worker(&app.base_mount_point).await;
+        let state = StateStore::open(&app.base_mount_point)?;
+        let view = database_view(&state, find_database(&config, &request.name)?)?;
+        Ok((StatusCode::CREATED, Json(view)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/databases/{database}",
+    tag = "databases",
+    params(("database" = String, Path, description = "Name of the database")),
+    responses(
+        (status = 200, body = DatabaseView),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_database(
+    State(app): State<Arc<AppState>>,
+    Path(database_name): Path<String>,
+) -> Result<Json<DatabaseView>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        Ok(Json(database_view(
+            &state,
+            find_database(&config, &database_name)?,
+        )?))
+    })
+    .await
+}
+
+/// Changes connection settings, the update interval or the RPO
+///
+/// The worker picks up the change without a restart.
+#[utoipa::path(
+    patch,
+    path = "/api/databases/{database}",
+    tag = "databases",
+    params(("database" = String, Path, description = "Name of the database")),
+    request_body = DatabaseUpdate,
+    responses(
+        (status = 200, body = DatabaseView),
+        (status = 400, description = "The change would leave the config invalid", body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn update_database(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(database_name): Path<String>,
+    Json(update): Json<DatabaseUpdate>,
+) -> Result<Json<DatabaseView>, ApiError> {
+    // Only admins may change how the database is reached
+    if update.host.is_some()
+        || update.port.is_some()
+        || update.user.is_some()
+        || update.password.is_some()
+    {
+        caller.require(Role::Admin)?;
+    } else {
+        caller.require(Role::Operator)?;
+    }
+    run_blocking(move || async move {
+        find_database(&read_config(&app.base_mount_point)?, &database_name)?;
+        let password = match update.password {
+            Some(password) => Some(
+                password
+                    .parse::<SecretRef>()
+                    .map_err(ApiError::bad_request)?,
+            ),
+            None => None,
+        };
+
+        let config = edit_config(&app.base_mount_point, |config| {
+            let database = config.get_database(&database_name).ok_or_else(|| {
+                PbusError::config(format!("database {} is not configured", database_name))
+            })?;
+            if let Some(host) = update.host {
+                database.database_host = host;
+            }
+            if let Some(port) = update.port {
+                database.server_port = port;
+            }
+            if let Some(user) = update.user {
+                database.database_user = user;
+            }
+            if let Some(password) = password {
+                database.database_password = password;
+            }
+            if let Some(update_interval) = update.update_interval {
+                database.update_interval = update_interval;
+            }
+            if let Some(rpo) = update.rpo {
+                database.rpo = (rpo > 0).then_some(rpo);
+            }
+            if let Some(retention) = update.retention {
+                database.retention = (retention > 0).then_some(retention);
+            }
+            Ok(())
+        })?;
+        reload_worker(&app.base_mount_point).await;
+
+        let state = StateStore::open(&app.base_mount_point)?;
+        Ok(Json(database_view(
+            &state,
+            find_database(&config, &database_name)?,
+        )?))
+    })
+    .await
+}
+
+/// Takes the database out of the config
+///
+/// Its backups stay in the catalog and can still be restored.
+#[utoipa::path(
+    delete,
+    path = "/api/databases/{database}",
+    tag = "databases",
+    params(("database" = String, Path, description = "Name of the database")),
+    responses(
+        (status = 204, description = "The database was removed"),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn delete_database(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(database_name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        find_database(&read_config(&app.base_mount_point)?, &database_name)?;
+        edit_config(&app.base_mount_point, |config| {
+            config.remove_database(&database_name).ok_or_else(|| {
+                PbusError::config(format!("database {} is not configured", database_name))
+            })?;
+            Ok(())
+        })?;
+        reload_worker(&app.base_mount_point).await;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
@@ -0,0 +1,118 @@
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use pbus_db_manager::{EventLog, LoggedEvent};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::api::{run_blocking, ApiError, AppState};
+
+/// How often the event log is checked for new events
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most events read from the log at once
+const READ_LIMIT: u32 = 500;
+
+#[derive(Deserialize, IntoParams, Debug, Clone)]
+pub struct EventsQuery {
+    /// Only events of this database
+    pub database: Option<String>,
+    /// Only events of targets with this name
+    pub target: Option<String>,
+    /// Start after this event id instead of with new events, `Last-Event-ID`
+    /// takes precedence
+    pub after: Option<i64>,
+}
+
+impl EventsQuery {
+    fn matches(&self, event: &LoggedEvent) -> bool {
+        self.database
+            .as_ref()
+            .is_none_or(|database| *database == event.database_name)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| *target == event.target_name)
+    }
+}
+
+/// Streams the progress of backup runs as server-sent events
+///
+/// Covers runs of the worker and runs started through the API alike. Each event
+/// is named after its `type` (`run-started`, `batch-written`, `error` or
+/// `run-finished`), carries it as JSON and has an id, so a client that
+/// reconnects with `Last-Event-ID` misses nothing that is still in the log.
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "backups",
+    params(EventsQuery),
+    responses((status = 200, description = "A stream of run events", content_type = "text/event-stream"))
+)]
+pub async fn stream_events(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, ApiError> {
+    let resume = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+        .or(query.after);
+    let after = match resume {
+        Some(after) => after,
+        None => {
+            let app = app.clone();
+            run_blocking(
+                move || async move { Ok(EventLog::open(&app.base_mount_point)?.last_id()?) },
+            )
+            .await?
+        }
+    };
+
+    let events = stream::unfold((after, VecDeque::new()), move |(mut after, mut pending)| {
+        let app = app.clone();
+        let query = query.clone();
+        async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((Ok(event), (after, pending)));
+                }
+                match read_events(&app, after).await {
+                    Ok(logged) => {
+                        for event in logged {
+                            after = event.id;
+                            if query.matches(&event) {
+                                pending.push_back(
+                                    SseEvent::default()
+                                        .id(event.id.to_string())
+                                        .event(event.kind)
+                                        .data(event.payload),
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Can't read the event log: {}", e.message),
+                }
+                if pending.is_empty() {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn read_events(app: &Arc<AppState>, after: i64) -> Result<Vec<LoggedEvent>, ApiError> {
+    let app = app.clone();
+    run_blocking(move || async move {
+        Ok(EventLog::open(&app.base_mount_point)?.get_events_after(after, READ_LIMIT)?)
+    })
+    .await
+}
@@ -0,0 +1,65 @@
+use axum::extract::{Query, State};
+use axum::Json;
+use pbus_db_manager::{Catalog, StateStore};
+use pbus_timer::freshness::check_freshness;
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::api::{read_config, run_blocking, ApiError, AppState};
+use crate::models::*;
+
+#[derive(Deserialize, IntoParams, Debug)]
+pub struct FreshnessQuery {
+    /// Only targets of this database
+    pub database: Option<String>,
+    /// Only targets with this name
+    pub target: Option<String>,
+}
+
+/// How far each backup trails its source, and whether it is past its RPO
+///
+/// Every matching database is queried for its current cursor, one that can't
+/// be reached has `source_error` set instead.
+#[utoipa::path(
+    get,
+    path = "/api/freshness",
+    tag = "backups",
+    params(FreshnessQuery),
+    responses((status = 200, body = [FreshnessView]))
+)]
+pub async fn list_freshness(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<FreshnessQuery>,
+) -> Result<Json<Vec<FreshnessView>>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+
+        let mut views = Vec::new();
+        for database in config.get_databases() {
+            if query
+                .database
+                .as_ref()
+                .is_some_and(|name| *name != database.database_name)
+            {
+                continue;
+            }
+            let report = check_freshness(&app.base_mount_point, &catalog, &state, database).await?;
+            views.extend(
+                report
+                    .into_iter()
+                    .filter(|freshness| {
+                        query
+                            .target
+                            .as_ref()
+                            .is_none_or(|name| *name == freshness.target_name)
+                    })
+                    .map(freshness_view),
+            );
+        }
+        Ok(Json(views))
+    })
+    .await
+}
@@ -0,0 +1,363 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, patch, post};
+use axum::{middleware, Json, Router};
+use pbus_config_handler::{validation, Config, Database};
+use pbus_db_manager::StateStore;
+use pbus_timer::control::{self, Request};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tracing::warn;
+use utility::PbusError;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::auth::authenticate;
+use crate::dashboard;
+use crate::models::{self, *};
+
+pub mod audit;
+pub mod databases;
+pub mod events;
+pub mod freshness;
+pub mod runs;
+pub mod storage;
+pub mod targets;
+pub mod tokens;
+pub mod users;
+pub mod worker;
+
+/// What every handler works on
+///
+/// Nothing is cached: the config, catalog and state store are read on every
+/// request, so the API sees the same data as the worker and the CLI.
+pub struct AppState {
+    pub base_mount_point: String,
+}
+
+/// The OpenAPI description of every route, served at `/api/openapi.json`
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "pbus",
+        description = "Manage and run incremental PostgreSQL backups.\n\n\
+            Viewers see everything but passwords. Operators also start, pause and resume \
+            backups and change targets and schedules. Admins also restore, stop the \
+            worker, change how databases are reached and manage users."
+    ),
+    paths(
+        databases::list_databases,
+        databases::create_database,
+        databases::get_database,
+        databases::update_database,
+        databases::delete_database,
+        targets::list_targets,
+        targets::create_target,
+        targets::get_target,
+        targets::update_target,
+        targets::delete_target,
+        runs::start_backup,
+        runs::list_runs,
+        runs::list_segments,
+        runs::start_restore,
+        runs::start_drill,
+        storage::get_storage,
+        events::stream_events,
+        freshness::list_freshness,
+        users::get_session,
+        users::list_users,
+        users::create_user,
+        users::update_user,
+        users::delete_user,
+        tokens::list_tokens,
+        tokens::create_token,
+        tokens::delete_token,
+        audit::list_audit,
+        worker::get_worker,
+        worker::reload_worker,
+        worker::shutdown_worker,
+        worker::pause_database,
+        worker::resume_database,
+        worker::pause_target,
+        worker::resume_target,
+    ),
+    components(schemas(
+        DatabaseView,
+        TargetView,
+        CircuitView,
+        NewDatabase,
+        DatabaseUpdate,
+        NewTarget,
+        TargetUpdate,
+        RunView,
+        SegmentView,
+        FreshnessView,
+        BackupResult,
+        RestoreRequest,
+        RestoreResult,
+        CursorGap,
+        DrillResult,
+        StorageView,
+        TargetStorageView,
+        SessionView,
+        UserView,
+        NewUser,
+        UserUpdate,
+        TokenView,
+        NewToken,
+        CreatedToken,
+        AuditView,
+        WorkerView,
+        ScheduleView,
+        ErrorBody,
+    )),
+    modifiers(&SecuritySchemes),
+    security(("token" = []), ("password" = [])),
+    tags(
+        (name = "databases", description = "Databases in the config"),
+        (name = "targets", description = "Tables backed up from a database"),
+        (name = "backups", description = "Backup runs, their progress, segments and restores"),
+        (name = "access", description = "Users, API tokens and the audit log"),
+        (name = "worker", description = "The scheduler running next to the server, reached through its control socket"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Callers authenticate with an API token or their name and password
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "password",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
+
+/// Every route but the OpenAPI description and the dashboard needs an
+/// authenticated caller
+pub fn router(state: AppState) -> Router {
+    let state = Arc::new(state);
+    let target = "/api/databases/{database}/targets/{target}";
+    Router::new()
+        .route(
+            "/api/databases",
+            get(databases::list_databases).post(databases::create_database),
+        )
+        .route(
+            "/api/databases/{database}",
+            get(databases::get_database)
+                .patch(databases::update_database)
+                .delete(databases::delete_database),
+        )
+        .route(
+            "/api/databases/{database}/targets",
+            get(targets::list_targets).post(targets::create_target),
+        )
+        .route(
+            target,
+            get(targets::get_target)
+                .patch(targets::update_target)
+                .delete(targets::delete_target),
+        )
+        .route(&format!("{}/backups", target), post(runs::start_backup))
+        .route(&format!("{}/runs", target), get(runs::list_runs))
+        .route(&format!("{}/segments", target), get(runs::list_segments))
+        .route(&format!("{}/restores", target), post(runs::start_restore))
+        .route(&format!("{}/drills", target), post(runs::start_drill))
+        .route("/api/events", get(events::stream_events))
+        .route("/api/freshness", get(freshness::list_freshness))
+        .route("/api/storage", get(storage::get_storage))
+        .route("/api/session", get(users::get_session))
+        .route(
+            "/api/users",
+            get(users::list_users).post(users::create_user),
+        )
+        .route(
+            "/api/users/{user}",
+            patch(users::update_user).delete(users::delete_user),
+        )
+        .route(
+            "/api/tokens",
+            get(tokens::list_tokens).post(tokens::create_token),
+        )
+        .route("/api/tokens/{id}", delete(tokens::delete_token))
+        .route("/api/audit", get(audit::list_audit))
+        .route("/api/worker", get(worker::get_worker))
+        .route("/api/worker/reload", post(worker::reload_worker))
+        .route("/api/worker/shutdown", post(worker::shutdown_worker))
+        .route(
+            "/api/databases/{database}/pause",
+            post(worker::pause_database),
+        )
+        .route(
+            "/api/databases/{database}/resume",
+            post(worker::resume_database),
+        )
+        .route(&format!("{}/pause", target), post(worker::pause_target))
+        .route(&format!("{}/resume", target), post(worker::resume_target))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .route(
+            "/api/openapi.json",
+            get(|| async { Json(ApiDoc::openapi()) }),
+        )
+        .merge(dashboard::routes())
+        .with_state(state)
+}
+
+/// An error answered with its status and an `ErrorBody`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl fmt::Display) -> ApiError {
+        ApiError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl fmt::Display) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl fmt::Display) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl fmt::Display) -> ApiError {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+/// Config errors are the client's fault, as the only config a request can break
+/// is the one it sends; trouble with a source database is a bad gateway
+impl From<PbusError> for ApiError {
+    fn from(e: PbusError) -> ApiError {
+        let status = match &e {
+            PbusError::Config(_) => StatusCode::BAD_REQUEST,
+            PbusError::Schema(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            PbusError::Connection { .. } | PbusError::Query { .. } => StatusCode::BAD_GATEWAY,
+            PbusError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PbusError::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        ApiError::new(status, e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(ErrorBody {
+                error: self.message,
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Runs `work` on a blocking thread of the runtime
+///
+/// The catalog and state store hold SQLite connections, which can't be shared
+/// between threads, so work that keeps them across an await isn't `Send` and
+/// can't run in a handler directly. Their calls block as well.
+pub async fn run_blocking<F, Fut, T>(work: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, ApiError>>,
+    T: Send + 'static,
+{
+    let handle = Handle::current();
+    tokio::task::spawn_blocking(move || handle.block_on(work()))
+        .await
+        .map_err(ApiError::internal)?
+}
+
+/// Asks the worker to apply the config that was just written, so the change is
+/// in effect once the request is answered
+///
+/// Without a running worker there is nobody to tell. The config was validated
+/// before it was written, so a worker rejecting it is only logged.
+pub async fn reload_worker(base_mount_point: &str) {
+    if let Err(e) = control::send(base_mount_point, &Request::Reload).await {
+        warn!("The worker didn't apply the config change: {}", e);
+    }
+}
+
+/// The config the worker runs with
+///
+/// A config that can't be read is the server's problem, not the request's.
+pub fn read_config(base_mount_point: &str) -> Result<Config, ApiError> {
+    Config::read_config(base_mount_point).map_err(ApiError::internal)
+}
+
+/// Edits the config under the config lock, refusing edits that would leave it
+/// invalid
+pub fn edit_config<F>(base_mount_point: &str, edit: F) -> Result<Config, ApiError>
+where
+    F: FnOnce(&mut Config) -> Result<(), PbusError>,
+{
+    let config = Config::edit_config(base_mount_point, |config| {
+        edit(config)?;
+        let document = serde_json::to_string(config).map_err(PbusError::config)?;
+        validation::validate_config_str(&document)?;
+        Ok(())
+    })?;
+    Ok(config)
+}
+
+pub fn find_database<'a>(
+    config: &'a Config,
+    database_name: &str,
+) -> Result<&'a Database, ApiError> {
+    config
+        .get_databases()
+        .iter()
+        .find(|database| database.database_name == database_name)
+        .ok_or_else(|| ApiError::not_found(format!("database {} is not configured", database_name)))
+}
+
+pub fn database_view(state: &StateStore, database: &Database) -> Result<DatabaseView, ApiError> {
+    let targets = database
+        .get_targets()
+        .iter()
+        .map(|target| target_view(state, database, target.get_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let circuit = state.get_circuit(&database.database_name)?;
+    Ok(models::database_view(
+        database,
+        targets,
+        circuit_view(circuit),
+    ))
+}
+
+pub fn target_view(
+    state: &StateStore,
+    database: &Database,
+    target_name: &str,
+) -> Result<TargetView, ApiError> {
+    let target = database
+        .get_targets()
+        .iter()
+        .find(|target| target.get_name() == target_name)
+        .ok_or_else(|| {
+            ApiError::not_found(format!(
+                "target {}.{} is not configured",
+                database.database_name, target_name
+            ))
+        })?;
+    let target_state = state.get_target_state(&database.database_name, target_name)?;
+    Ok(models::target_view(target, &target_state))
+}
@@ -0,0 +1,324 @@
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::{stream, StreamExt};
+use pbus_db_manager::{Catalog, Role, RunStatus, StateStore};
+use pbus_timer::backup_target;
+use pbus_timer::control::{self, Request, Response as ControlResponse};
+use pbus_timer::drill::run_drill;
+use pbus_timer::restore::{replay_segments, restore_segments, segment_rows, Replay};
+use serde::Deserialize;
+use std::sync::Arc;
+use utility::PbusError;
+use utoipa::IntoParams;
+
+use crate::api::{find_database, read_config, run_blocking, target_view};
+use crate::api::{ApiError, AppState};
+use crate::auth::Caller;
+use crate::models::*;
+
+#[derive(Deserialize, IntoParams, Debug)]
+pub struct RunsQuery {
+    /// Most runs to return, newest first
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+}
+
+fn default_limit() -> u32 {
+    20
+}
+
+/// Backs the target up right now, whether it is enabled or not
+///
+/// The running worker is asked to do it, so runs of a target never overlap.
+/// Without one the server runs the backup itself. Answers once the run
+/// finished, a run in the worker is followed in the catalog. The run is
+/// recorded like any other, so it shows up in the run history either way.
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets/{target}/backups",
+    tag = "backups",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 200, description = "The run succeeded", body = BackupResult),
+        (status = 404, body = ErrorBody),
+        (status = 422, description = "The table doesn't match the target", body = ErrorBody),
+        (status = 502, description = "The database can't be reached, the query failed or the worker's run failed", body = ErrorBody),
+    )
+)]
+pub async fn start_backup(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database_name, target_name)): Path<(String, String)>,
+) -> Result<Json<BackupResult>, ApiError> {
+    caller.require(Role::Operator)?;
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let database = find_database(&config, &database_name)?;
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        target_view(&state, database, &target_name)?;
+
+        let request = Request::RunNow {
+            database: database_name.clone(),
+            target: target_name.clone(),
+        };
+        match control::send(&app.base_mount_point, &request).await? {
+            Some(ControlResponse::Started { run_id }) => {
+                let run = control::wait_for_run(&catalog, run_id).await?;
+                return match run.status {
+                    RunStatus::Succeeded => Ok(Json(backup_result(run.stats()))),
+                    _ => Err(ApiError::new(
+                        StatusCode::BAD_GATEWAY,
+                        run.error.unwrap_or_else(|| "the run failed".to_string()),
+                    )),
+                };
+            }
+            Some(response) => {
+                return Err(ApiError::internal(format!(
+                    "the worker answered a run with {:?}",
+                    response
+                )))
+            }
+            None => {}
+        }
+        let stats = backup_target(
+            &app.base_mount_point,
+            &catalog,
+            &state,
+            database,
+            &target_name,
+        )
+        .await?;
+        Ok(Json(backup_result(stats)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/databases/{database}/targets/{target}/runs",
+    tag = "backups",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+        RunsQuery,
+    ),
+    responses((status = 200, description = "Runs of the target, newest first", body = [RunView]))
+)]
+pub async fn list_runs(
+    State(app): State<Arc<AppState>>,
+    Path((database_name, target_name)): Path<(String, String)>,
+    Query(query): Query<RunsQuery>,
+) -> Result<Json<Vec<RunView>>, ApiError> {
+    run_blocking(move || async move {
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        let runs = catalog.get_runs(&database_name, &target_name, query.limit)?;
+        Ok(Json(runs.into_iter().map(run_view).collect()))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/databases/{database}/targets/{target}/segments",
+    tag = "backups",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses((status = 200, description = "Active segments in cursor order", body = [SegmentView]))
+)]
+pub async fn list_segments(
+    State(app): State<Arc<AppState>>,
+    Path((database_name, target_name)): Path<(String, String)>,
+) -> Result<Json<Vec<SegmentView>>, ApiError> {
+    run_blocking(move || async move {
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        let segments = catalog.get_segments(&database_name, &target_name)?;
+        Ok(Json(segments.into_iter().map(segment_view).collect()))
+    })
+    .await
+}
+
+/// Replays the backed up rows of a target
+///
+/// With `into` set the rows are inserted into that configured database, one
+/// transaction per segment, rows already there are skipped. Otherwise they are
+/// streamed back as JSON lines. Every segment is checked against its checksum
+/// first, a corrupt one stops the restore before anything is written.
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets/{target}/restores",
+    tag = "backups",
+    params(
+        ("database" = String, Path, description = "Name of the database the rows were backed up from"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    request_body = RestoreRequest,
+    responses(
+        (status = 200, description = "The rows were inserted", body = RestoreResult),
+        (status = 200, description = "The rows, one JSON document per line", content_type = "application/x-ndjson"),
+        (status = 404, description = "There are no segments to restore", body = ErrorBody),
+        (status = 409, description = "A segment is corrupt", body = ErrorBody),
+        (status = 502, description = "The database to insert into can't be reached", body = ErrorBody),
+    )
+)]
+pub async fn start_restore(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database_name, target_name)): Path<(String, String)>,
+    Json(request): Json<RestoreRequest>,
+) -> Result<Response, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        if catalog
+            .get_segments(&database_name, &target_name)?
+            .is_empty()
+        {
+            return Err(ApiError::not_found(format!(
+                "no segments for {}.{}",
+                database_name, target_name
+            )));
+        }
+        let (segments, gaps) = match replay_segments(
+            &app.base_mount_point,
+            &catalog,
+            &database_name,
+            &target_name,
+            request.to_cursor,
+        )? {
+            Replay::Segments { segments, gaps } => (segments, gaps),
+            Replay::Corrupt(segment) => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    format!("segment {} is corrupt, run verify", segment.path),
+                ))
+            }
+        };
+
+        match request.into {
+            Some(into) => {
+                let config = read_config(&app.base_mount_point)?;
+                let restored = restore_segments(
+                    &app.base_mount_point,
+                    &config,
+                    &into,
+                    &database_name,
+                    &target_name,
+                    &segments,
+                    request.to_cursor,
+                )
+                .await?;
+                Ok(Json(RestoreResult {
+                    database: into,
+                    rows: restored.rows,
+                    inserted: restored.inserted,
+                    gaps: gaps
+                        .into_iter()
+                        .map(|(from, to)| CursorGap { from, to })
+                        .collect(),
+                })
+                .into_response())
+            }
+            None => {
+                // One chunk per segment, so only one segment is in memory at a time
+                let base_mount_point = app.base_mount_point.clone();
+                let to_cursor = request.to_cursor;
+                let chunks = stream::iter(segments).map(move |segment| {
+                    let mut chunk = String::new();
+                    for row in segment_rows(&base_mount_point, &segment, to_cursor)? {
+                        chunk.push_str(&row.to_string());
+                        chunk.push('\n');
+                    }
+                    Ok::<_, PbusError>(chunk)
+                });
+                Ok((
+                    [(CONTENT_TYPE, "application/x-ndjson")],
+                    Body::from_stream(chunks),
+                )
+                    .into_response())
+            }
+        }
+    })
+    .await
+}
+
+/// Restores the target into a throwaway schema on the scratch server and
+/// compares a sample of the restored rows against the source
+///
+/// Every active segment is verified against its checksum and restored. A
+/// corrupt segment, a missing cursor range, a row that didn't restore or a
+/// sampled row that differs from the source fails the drill. The schema is
+/// dropped afterwards and the drill is recorded in the run history.
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets/{target}/drills",
+    tag = "backups",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 200, description = "The drill ran, whether it passed or not", body = DrillResult),
+        (status = 400, description = "No scratch server is configured", body = ErrorBody),
+        (status = 404, description = "There are no segments to replay", body = ErrorBody),
+        (status = 502, description = "The source or the scratch server can't be reached", body = ErrorBody),
+    )
+)]
+pub async fn start_drill(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database_name, target_name)): Path<(String, String)>,
+) -> Result<Json<DrillResult>, ApiError> {
+    caller.require(Role::Operator)?;
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        if catalog
+            .get_segments(&database_name, &target_name)?
+            .is_empty()
+        {
+            return Err(ApiError::not_found(format!(
+                "no segments for {}.{}",
+                database_name, target_name
+            )));
+        }
+
+        let report = run_drill(
+            &app.base_mount_point,
+            &catalog,
+            &config,
+            &database_name,
+            &target_name,
+        )
+        .await?;
+        Ok(Json(DrillResult {
+            database: database_name,
+            target: target_name,
+            run_id: report.run_id,
+            passed: report.passed(),
+            segments: report.segments,
+            rows: report.rows,
+            restored: report.restored,
+            cursor: report.cursor,
+            gaps: report
+                .gaps
+                .into_iter()
+                .map(|(from, to)| CursorGap { from, to })
+                .collect(),
+            corrupt_segment: report.corrupt_segment,
+            sampled: report.sampled,
+            mismatched: report.mismatched,
+            duration_ms: report.duration.as_millis() as u64,
+        }))
+    })
+    .await
+}
@@ -0,0 +1,86 @@
+use axum::extract::State;
+use axum::Json;
+use pbus_db_manager::Catalog;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use crate::api::{read_config, run_blocking, ApiError, AppState};
+use crate::models::*;
+
+/// What the backups of every configured target take up
+///
+/// The space of the file system is left unset if it can't be read. Segments
+/// past a database's retention are counted, never deleted: they hold the only
+/// copy of their rows.
+#[utoipa::path(
+    get,
+    path = "/api/storage",
+    tag = "backups",
+    responses((status = 200, body = StorageView))
+)]
+pub async fn get_storage(State(app): State<Arc<AppState>>) -> Result<Json<StorageView>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let catalog = Catalog::open(&app.base_mount_point)?;
+        let now = SystemTime::now();
+
+        let mut targets = Vec::new();
+        for database in config.get_databases() {
+            let corrupt = catalog.get_corrupt_segments(&database.database_name)?;
+            let past_retention = match database
+                .retention
+                .and_then(|retention| now.checked_sub(Duration::from_secs(retention)))
+            {
+                Some(before) => catalog.get_segments_older_than(before)?,
+                None => Vec::new(),
+            };
+            for target in database.get_targets() {
+                let segments = catalog.get_segments(&database.database_name, target.get_name())?;
+                targets.push(TargetStorageView {
+                    database: database.database_name.clone(),
+                    target: target.get_name().clone(),
+                    segments: segments.len(),
+                    rows: segments.iter().map(|segment| segment.row_count).sum(),
+                    bytes: segments.iter().map(|segment| segment.size_bytes).sum(),
+                    oldest_segment: segments
+                        .iter()
+                        .map(|segment| segment.created_at)
+                        .min()
+                        .map(format_time),
+                    newest_segment: segments
+                        .iter()
+                        .map(|segment| segment.created_at)
+                        .max()
+                        .map(format_time),
+                    corrupt_segments: corrupt
+                        .iter()
+                        .filter(|segment| segment.target_name == *target.get_name())
+                        .count(),
+                    retention: database.retention,
+                    past_retention: past_retention
+                        .iter()
+                        .filter(|segment| {
+                            segment.database_name == database.database_name
+                                && segment.target_name == *target.get_name()
+                        })
+                        .count(),
+                });
+            }
+        }
+
+        let space = |read: fn(&str) -> std::io::Result<u64>| match read(&app.base_mount_point) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!("Can't read disk usage of {}: {}", app.base_mount_point, e);
+                None
+            }
+        };
+        Ok(Json(StorageView {
+            total_bytes: space(|path| fs2::total_space(path)),
+            available_bytes: space(|path| fs2::available_space(path)),
+            targets,
+        }))
+    })
+    .await
+}
@@ -0,0 +1,247 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use pbus_db_manager::{Role, StateStore};
+use pbus_timer::onboarding;
+use std::sync::Arc;
+use utility::PbusError;
+
+use crate::api::{
+    edit_config, find_database, read_config, reload_worker, run_blocking, target_view,
+};
+use crate::api::{ApiError, AppState};
+use crate::auth::Caller;
+use crate::models::*;
+
+#[utoipa::path(
+    get,
+    path = "/api/databases/{database}/targets",
+    tag = "targets",
+    params(("database" = String, Path, description = "Name of the database")),
+    responses(
+        (status = 200, body = [TargetView]),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn list_targets(
+    State(app): State<Arc<AppState>>,
+    Path(database_name): Path<String>,
+) -> Result<Json<Vec<TargetView>>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let database = find_database(&config, &database_name)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        let views = database
+            .get_targets()
+            .iter()
+            .map(|target| target_view(&state, database, target.get_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Json(views))
+    })
+    .await
+}
+
+/// Looks the table up in the database and adds it as a target
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets",
+    tag = "targets",
+    params(("database" = String, Path, description = "Name of the database")),
+    request_body = NewTarget,
+    responses(
+        (status = 201, description = "The target was added", body = TargetView),
+        (status = 400, description = "Invalid or already configured", body = ErrorBody),
+        (status = 404, description = "No such database or table", body = ErrorBody),
+        (status = 422, description = "The table can't be backed up", body = ErrorBody),
+        (status = 502, description = "The database can't be reached", body = ErrorBody),
+    )
+)]
+pub async fn create_target(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(database_name): Path<String>,
+    Json(request): Json<NewTarget>,
+) -> Result<(StatusCode, Json<TargetView>), ApiError> {
+    caller.require(Role::Operator)?;
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let database = find_database(&config, &database_name)?;
+
+        let candidates = onboarding::discover_tables(&app.base_mount_point, database).await?;
+        let candidate = candidates
+            .into_iter()
+            .find(|candidate| candidate.name == request.name)
+            .ok_or_else(|| {
+                ApiError::not_found(format!(
+                    "table {} was not found in {}",
+                    request.name, database_name
+                ))
+            })?;
+        let mut config = onboarding::add_target(&app.base_mount_point, &database_name, &candidate)?;
+        if request.rpo.is_some() {
+            config = edit_config(&app.base_mount_point, |config| {
+                set_rpo(config, &database_name, &request.name, request.rpo)
+            })?;
+        }
+        reload_worker(&app.base_mount_point).await;
+
+        let state = StateStore::open(&app.base_mount_point)?;
+        let view = target_view(
+            &state,
+            find_database(&config, &database_name)?,
+            &request.name,
+        )?;
+        Ok((StatusCode::CREATED, Json(view)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/databases/{database}/targets/{target}",
+    tag = "targets",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 200, body = TargetView),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_target(
+    State(app): State<Arc<AppState>>,
+    Path((database_name, target_name)): Path<(String, String)>,
+) -> Result<Json<TargetView>, ApiError> {
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        Ok(Json(target_view(
+            &state,
+            find_database(&config, &database_name)?,
+            &target_name,
+        )?))
+    })
+    .await
+}
+
+/// Enables or disables the target, or changes its RPO
+#[utoipa::path(
+    patch,
+    path = "/api/databases/{database}/targets/{target}",
+    tag = "targets",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    request_body = TargetUpdate,
+    responses(
+        (status = 200, body = TargetView),
+        (status = 400, description = "The change would leave the config invalid", body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn update_target(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database_name, target_name)): Path<(String, String)>,
+    Json(update): Json<TargetUpdate>,
+) -> Result<Json<TargetView>, ApiError> {
+    caller.require(Role::Operator)?;
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        target_view(
+            &state,
+            find_database(&config, &database_name)?,
+            &target_name,
+        )?;
+
+        let config = edit_config(&app.base_mount_point, |config| {
+            if let Some(enabled) = update.enabled {
+                config
+                    .get_database(&database_name)
+                    .ok_or_else(|| not_configured(&database_name, &target_name))?
+                    .set_target_enabled(target_name.clone(), enabled);
+            }
+            if update.rpo.is_some() {
+                set_rpo(config, &database_name, &target_name, update.rpo)?;
+            }
+            Ok(())
+        })?;
+        reload_worker(&app.base_mount_point).await;
+
+        Ok(Json(target_view(
+            &state,
+            find_database(&config, &database_name)?,
+            &target_name,
+        )?))
+    })
+    .await
+}
+
+/// Takes the target out of the config
+///
+/// Its backups stay in the catalog and can still be restored.
+#[utoipa::path(
+    delete,
+    path = "/api/databases/{database}/targets/{target}",
+    tag = "targets",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 204, description = "The target was removed"),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn delete_target(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database_name, target_name)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    run_blocking(move || async move {
+        let config = read_config(&app.base_mount_point)?;
+        let state = StateStore::open(&app.base_mount_point)?;
+        target_view(
+            &state,
+            find_database(&config, &database_name)?,
+            &target_name,
+        )?;
+
+        edit_config(&app.base_mount_point, |config| {
+            config
+                .get_database(&database_name)
+                .and_then(|database| database.remove_target(&target_name))
+                .ok_or_else(|| not_configured(&database_name, &target_name))?;
+            Ok(())
+        })?;
+        reload_worker(&app.base_mount_point).await;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+/// Sets the target's own RPO, 0 removes it
+fn set_rpo(
+    config: &mut pbus_config_handler::Config,
+    database_name: &str,
+    target_name: &str,
+    rpo: Option<u64>,
+) -> Result<(), PbusError> {
+    config
+        .get_database(&database_name.to_string())
+        .and_then(|database| database.get_target(target_name.to_string()))
+        .ok_or_else(|| not_configured(database_name, target_name))?
+        .set_rpo(rpo.filter(|rpo| *rpo > 0));
+    Ok(())
+}
+
+fn not_configured(database_name: &str, target_name: &str) -> PbusError {
+    PbusError::config(format!(
+        "target {}.{} is not configured",
+        database_name, target_name
+    ))
+}
@@ -0,0 +1,91 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use pbus_db_manager::AuthStore;
+use std::sync::Arc;
+
+use crate::api::{run_blocking, ApiError, AppState};
+use crate::auth::{hash_token, new_token, Caller};
+use crate::models::*;
+
+/// API tokens of the calling user
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    tag = "access",
+    responses((status = 200, body = [TokenView]))
+)]
+pub async fn list_tokens(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<TokenView>>, ApiError> {
+    run_blocking(move || async move {
+        let auth = AuthStore::open(&app.base_mount_point)?;
+        Ok(Json(
+            auth.get_tokens(&caller.name)?
+                .into_iter()
+                .map(token_view)
+                .collect(),
+        ))
+    })
+    .await
+}
+
+/// Creates a token that authenticates as the calling user
+///
+/// The token is only part of this answer, it can't be shown again.
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    tag = "access",
+    request_body = NewToken,
+    responses((status = 201, description = "The token was created", body = CreatedToken))
+)]
+pub async fn create_token(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Json(request): Json<NewToken>,
+) -> Result<(StatusCode, Json<CreatedToken>), ApiError> {
+    run_blocking(move || async move {
+        let token = new_token();
+        let created = AuthStore::open(&app.base_mount_point)?.add_token(
+            &caller.name,
+            &request.label,
+            &hash_token(&token),
+        )?;
+        Ok((
+            StatusCode::CREATED,
+            Json(CreatedToken {
+                id: created.id,
+                label: created.label,
+                token,
+            }),
+        ))
+    })
+    .await
+}
+
+/// Revokes a token of the calling user
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    tag = "access",
+    params(("id" = i64, Path, description = "Id of the token")),
+    responses(
+        (status = 204, description = "The token was revoked"),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn delete_token(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    run_blocking(move || async move {
+        if !AuthStore::open(&app.base_mount_point)?.remove_token(&caller.name, id)? {
+            return Err(ApiError::not_found(format!("you have no token {}", id)));
+        }
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
@@ -0,0 +1,158 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use pbus_db_manager::{AuthStore, Role, User};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::api::{run_blocking, ApiError, AppState};
+use crate::auth::{hash_password, Caller};
+use crate::models::*;
+
+/// The user the request authenticated as
+#[utoipa::path(
+    get,
+    path = "/api/session",
+    tag = "access",
+    responses((status = 200, body = SessionView))
+)]
+pub async fn get_session(caller: Caller) -> Json<SessionView> {
+    Json(SessionView {
+        user: caller.name,
+        role: caller.role,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "access",
+    responses((status = 200, body = [UserView]))
+)]
+pub async fn list_users(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<UserView>>, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let auth = AuthStore::open(&app.base_mount_point)?;
+        Ok(Json(auth.get_users()?.into_iter().map(user_view).collect()))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "access",
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "The user was added", body = UserView),
+        (status = 400, description = "The name is taken or the password too short", body = ErrorBody),
+    )
+)]
+pub async fn create_user(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Json(request): Json<NewUser>,
+) -> Result<(StatusCode, Json<UserView>), ApiError> {
+    caller.require(Role::Admin)?;
+    if request.name.is_empty() || request.name.contains(':') {
+        return Err(ApiError::bad_request(
+            "user names can't be empty or contain ':'",
+        ));
+    }
+    run_blocking(move || async move {
+        let user = User {
+            name: request.name,
+            password_hash: hash_password(&request.password)?,
+            role: request.role,
+            created_at: SystemTime::now(),
+        };
+        AuthStore::open(&app.base_mount_point)?.add_user(&user)?;
+        Ok((StatusCode::CREATED, Json(user_view(user))))
+    })
+    .await
+}
+
+/// Changes the password or role of a user
+#[utoipa::path(
+    patch,
+    path = "/api/users/{user}",
+    tag = "access",
+    params(("user" = String, Path, description = "Name of the user")),
+    request_body = UserUpdate,
+    responses(
+        (status = 200, body = UserView),
+        (status = 400, description = "The password is too short", body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "It would leave no admin", body = ErrorBody),
+    )
+)]
+pub async fn update_user(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(user_name): Path<String>,
+    Json(update): Json<UserUpdate>,
+) -> Result<Json<UserView>, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let auth = AuthStore::open(&app.base_mount_point)?;
+        let mut user = find_user(&auth, &user_name)?;
+        if let Some(role) = update.role {
+            keep_an_admin(&auth, &user, Some(role))?;
+            user.role = role;
+        }
+        if let Some(password) = update.password {
+            user.password_hash = hash_password(&password)?;
+        }
+        auth.update_user(&user)?;
+        Ok(Json(user_view(user)))
+    })
+    .await
+}
+
+/// Removes a user and revokes their tokens
+#[utoipa::path(
+    delete,
+    path = "/api/users/{user}",
+    tag = "access",
+    params(("user" = String, Path, description = "Name of the user")),
+    responses(
+        (status = 204, description = "The user was removed"),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "It would leave no admin", body = ErrorBody),
+    )
+)]
+pub async fn delete_user(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(user_name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Admin)?;
+    run_blocking(move || async move {
+        let mut auth = AuthStore::open(&app.base_mount_point)?;
+        let user = find_user(&auth, &user_name)?;
+        keep_an_admin(&auth, &user, None)?;
+        auth.remove_user(&user_name)?;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+fn find_user(auth: &AuthStore, user_name: &str) -> Result<User, ApiError> {
+    auth.get_user(user_name)?
+        .ok_or_else(|| ApiError::not_found(format!("there is no user {}", user_name)))
+}
+
+/// Answers a change that would leave no admin with a conflict, rather than the
+/// error `AuthStore` refuses it with
+fn keep_an_admin(auth: &AuthStore, user: &User, role: Option<Role>) -> Result<(), ApiError> {
+    if role != Some(Role::Admin) && auth.is_last_admin(&user.name)? {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("{} is the last admin", user.name),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use pbus_db_manager::Role;
+use pbus_timer::control::{self, socket_path, Request, Response};
+use std::sync::Arc;
+
+use crate::api::{ApiError, AppState};
+use crate::auth::Caller;
+use crate::models::*;
+
+/// Sends `request` to the worker, which has to be running
+async fn ask(app: &AppState, request: Request) -> Result<Response, ApiError> {
+    control::send(&app.base_mount_point, &request)
+        .await?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "no worker is running on {}",
+                    socket_path(&app.base_mount_point).display()
+                ),
+            )
+        })
+}
+
+/// What the worker is doing and when each target runs next
+#[utoipa::path(
+    get,
+    path = "/api/worker",
+    tag = "worker",
+    responses(
+        (status = 200, body = WorkerView),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn get_worker(State(app): State<Arc<AppState>>) -> Result<Json<WorkerView>, ApiError> {
+    match ask(&app, Request::Status).await? {
+        Response::Status(status) => Ok(Json(worker_view(status))),
+        response => Err(ApiError::internal(format!(
+            "the worker answered a status request with {:?}",
+            response
+        ))),
+    }
+}
+
+/// Has the worker re-read the config file now
+///
+/// Changes made through the API are applied right away, this is for edits of
+/// the file itself.
+#[utoipa::path(
+    post,
+    path = "/api/worker/reload",
+    tag = "worker",
+    responses(
+        (status = 204, description = "The worker runs with the config file as it is"),
+        (status = 400, description = "The config file is invalid, the worker keeps its current config", body = ErrorBody),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn reload_worker(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    ask(&app, Request::Reload).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops the worker
+#[utoipa::path(
+    post,
+    path = "/api/worker/shutdown",
+    tag = "worker",
+    responses(
+        (status = 204, description = "The worker stopped"),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn shutdown_worker(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Admin)?;
+    ask(&app, Request::Shutdown).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops scheduling every target of the database until it is resumed
+///
+/// Pauses last until the worker restarts. Backups started by hand still run.
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/pause",
+    tag = "worker",
+    params(("database" = String, Path, description = "Name of the database")),
+    responses(
+        (status = 204, description = "The database is paused"),
+        (status = 400, description = "The database is not configured", body = ErrorBody),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn pause_database(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(database): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    ask(
+        &app,
+        Request::Pause {
+            database,
+            target: None,
+        },
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Schedules the targets of the database again, including ones paused one by one
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/resume",
+    tag = "worker",
+    params(("database" = String, Path, description = "Name of the database")),
+    responses(
+        (status = 204, description = "The database is scheduled again"),
+        (status = 400, description = "Nothing of the database is paused", body = ErrorBody),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn resume_database(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path(database): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    ask(
+        &app,
+        Request::Resume {
+            database,
+            target: None,
+        },
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops scheduling the target until it is resumed
+///
+/// Pauses last until the worker restarts. Backups started by hand still run.
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets/{target}/pause",
+    tag = "worker",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 204, description = "The target is paused"),
+        (status = 400, description = "The target is not configured", body = ErrorBody),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn pause_target(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database, target)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    ask(
+        &app,
+        Request::Pause {
+            database,
+            target: Some(target),
+        },
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Schedules the paused target again, it runs one interval later
+#[utoipa::path(
+    post,
+    path = "/api/databases/{database}/targets/{target}/resume",
+    tag = "worker",
+    params(
+        ("database" = String, Path, description = "Name of the database"),
+        ("target" = String, Path, description = "Name of the target"),
+    ),
+    responses(
+        (status = 204, description = "The target is scheduled again"),
+        (status = 400, description = "The target is not paused by itself", body = ErrorBody),
+        (status = 503, description = "No worker is running", body = ErrorBody),
+    )
+)]
+pub async fn resume_target(
+    State(app): State<Arc<AppState>>,
+    caller: Caller,
+    Path((database, target)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    caller.require(Role::Operator)?;
+    ask(
+        &app,
+        Request::Resume {
+            database,
+            target: Some(target),
+        },
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,207 @@
+//! Who is calling the API and what they may do
+//!
+//! Every request under `/api` except the OpenAPI description must carry either
+//! an API token (`Authorization: Bearer pbus_...`) or the name and password of a
+//! user (`Authorization: Basic ...`). Handlers then check the caller's role.
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pbus_db_manager::{AuthStore, Role, User};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::{error, info};
+use utility::PbusError;
+
+use crate::api::{run_blocking, ApiError, AppState};
+
+/// Every API token starts with this, so leaked ones are easy to search for
+pub const TOKEN_PREFIX: &str = "pbus_";
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// The authenticated user of a request
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Caller {
+    /// Refuses the request unless the caller has at least `role`
+    pub fn require(&self, role: Role) -> Result<(), ApiError> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("{} needs the {} role", self.name, role.as_str()),
+            ))
+        }
+    }
+}
+
+/// Set by `authenticate`, so a handler that takes a `Caller` only runs for
+/// authenticated requests
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Caller, ApiError> {
+        parts
+            .extensions
+            .get::<Caller>()
+            .cloned()
+            .ok_or_else(unauthorized)
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, PbusError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(PbusError::config(format!(
+            "passwords need at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(PbusError::storage)?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// A new random API token, shown once and stored only as its hash
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Tokens are random enough that a plain SHA-256 is as good as a password hash,
+/// and it can be looked up
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Finds the user a request authenticates as, records mutating requests in the
+/// audit log
+pub async fn authenticate(
+    State(app): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let caller = match authorization {
+        Some(authorization) => {
+            let base_mount_point = app.base_mount_point.clone();
+            run_blocking(move || async move { find_caller(&base_mount_point, &authorization) })
+                .await
+        }
+        None => Ok(None),
+    };
+    let (user_name, response) = match caller {
+        Ok(Some(caller)) => {
+            let user_name = caller.name.clone();
+            request.extensions_mut().insert(caller);
+            (Some(user_name), next.run(request).await)
+        }
+        Ok(None) => {
+            let mut response = unauthorized().into_response();
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"pbus\", Bearer"),
+            );
+            (None, response)
+        }
+        Err(e) => (None, e.into_response()),
+    };
+
+    if method != Method::GET && method != Method::HEAD {
+        audit(&app, user_name, method, path, response.status()).await;
+    }
+    response
+}
+
+/// The user behind an `Authorization` header, `None` if the credentials are
+/// wrong
+fn find_caller(base_mount_point: &str, authorization: &str) -> Result<Option<Caller>, ApiError> {
+    let auth = AuthStore::open(base_mount_point)?;
+    let user = if let Some(token) = authorization.strip_prefix("Bearer ") {
+        auth.use_token(&hash_token(token.trim()))?
+            .map(|(_, user)| user)
+    } else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+        let credentials = STANDARD
+            .decode(credentials.trim())
+            .ok()
+            .and_then(|credentials| String::from_utf8(credentials).ok());
+        match credentials.as_deref().and_then(|c| c.split_once(':')) {
+            Some((name, password)) => auth
+                .get_user(name)?
+                .filter(|user: &User| verify_password(password, &user.password_hash)),
+            None => None,
+        }
+    } else {
+        None
+    };
+    Ok(user.map(|user| Caller {
+        name: user.name,
+        role: user.role,
+    }))
+}
+
+async fn audit(
+    app: &Arc<AppState>,
+    user_name: Option<String>,
+    method: Method,
+    path: String,
+    status: StatusCode,
+) {
+    info!(
+        user = user_name.as_deref().unwrap_or("-"),
+        action = %method,
+        resource = %path,
+        status = status.as_u16(),
+        "Audit"
+    );
+    let base_mount_point = app.base_mount_point.clone();
+    let recorded = run_blocking(move || async move {
+        AuthStore::open(&base_mount_point)?.record_audit(
+            user_name.as_deref(),
+            method.as_str(),
+            &path,
+            status.as_u16(),
+        )?;
+        Ok(())
+    })
+    .await;
+    if let Err(e) = recorded {
+        error!("Can't write the audit log: {}", e.message);
+    }
+}
+
+fn unauthorized() -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "authentication required")
+}
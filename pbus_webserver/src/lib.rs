@@ -0,0 +1,13 @@
+//! HTTP API over the config, catalog and state store of a pbus data directory
+//!
+//! The server runs next to the worker and changes the config file the same way
+//! the CLI does, the worker picks changes up as they are written. Callers are
+//! users of the server with a role, see `auth`. A dashboard for browsers is
+//! served at `/`, see `dashboard`.
+
+pub mod api;
+pub mod auth;
+pub mod dashboard;
+pub mod models;
+
+pub use crate::api::{router, ApiDoc, AppState};
@@ -1,3 +1,253 @@
-fn main() {
-    println!("Hello, world!");
+use clap::{Parser, Subcommand};
+use pbus_config_handler::config_file::set_config_file;
+use pbus_config_handler::{check_config, init_logging, Config, LoggingConfig};
+use pbus_db_manager::{AuthStore, Role, User};
+use pbus_webserver::auth::{hash_password, hash_token, new_token};
+use pbus_webserver::{router, ApiDoc, AppState};
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+use utility::PbusError;
+use utoipa::OpenApi;
+
+/// The server ran into an error
+const EXIT_FAILURE: u8 = 1;
+/// The config file is missing, unreadable or invalid (`EX_CONFIG` from sysexits.h)
+const EXIT_CONFIG: u8 = 78;
+
+/// HTTP API and dashboard for managing and running pbus backups
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// Directory holding the config, catalog, state and segment files
+    #[arg(long, env = "PBUS_DATA_DIR", default_value = "../data/")]
+    data_dir: String,
+
+    /// Config file to use instead of the one in the data directory
+    #[arg(long, env = "PBUS_CONFIG_FILE")]
+    config: Option<String>,
+
+    /// Address to serve the API on
+    #[arg(long, env = "PBUS_LISTEN_ADDR", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Print the OpenAPI description of the API and exit
+    #[arg(long)]
+    openapi: bool,
+
+    /// Manage users and tokens instead of serving the API
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Add a user, the first one has to be added this way
+    AddUser {
+        name: String,
+        /// `viewer`, `operator` or `admin`
+        #[arg(long, value_parser = parse_role, default_value = "viewer")]
+        role: Role,
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Set a new password for a user
+    SetPassword {
+        name: String,
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Remove a user and revoke their tokens
+    RemoveUser { name: String },
+    /// List users and their roles
+    ListUsers,
+    /// Create an API token for a user and print it
+    CreateToken {
+        name: String,
+        /// What the token is for
+        #[arg(long, default_value = "cli")]
+        label: String,
+    },
+}
+
+fn parse_role(role: &str) -> Result<Role, String> {
+    Role::parse(role).ok_or_else(|| format!("{} is not viewer, operator or admin", role))
+}
+
+impl Cli {
+    /// The data directory as a base mount point, which always ends in a slash
+    fn base_mount_point(&self) -> String {
+        if self.data_dir.ends_with('/') {
+            self.data_dir.clone()
+        } else {
+            format!("{}/", self.data_dir)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.openapi {
+        match ApiDoc::openapi().to_pretty_json() {
+            Ok(spec) => println!("{}", spec),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+    let base_mount_point = cli.base_mount_point();
+    if let Some(command) = cli.command {
+        return match manage_users(&base_mount_point, command) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::from(EXIT_FAILURE)
+            }
+        };
+    }
+
+    if let Some(path) = &cli.config {
+        if let Err(e) = set_config_file(path) {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    }
+    let _log_guard = match init_logging(&base_mount_point, &LoggingConfig::load(&base_mount_point))
+    {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+    if let Err(e) =
+        check_config(&base_mount_point).and_then(|_| Config::read_config(&base_mount_point))
+    {
+        error!("{}", e);
+        return ExitCode::from(EXIT_CONFIG);
+    }
+
+    let listener = match TcpListener::bind(cli.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Can't serve the API on {}: {}", cli.listen, e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    info!(
+        "Serving the API on http://{}/api and the dashboard on http://{}/",
+        cli.listen, cli.listen
+    );
+    match AuthStore::open(&base_mount_point).and_then(|auth| auth.get_users()) {
+        Ok(users) if users.is_empty() => {
+            warn!("There are no users, every request will be refused until one is added with add-user")
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Can't read the users: {}", e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    }
+
+    let app = router(AppState { base_mount_point });
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutting down");
+    };
+    match axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Server stopped: {}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+fn manage_users(base_mount_point: &str, command: Command) -> Result<(), PbusError> {
+    let mut auth = AuthStore::open(base_mount_point)?;
+    match command {
+        Command::AddUser {
+            name,
+            role,
+            password_stdin,
+        } => {
+            if name.is_empty() || name.contains(':') {
+                return Err(PbusError::config(
+                    "user names can't be empty or contain ':'",
+                ));
+            }
+            auth.add_user(&User {
+                name: name.clone(),
+                password_hash: hash_password(&read_password(password_stdin)?)?,
+                role,
+                created_at: SystemTime::now(),
+            })?;
+            println!("Added {} as {}", name, role.as_str());
+        }
+        Command::SetPassword {
+            name,
+            password_stdin,
+        } => {
+            let mut user = find_user(&auth, &name)?;
+            user.password_hash = hash_password(&read_password(password_stdin)?)?;
+            auth.update_user(&user)?;
+            println!("Changed the password of {}", name);
+        }
+        Command::RemoveUser { name } => {
+            if !auth.remove_user(&name)? {
+                return Err(PbusError::config(format!("there is no user {}", name)));
+            }
+            println!("Removed {}", name);
+        }
+        Command::ListUsers => {
+            for user in auth.get_users()? {
+                println!(
+                    "{}\t{}\t{}",
+                    user.name,
+                    user.role.as_str(),
+                    humantime::format_rfc3339_seconds(user.created_at)
+                );
+            }
+        }
+        Command::CreateToken { name, label } => {
+            let user = find_user(&auth, &name)?;
+            let token = new_token();
+            auth.add_token(&user.name, &label, &hash_token(&token))?;
+            println!("{}", token);
+        }
+    }
+    Ok(())
+}
+
+fn find_user(auth: &AuthStore, name: &str) -> Result<User, PbusError> {
+    auth.get_user(name)?
+        .ok_or_else(|| PbusError::config(format!("there is no user {}", name)))
+}
+
+fn read_password(from_stdin: bool) -> Result<String, PbusError> {
+    if from_stdin {
+        let mut password = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .map_err(PbusError::config)?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+    let password = rpassword::prompt_password("Password: ").map_err(PbusError::config)?;
+    let repeated = rpassword::prompt_password("Repeat it: ").map_err(PbusError::config)?;
+    if password != repeated {
+        return Err(PbusError::config("the passwords don't match"));
+    }
+    Ok(password)
 }
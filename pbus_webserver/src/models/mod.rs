@@ -0,0 +1,173 @@
+//! Request and response bodies of the API, see `pbus_models::api`, and how
+//! they are made from what the server stores
+
+use pbus_config_handler::Database;
+use pbus_db_manager::{
+    ApiToken, AuditEntry, BackupRun, CircuitState, RunStats, Segment, TargetState, User,
+};
+use pbus_timer::control::{ScheduledTarget, WorkerStatus};
+use pbus_timer::freshness::TargetFreshness;
+use std::time::SystemTime;
+use utility::Target;
+
+pub use pbus_models::api::*;
+
+/// Shown instead of a password that is stored in the config as plaintext
+pub const REDACTED: &str = "<redacted>";
+
+pub fn database_view(
+    database: &Database,
+    targets: Vec<TargetView>,
+    circuit: CircuitView,
+) -> DatabaseView {
+    DatabaseView {
+        name: database.database_name.clone(),
+        host: database.database_host.clone(),
+        port: database.server_port,
+        user: database.database_user.clone(),
+        password: if database.database_password.is_plain() {
+            REDACTED.to_string()
+        } else {
+            database.database_password.to_reference()
+        },
+        update_interval: database.update_interval,
+        rpo: database.rpo,
+        retention: database.retention,
+        targets,
+        circuit,
+    }
+}
+
+pub fn target_view(target: &Target, state: &TargetState) -> TargetView {
+    TargetView {
+        name: target.get_name().clone(),
+        enabled: target.get_enabled(),
+        rpo: target.get_rpo(),
+        fields: target.get_fields().clone(),
+        last_id: state.last_id,
+        last_updated: format_epoch(state.last_updated),
+        last_checked: format_epoch(state.last_checked),
+    }
+}
+
+pub fn circuit_view(circuit: CircuitState) -> CircuitView {
+    CircuitView {
+        open: circuit.is_open(),
+        failures: circuit.failures,
+        last_error: circuit.last_error,
+        opened_at: circuit.opened_at.map(format_time),
+        next_probe: circuit.next_probe.map(format_time),
+    }
+}
+
+pub fn run_view(run: BackupRun) -> RunView {
+    RunView {
+        id: run.id,
+        database: run.database_name,
+        target: run.target_name,
+        kind: run.kind.as_str().to_string(),
+        status: run.status.as_str().to_string(),
+        started_at: format_time(run.started_at),
+        finished_at: run.finished_at.map(format_time),
+        cursor_before: run.cursor_before,
+        cursor_after: run.cursor_after,
+        rows_captured: run.rows_captured,
+        bytes_written: run.bytes_written,
+        retries: run.retries,
+        error: run.error,
+    }
+}
+
+pub fn segment_view(segment: Segment) -> SegmentView {
+    SegmentView {
+        id: segment.id,
+        run_id: segment.run_id,
+        path: segment.path,
+        cursor_start: segment.cursor_start,
+        cursor_end: segment.cursor_end,
+        row_count: segment.row_count,
+        size_bytes: segment.size_bytes,
+        status: segment.status.as_str().to_string(),
+        created_at: format_time(segment.created_at),
+    }
+}
+
+pub fn freshness_view(freshness: TargetFreshness) -> FreshnessView {
+    FreshnessView {
+        database: freshness.database_name,
+        target: freshness.target_name,
+        last_success: freshness.last_success.map(format_time),
+        age: freshness.age.map(|age| age.as_secs()),
+        captured_cursor: freshness.captured_cursor,
+        source_cursor: freshness.source_cursor,
+        cursor_lag: freshness.cursor_lag,
+        wal_lag_bytes: freshness.wal_lag_bytes,
+        rpo: freshness.rpo.map(|rpo| rpo.as_secs()),
+        rpo_breached: freshness.rpo_breached,
+        source_error: freshness.source_error,
+    }
+}
+
+pub fn backup_result(stats: RunStats) -> BackupResult {
+    BackupResult {
+        cursor_after: stats.cursor_after,
+        rows_captured: stats.rows_captured,
+        bytes_written: stats.bytes_written,
+        retries: stats.retries,
+    }
+}
+
+pub fn worker_view(status: WorkerStatus) -> WorkerView {
+    WorkerView {
+        pid: status.pid,
+        started_at: status.started_at,
+        cycles: status.cycles,
+        targets: status.targets.into_iter().map(schedule_view).collect(),
+    }
+}
+
+pub fn schedule_view(target: ScheduledTarget) -> ScheduleView {
+    ScheduleView {
+        database: target.database,
+        target: target.target,
+        schedule: target.schedule.as_str().to_string(),
+        next_hit: target.next_hit,
+    }
+}
+
+pub fn user_view(user: User) -> UserView {
+    UserView {
+        name: user.name,
+        role: user.role,
+        created_at: format_time(user.created_at),
+    }
+}
+
+pub fn token_view(token: ApiToken) -> TokenView {
+    TokenView {
+        id: token.id,
+        label: token.label,
+        created_at: format_time(token.created_at),
+        last_used: token.last_used.map(format_time),
+    }
+}
+
+pub fn audit_view(entry: AuditEntry) -> AuditView {
+    AuditView {
+        id: entry.id,
+        at: format_time(entry.at),
+        user: entry.user_name,
+        action: entry.action,
+        resource: entry.resource,
+        status: entry.status,
+    }
+}
+
+pub fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// `None` for the epoch, which the state store uses for "never"
+fn format_epoch(time: SystemTime) -> Option<String> {
+    (time > SystemTime::UNIX_EPOCH).then(|| format_time(time))
+}
@@ -0,0 +1,249 @@
+use axum::body::Body;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http_body_util::BodyExt;
+use pbus_db_manager::{AuthStore, Role, User};
+use pbus_webserver::auth::{hash_password, hash_token, new_token, Caller, TOKEN_PREFIX};
+use pbus_webserver::{router, AppState};
+use serde_json::{json, Value};
+use std::time::SystemTime;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const PASSWORD: &str = "correct horse";
+
+/// A data directory with one user per role, each holding a token
+struct Server {
+    _dir: TempDir,
+    base_mount_point: String,
+}
+
+impl Server {
+    fn new() -> Server {
+        let dir = TempDir::new().unwrap();
+        let base_mount_point = format!("{}/", dir.path().display());
+        let server = Server {
+            _dir: dir,
+            base_mount_point,
+        };
+        for role in [Role::Viewer, Role::Operator, Role::Admin] {
+            server.add_user(role.as_str(), role);
+        }
+        server
+    }
+
+    fn auth(&self) -> AuthStore {
+        AuthStore::open(&self.base_mount_point).unwrap()
+    }
+
+    fn add_user(&self, name: &str, role: Role) {
+        let auth = self.auth();
+        auth.add_user(&User {
+            name: name.to_string(),
+            password_hash: hash_password(PASSWORD).unwrap(),
+            role,
+            created_at: SystemTime::now(),
+        })
+        .unwrap();
+        auth.add_token(name, "tests", &hash_token(&token_of(name)))
+            .unwrap();
+    }
+
+    fn router(&self) -> Router {
+        router(AppState {
+            base_mount_point: self.base_mount_point.clone(),
+        })
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        uri: &str,
+        authorization: Option<String>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = self.router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+}
+
+/// Tokens are made up per user, so tests can present them
+fn token_of(name: &str) -> String {
+    format!("{}test-{}", TOKEN_PREFIX, name)
+}
+
+fn bearer(name: &str) -> Option<String> {
+    Some(format!("Bearer {}", token_of(name)))
+}
+
+fn basic(name: &str, password: &str) -> Option<String> {
+    Some(format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", name, password))
+    ))
+}
+
+#[test]
+fn roles_include_the_ones_below_them() {
+    let caller = |role| Caller {
+        name: "someone".to_string(),
+        role,
+    };
+
+    assert!(caller(Role::Viewer).require(Role::Viewer).is_ok());
+    assert!(caller(Role::Operator).require(Role::Viewer).is_ok());
+    assert!(caller(Role::Admin).require(Role::Operator).is_ok());
+
+    let refused = caller(Role::Operator).require(Role::Admin).unwrap_err();
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+    assert!(refused.message.contains("admin"));
+    assert_eq!(
+        caller(Role::Viewer)
+            .require(Role::Operator)
+            .unwrap_err()
+            .status,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[test]
+fn tokens_are_random_and_stored_hashed() {
+    let token = new_token();
+    assert!(token.starts_with(TOKEN_PREFIX));
+    assert_ne!(token, new_token());
+
+    let hash = hash_token(&token);
+    assert_eq!(hash, hash_token(&token));
+    assert_eq!(hash.len(), 64);
+    assert!(!hash.contains(&token[TOKEN_PREFIX.len()..]));
+    assert_ne!(hash, hash_token(&new_token()));
+}
+
+#[test]
+fn short_passwords_are_refused() {
+    assert!(hash_password("short").is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_need_valid_credentials() {
+    let server = Server::new();
+
+    let (status, _) = server.call("GET", "/api/session", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let wrong_token = Some(format!("Bearer {}nope", TOKEN_PREFIX));
+    let (status, _) = server.call("GET", "/api/session", wrong_token, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = server
+        .call(
+            "GET",
+            "/api/session",
+            basic("admin", "wrong password"),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, session) = server
+        .call("GET", "/api/session", basic("operator", PASSWORD), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session, json!({ "user": "operator", "role": "operator" }));
+
+    let (status, session) = server
+        .call("GET", "/api/session", bearer("viewer"), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["role"], "viewer");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn handlers_check_the_role() {
+    let server = Server::new();
+
+    for (name, expected) in [
+        ("viewer", StatusCode::FORBIDDEN),
+        ("operator", StatusCode::FORBIDDEN),
+        ("admin", StatusCode::OK),
+    ] {
+        let (status, _) = server.call("GET", "/api/users", bearer(name), None).await;
+        assert_eq!(status, expected, "{} listing users", name);
+    }
+
+    // Refused requests still end up in the audit log
+    let (status, _) = server
+        .call("DELETE", "/api/users/viewer", bearer("operator"), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let audit = server.auth().get_audit(10).unwrap();
+    assert_eq!(audit[0].user_name.as_deref(), Some("operator"));
+    assert_eq!(audit[0].status, 403);
+    assert!(server.auth().get_user("viewer").unwrap().is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_last_admin_is_kept() {
+    let server = Server::new();
+
+    let (status, _) = server
+        .call("DELETE", "/api/users/admin", bearer("admin"), None)
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = server
+        .call(
+            "PATCH",
+            "/api/users/admin",
+            bearer("admin"),
+            Some(json!({ "role": "operator" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        server.auth().get_user("admin").unwrap().unwrap().role,
+        Role::Admin
+    );
+
+    // The store refuses too, as the CLI goes to it directly
+    let mut auth = server.auth();
+    assert!(auth.is_last_admin("admin").unwrap());
+    assert!(!auth.is_last_admin("operator").unwrap());
+    assert!(auth.remove_user("admin").is_err());
+    let mut demoted = auth.get_user("admin").unwrap().unwrap();
+    demoted.role = Role::Operator;
+    assert!(auth.update_user(&demoted).is_err());
+    assert!(auth.remove_user("viewer").unwrap());
+    assert_eq!(auth.get_user("admin").unwrap().unwrap().role, Role::Admin);
+
+    // With a second admin either may go
+    server.add_user("second", Role::Admin);
+    let (status, _) = server
+        .call("DELETE", "/api/users/admin", bearer("second"), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(server.auth().get_user("admin").unwrap().is_none());
+    let (status, _) = server
+        .call("GET", "/api/session", bearer("admin"), None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
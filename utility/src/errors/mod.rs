@@ -0,0 +1,101 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors of the pbus crates, grouped by what the worker can do about them
+///
+/// See `is_retryable` for which ones are worth trying again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PbusError {
+    /// The config is missing, can't be parsed or is invalid
+    Config(String),
+    /// A source database can't be reached or refused the connection
+    Connection { message: String, retryable: bool },
+    /// A statement against a source database failed
+    Query { message: String, retryable: bool },
+    /// The catalog, the state store or the segment files failed
+    Storage(String),
+    /// A source table doesn't have the shape its target expects
+    Schema(String),
+    /// The work was stopped before it finished
+    Cancelled,
+}
+
+impl PbusError {
+    pub fn config(message: impl fmt::Display) -> PbusError {
+        PbusError::Config(message.to_string())
+    }
+
+    pub fn storage(message: impl fmt::Display) -> PbusError {
+        PbusError::Storage(message.to_string())
+    }
+
+    pub fn schema(message: impl fmt::Display) -> PbusError {
+        PbusError::Schema(message.to_string())
+    }
+
+    /// The same error with `context` in front of its message
+    pub fn context(self, context: impl fmt::Display) -> PbusError {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            PbusError::Config(message) => PbusError::Config(prefix(message)),
+            PbusError::Connection { message, retryable } => PbusError::Connection {
+                message: prefix(message),
+                retryable,
+            },
+            PbusError::Query { message, retryable } => PbusError::Query {
+                message: prefix(message),
+                retryable,
+            },
+            PbusError::Storage(message) => PbusError::Storage(prefix(message)),
+            PbusError::Schema(message) => PbusError::Schema(prefix(message)),
+            PbusError::Cancelled => PbusError::Cancelled,
+        }
+    }
+
+    /// Whether the same operation may succeed if tried again later, such as after
+    /// a dropped connection or a deadlock
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            PbusError::Connection { retryable, .. } | PbusError::Query { retryable, .. } => {
+                *retryable
+            }
+            PbusError::Config(_)
+            | PbusError::Storage(_)
+            | PbusError::Schema(_)
+            | PbusError::Cancelled => false,
+        }
+    }
+
+    /// Short name of the error class, for logs and metrics
+    pub fn class(&self) -> &'static str {
+        match self {
+            PbusError::Config(_) => "config",
+            PbusError::Connection { .. } => "connection",
+            PbusError::Query { .. } => "query",
+            PbusError::Storage(_) => "storage",
+            PbusError::Schema(_) => "schema",
+            PbusError::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for PbusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PbusError::Config(message) => write!(f, "config error: {}", message),
+            PbusError::Connection { message, .. } => write!(f, "connection error: {}", message),
+            PbusError::Query { message, .. } => write!(f, "query error: {}", message),
+            PbusError::Storage(message) => write!(f, "storage error: {}", message),
+            PbusError::Schema(message) => write!(f, "schema error: {}", message),
+            PbusError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Error for PbusError {}
+
+impl From<std::io::Error> for PbusError {
+    fn from(e: std::io::Error) -> PbusError {
+        PbusError::storage(e)
+    }
+}
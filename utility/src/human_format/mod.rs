@@ -0,0 +1,100 @@
+//! Serde helpers that keep config values readable when edited by hand
+
+/// A number of seconds written as a duration such as `"15m"` or `"1h 30m"`
+///
+/// Plain numbers are still read as seconds.
+pub mod duration_secs {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Secs(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(secs: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = humantime::format_duration(Duration::from_secs(*secs)).to_string();
+        serializer.serialize_str(&text)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Secs(secs) => Ok(secs),
+            Repr::Text(text) => humantime::parse_duration(&text)
+                .map(|duration| duration.as_secs())
+                .map_err(|e| de::Error::custom(format!("invalid duration `{}`: {}", text, e))),
+        }
+    }
+}
+
+/// Like `duration_secs`, for a value that may be left out
+pub mod optional_duration_secs {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Secs(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(secs: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match secs {
+            Some(secs) => {
+                let text = humantime::format_duration(Duration::from_secs(*secs)).to_string();
+                serializer.serialize_some(&text)
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        match Option::<Repr>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(Repr::Secs(secs)) => Ok(Some(secs)),
+            Some(Repr::Text(text)) => humantime::parse_duration(&text)
+                .map(|duration| Some(duration.as_secs()))
+                .map_err(|e| de::Error::custom(format!("invalid duration `{}`: {}", text, e))),
+        }
+    }
+}
+
+/// A point in time written as an RFC 3339 timestamp such as `"2024-01-31T12:00:00Z"`
+///
+/// The `{secs_since_epoch, nanos_since_epoch}` form older versions wrote is still
+/// read.
+pub mod system_time {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        Epoch {
+            secs_since_epoch: u64,
+            nanos_since_epoch: u32,
+        },
+    }
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = humantime::format_rfc3339_seconds(*time).to_string();
+        serializer.serialize_str(&text)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Text(text) => humantime::parse_rfc3339_weak(&text)
+                .map_err(|e| de::Error::custom(format!("invalid timestamp `{}`: {}", text, e))),
+            Repr::Epoch {
+                secs_since_epoch,
+                nanos_since_epoch,
+            } => Ok(SystemTime::UNIX_EPOCH + Duration::new(secs_since_epoch, nanos_since_epoch)),
+        }
+    }
+}
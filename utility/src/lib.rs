@@ -1,5 +1,8 @@
+pub mod errors;
+pub mod human_format;
 pub mod targets;
 pub mod time_handler;
 
+pub use crate::errors::PbusError;
 pub use crate::targets::Target;
 pub use crate::time_handler::HitTargets;
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, time::SystemTime};
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
@@ -6,15 +6,19 @@ pub struct TableField {
     pub name: String,
     pub data_type: String,
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Target {
     name: String,
     // create a hashmap of fields that maps string to type T
     fields: HashMap<String, String>,
-    last_id: i32,
-    last_updated: SystemTime,
-    last_checked: SystemTime,
     enabled: bool,
+    /// Longest the backup may trail the source, in seconds, overriding the database's
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::human_format::optional_duration_secs"
+    )]
+    rpo: Option<u64>,
 }
 
 impl Target {
@@ -22,39 +26,17 @@ impl Target {
         Target {
             name,
             fields: HashMap::new(),
-            last_id: 0,
-            last_updated: SystemTime::now(),
-            last_checked: SystemTime::now(),
             enabled: true,
+            rpo: None,
         }
     }
 
-    pub fn construct(
-        name: String,
-        fields: HashMap<String, String>,
-        last_id: i32,
-        last_updated: String,
-        last_checked: String,
-        enabled: bool,
-    ) -> Target {
-        let last_updated = SystemTime::UNIX_EPOCH
-            .checked_add(std::time::Duration::from_secs(
-                last_updated.parse().unwrap(),
-            ))
-            .unwrap();
-        let last_checked = SystemTime::UNIX_EPOCH
-            .checked_add(std::time::Duration::from_secs(
-                last_checked.parse().unwrap(),
-            ))
-            .unwrap();
-
+    pub fn construct(name: String, fields: HashMap<String, String>, enabled: bool) -> Target {
         Target {
             name,
             fields,
-            last_id: last_id,
-            last_updated: last_updated,
-            last_checked: last_checked,
             enabled,
+            rpo: None,
         }
     }
 
@@ -80,30 +62,6 @@ impl Target {
         &self.name
     }
 
-    pub fn get_last_id(&self) -> i32 {
-        self.last_id
-    }
-
-    pub fn set_last_id(&mut self, last_id: i32) {
-        self.last_id = last_id;
-    }
-
-    pub fn get_last_updated(&self) -> &SystemTime {
-        &self.last_updated
-    }
-
-    pub fn set_last_updated(&mut self, last_updated: SystemTime) {
-        self.last_updated = last_updated;
-    }
-
-    pub fn get_last_checked(&self) -> &SystemTime {
-        &self.last_checked
-    }
-
-    pub fn set_last_checked(&mut self, last_checked: SystemTime) {
-        self.last_checked = last_checked;
-    }
-
     pub fn get_enabled(&self) -> bool {
         self.enabled
     }
@@ -111,4 +69,12 @@ impl Target {
     pub fn set_enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
     }
+
+    pub fn get_rpo(&self) -> Option<u64> {
+        self.rpo
+    }
+
+    pub fn set_rpo(&mut self, rpo: Option<u64>) {
+        self.rpo = rpo;
+    }
 }
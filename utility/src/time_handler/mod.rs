@@ -46,6 +46,12 @@ impl HitTargets {
         self.next_hit = next_hit;
     }
 
+    /// Changes the interval, rescheduling the next hit relative to the last one
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.next_hit = self.last_hit + interval;
+    }
+
     pub fn copy(&self) -> HitTargets {
         HitTargets {
             name: self.name.clone(),